@@ -5,16 +5,54 @@ use tower_lsp::lsp_types::{
     MarkupKind, Position, Url,
 };
 
-pub fn resolve_relative_path(root_paths: &[Url], path: &str) -> Option<Url> {
-    root_paths.iter().find_map(|baseuri| {
-        let mut uri = baseuri.clone();
-        uri.path_segments_mut().unwrap().extend(path.split('/'));
-        if uri.to_file_path().ok()?.exists() {
-            Some(uri)
-        } else {
-            None
+use crate::{
+    hir_parser::Statement,
+    hir_tokenizer::{Span, Token},
+    path_resolver::PathResolver,
+};
+
+/// Parses a `file:linenr` location
+pub fn parse_file_location(s: &str) -> Option<(String, u32)> {
+    if let [filepath, linestr] = s.split(':').collect::<Vec<_>>()[..] {
+        if let Ok(line) = linestr.parse::<u32>() {
+            return Some((filepath.to_string(), line));
         }
-    })
+    }
+    None
+}
+
+/// A `file:linenr` location mentioned in a debug annotation, either directly
+/// or as part of a backtrace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceReference {
+    /// The span of the location, without the surrounding quotes
+    pub span: Span,
+    pub filepath: String,
+    pub line: u32,
+}
+
+/// Finds all source locations inside the debug annotations. They are resolved against the
+/// workspace only when needed, since generated files contain thousands of them.
+pub fn find_source_references(stmts: &[Statement]) -> Vec<SourceReference> {
+    stmts
+        .iter()
+        .filter_map(|s| match s {
+            Statement::DbgAnnotation { def, .. } => Some(def),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|(token, span)| {
+            let Token::Str(s) = token else {
+                return None;
+            };
+            let (filepath, line) = parse_file_location(s)?;
+            Some(SourceReference {
+                span: span.start + 1..span.end - 1,
+                filepath,
+                line,
+            })
+        })
+        .collect()
 }
 
 #[derive(Debug, Clone)]
@@ -31,7 +69,7 @@ fn shorten_filepath(path: &str) -> &str {
 }
 
 pub fn parse_backtrace_from_json(
-    root_paths: &[Url],
+    resolver: &PathResolver,
     json: serde_json::Value,
 ) -> Option<Vec<Frame>> {
     let backtrace = json.get("backtrace")?.as_array()?;
@@ -45,27 +83,20 @@ pub fn parse_backtrace_from_json(
 
             // If the name is empty, fallback to the file name or use `<invalid>`
             let mut name = "<invalid>";
-            if json_name.is_some() && !json_name.unwrap().is_empty() {
+            if let Some(json_name) = json_name.filter(|n| !n.is_empty()) {
                 // Use the name provided in the backtrace if present
-                name = json_name.unwrap();
+                name = json_name;
             } else if let Some(link) = json_link {
                 name = shorten_filepath(link);
             }
 
             // Parse `file:linenr`
-            let filepath_linenr = json_link.and_then(|s| -> Option<(String, u32)> {
-                if let [filepath, linestr] = s.split(':').collect::<Vec<_>>()[..] {
-                    if let Ok(line) = linestr.parse::<u32>() {
-                        return Some((filepath.to_string(), line));
-                    }
-                }
-                None
-            });
+            let filepath_linenr = json_link.and_then(parse_file_location);
 
             // Resolve the file path against the workspace roots
             let resolved_filepath = filepath_linenr
                 .as_ref()
-                .and_then(|f| resolve_relative_path(root_paths, &f.0));
+                .and_then(|f| resolver.resolve(&f.0));
 
             let line = filepath_linenr.as_ref().map(|f| f.1).unwrap_or(0);
             Frame {
@@ -107,7 +138,7 @@ pub fn identify_relevant_frames(frames: &[Frame]) -> Option<usize> {
 // Removes all potentially dangerous characters form a string which we are
 // about to embed into Markdown
 fn sanitize_markdown_code(txt: &str) -> String {
-    let mut sanitized = String::with_capacity(txt.bytes().len());
+    let mut sanitized = String::with_capacity(txt.len());
     for c in txt.chars() {
         if c.is_alphanumeric() || c.is_whitespace() {
             // We allow all alphanumeric chars.
@@ -225,10 +256,11 @@ pub fn inlay_hint_for_backtrace(pos: Position, frames: &[Frame]) -> InlayHint {
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_return)]
 fn parse_test_frame(txt: &str) -> Frame {
     let wrapped = r#"{"backtrace": ["#.to_string() + txt + r#"]}"#;
     let json_val = serde_json::from_str::<serde_json::Value>(&wrapped).unwrap();
-    let mut bt = parse_backtrace_from_json(&vec![], json_val).unwrap();
+    let mut bt = parse_backtrace_from_json(&PathResolver::default(), json_val).unwrap();
     assert_eq!(bt.len(), 1);
     return bt.remove(0);
 }
@@ -296,7 +328,7 @@ fn parse_multi_frame_backtrace() {
         r#"{"backtrace": [["myFunc", "./my/File.cpp:11"], ["yourFunc", "./your/File.cpp:12"]]}"#,
     )
     .unwrap();
-    let bt = parse_backtrace_from_json(&vec![], json_val).unwrap();
+    let bt = parse_backtrace_from_json(&PathResolver::default(), json_val).unwrap();
     // The first entry has a valid symbol name. Use it.
     // The file path can't be found in the root_paths, though.
     assert_eq!(bt[0].symbol, "myFunc");
@@ -308,3 +340,25 @@ fn parse_multi_frame_backtrace() {
     assert_eq!(bt[1].filepath, Some("./your/File.cpp".to_string()));
     assert_eq!(bt[1].line, 12);
 }
+
+#[test]
+fn test_find_source_references() {
+    let src = r#"!f1 = "./hyper/infra/util/RuntimeException.cpp:476"
+!2 = {"backtrace": [["myFunc", "./my/File.cpp:11"], ["", "no location"]]}
+"#;
+    let res = crate::hir_parser::parse_from_str(src);
+    let refs = find_source_references(&res.stmts);
+    assert_eq!(
+        refs.iter()
+            .map(|r| (&src[r.span.clone()], r.filepath.as_str(), r.line))
+            .collect::<Vec<_>>(),
+        [
+            (
+                "./hyper/infra/util/RuntimeException.cpp:476",
+                "./hyper/infra/util/RuntimeException.cpp",
+                476
+            ),
+            ("./my/File.cpp:11", "./my/File.cpp", 11),
+        ]
+    );
+}
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tower_lsp::lsp_types::Diagnostic;
+
+use crate::{
+    diagnostics::DiagnosticCode, member_access::StructNames, path_resolver::PathSubstitution,
+};
+
+/// The name of our configuration section, e.g. `hyperIr.pathSubstitutions`
+pub const CONFIG_SECTION: &str = "hyperIr";
+
+/// User configuration, received as `initializationOptions` or through `workspace/didChangeConfiguration`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Config {
+    /// Rewrites the source paths found in the debug annotations
+    pub path_substitutions: Vec<PathSubstitution>,
+    /// Diagnostics which should not be reported, either by code (`HIR012`) or by name
+    /// (`unresolved-source-path`)
+    pub suppressed_diagnostics: Vec<String>,
+    pub inlay_hints: InlayHintsConfig,
+    /// Names for struct layouts and their fields, keyed by the layout as printed by Hyper
+    pub struct_names: BTreeMap<String, StructNames>,
+}
+
+/// Inlay hints which can be turned off individually
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct InlayHintsConfig {
+    /// Parameter names in front of call arguments
+    pub parameter_names: bool,
+    /// The types of SSA values after their uses, and the member accessed by `gep` results
+    pub value_types: bool,
+    /// The struct field, type and byte offset accessed by `gep`, `load` and `store`
+    pub member_accesses: bool,
+    /// The maximum number of simultaneously live values per basic block
+    pub register_pressure: bool,
+    /// The values of instructions which always produce the same constant
+    pub constant_values: bool,
+}
+
+impl Default for InlayHintsConfig {
+    fn default() -> Self {
+        InlayHintsConfig {
+            parameter_names: true,
+            value_types: false,
+            member_accesses: true,
+            register_pressure: true,
+            constant_values: true,
+        }
+    }
+}
+
+impl Config {
+    /// Parses the configuration. Clients send either only our section or all settings.
+    pub fn from_settings(settings: &Value) -> Config {
+        let section = settings.get(CONFIG_SECTION).unwrap_or(settings);
+        serde_json::from_value(section.clone()).unwrap_or_default()
+    }
+
+    pub fn is_suppressed(&self, diagnostic: &Diagnostic) -> bool {
+        DiagnosticCode::from_diagnostic(diagnostic)
+            .is_some_and(|code| self.suppressed_diagnostics.iter().any(|s| code.matches(s)))
+    }
+}
+
+#[test]
+fn test_config_from_settings() {
+    let expected = Config {
+        path_substitutions: vec![PathSubstitution {
+            from: "/sandbox".to_string(),
+            to: "/home/me/hyper".to_string(),
+        }],
+        suppressed_diagnostics: vec!["HIR012".to_string()],
+        inlay_hints: InlayHintsConfig {
+            parameter_names: false,
+            value_types: true,
+            member_accesses: true,
+            register_pressure: true,
+            constant_values: true,
+        },
+        struct_names: BTreeMap::from([(
+            "{ptr, int64}".to_string(),
+            StructNames {
+                name: "QueryState".to_string(),
+                fields: vec!["queryResources".to_string(), "resultTupleCount".to_string()],
+            },
+        )]),
+    };
+    let section = serde_json::json!({
+        "pathSubstitutions": [{"from": "/sandbox", "to": "/home/me/hyper"}],
+        "suppressedDiagnostics": ["HIR012"],
+        "inlayHints": {"parameterNames": false, "valueTypes": true},
+        "structNames": {
+            "{ptr, int64}": {"name": "QueryState", "fields": ["queryResources", "resultTupleCount"]}
+        }
+    });
+    assert_eq!(Config::from_settings(&section), expected);
+    let settings = serde_json::json!({ "hyperIr": section });
+    assert_eq!(Config::from_settings(&settings), expected);
+    assert_eq!(Config::from_settings(&Value::Null), Config::default());
+}
+
+#[test]
+fn test_suppressed_diagnostics() {
+    use crate::diagnostics::create_diagnostic;
+    let config = Config {
+        suppressed_diagnostics: vec!["HIR012".to_string(), "duplicate-definition".to_string()],
+        ..Default::default()
+    };
+    let diagnostic = |code| create_diagnostic(code, Default::default(), String::new());
+    assert!(config.is_suppressed(&diagnostic(DiagnosticCode::UnresolvedSourcePath)));
+    assert!(config.is_suppressed(&diagnostic(DiagnosticCode::DuplicateDefinition)));
+    assert!(!config.is_suppressed(&diagnostic(DiagnosticCode::UndefinedSymbol)));
+}
@@ -0,0 +1,517 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    control_flow_graph::ControlFlowGraph,
+    hir_opcodes::OperandShape,
+    hir_operands::{parse_key_value, parse_typed_value, split_operands, Value},
+    hir_parser::{BasicBlock, Instruction},
+    hir_tokenizer::{Span, Spanned, Token},
+    hir_types::IrType,
+    interpreter::{binary_operation, bits_of, checked_arithmetic, mask, sign_extend, Val},
+};
+
+/// What is known about an SSA value
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Lattice {
+    /// Not computed yet, e.g. because its definition was not reached so far
+    Unknown,
+    Constant(Val),
+    /// Not a constant, e.g. a function argument or a loaded value
+    Overdefined,
+}
+
+impl Lattice {
+    fn meet(self, other: Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Unknown, x) | (x, Lattice::Unknown) => x,
+            (Lattice::Constant(a), Lattice::Constant(b)) if a == b => self,
+            _ => Lattice::Overdefined,
+        }
+    }
+}
+
+/// The successors of a branch which can be taken
+enum Targets<'a> {
+    All,
+    Labels(Vec<&'a str>),
+}
+
+/// The result of an instruction which is the same on every execution
+#[derive(Clone, Debug, PartialEq)]
+pub struct FoldedValue {
+    pub name: Spanned<String>,
+    pub type_: IrType,
+    pub value: Val,
+}
+
+impl FoldedValue {
+    /// Renders the value like a literal of its type, e.g. `true`, `-1` or `null`
+    pub fn describe(&self) -> String {
+        match (&self.type_, self.value) {
+            (IrType::Int(1), Val::Int(v)) => (v != 0).to_string(),
+            (IrType::Ptr, Val::Int(0)) => "null".to_string(),
+            (IrType::Ptr | IrType::Data128, Val::Int(v)) => format!("0x{:x}", v),
+            (type_, Val::Int(v)) => sign_extend(v, bits_of(type_)).to_string(),
+            (_, value) => value.to_string(),
+        }
+    }
+}
+
+/// A conditional branch which always goes to the same basic block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KnownBranch {
+    pub span: Span,
+    pub target: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConstantPropagation {
+    pub values: Vec<FoldedValue>,
+    pub known_branches: Vec<KnownBranch>,
+    /// The indices of the basic blocks which are never executed
+    pub dead_blocks: Vec<usize>,
+}
+
+struct Propagator<'a> {
+    /// The values defined within the function. Everything else, such as arguments, is overdefined.
+    values: HashMap<&'a str, Lattice>,
+}
+
+impl<'a> Propagator<'a> {
+    fn operand(&self, operand: &[Spanned<Token>]) -> Lattice {
+        let Some((value, [])) = parse_typed_value(operand) else {
+            return Lattice::Overdefined;
+        };
+        let bits = bits_of(&value.type_);
+        let val = match (&value.value.0, &value.type_) {
+            (Value::Local(name), _) => {
+                return self
+                    .values
+                    .get(name.as_str())
+                    .copied()
+                    .unwrap_or(Lattice::Overdefined)
+            }
+            (Value::Global(_), _) => return Lattice::Overdefined,
+            (Value::Int(v), IrType::Float(_)) => Val::Float(*v as f64),
+            (Value::Int(v), _) => Val::Int(mask(*v, bits)),
+            (Value::Float(v), _) => match v.parse() {
+                Ok(v) => Val::Float(v),
+                Err(_) => return Lattice::Overdefined,
+            },
+            (Value::Bool(b), _) => Val::Int(*b as u128),
+            (Value::Null, _) => Val::Int(0),
+        };
+        Lattice::Constant(val)
+    }
+
+    /// Evaluates the operands; returns early unless all of them are constant
+    fn constants<const N: usize>(
+        &self,
+        operands: [&[Spanned<Token>]; N],
+    ) -> Result<[(IrType, Val); N], Lattice> {
+        let mut result = [(); N].map(|_| (IrType::Void, Val::Void));
+        let mut unknown = false;
+        for (operand, slot) in operands.iter().zip(result.iter_mut()) {
+            match self.operand(operand) {
+                Lattice::Constant(v) => {
+                    let type_ = parse_typed_value(operand).map_or(IrType::Void, |t| t.0.type_);
+                    *slot = (type_, v);
+                }
+                Lattice::Unknown => unknown = true,
+                Lattice::Overdefined => return Err(Lattice::Overdefined),
+            }
+        }
+        if unknown {
+            Err(Lattice::Unknown)
+        } else {
+            Ok(result)
+        }
+    }
+
+    /// Evaluates a non-`phi` instruction, given the current knowledge about its operands
+    fn evaluate(&self, instr: &'a Instruction) -> (Lattice, Targets<'a>) {
+        let ops = split_operands(&instr.operands);
+        let refs = instr
+            .basic_block_refs
+            .iter()
+            .map(|r| r.0.as_str())
+            .collect::<Vec<_>>();
+        let opcode = instr.instruction.0.as_str();
+        let shape = instr.opcode().map(|spec| spec.shape);
+        macro_rules! constants {
+            ($($op:expr),*) => {
+                match self.constants([$($op),*]) {
+                    Ok(values) => values,
+                    Err(Lattice::Unknown) => return (Lattice::Unknown, Targets::Labels(vec![])),
+                    Err(_) => return (Lattice::Overdefined, Targets::All),
+                }
+            };
+        }
+        let value = match (opcode, ops.as_slice(), refs.as_slice()) {
+            ("br", [cond, _, _], [then, else_]) => {
+                let [(_, cond)] = constants!(cond);
+                let target = if cond == Val::Int(0) { else_ } else { then };
+                return (Lattice::Overdefined, Targets::Labels(vec![target]));
+            }
+            ("switch", [cond, cases @ ..], [default, labels @ ..]) => {
+                let [(type_, cond)] = constants!(cond);
+                let mut cases = cases
+                    .iter()
+                    .filter(|case| parse_key_value(case, "default").is_none());
+                for label in labels {
+                    let Some(case) = cases.next() else {
+                        return (Lattice::Overdefined, Targets::All);
+                    };
+                    match parse_typed_value(case).and_then(|(v, _)| match v.value.0 {
+                        Value::Int(v) => Some(v),
+                        _ => None,
+                    }) {
+                        Some(v) if Val::Int(mask(v, bits_of(&type_))) == cond => {
+                            return (Lattice::Overdefined, Targets::Labels(vec![label]));
+                        }
+                        Some(_) => {}
+                        None => return (Lattice::Overdefined, Targets::All),
+                    }
+                }
+                return (Lattice::Overdefined, Targets::Labels(vec![default]));
+            }
+            (_, [a, b, _, _], [cont, overflow])
+                if shape == Some(OperandShape::OverflowBranch(2)) =>
+            {
+                let [(type_, a), (_, b)] = constants!(a, b);
+                return match checked_arithmetic(&opcode[..opcode.len() - 2], &type_, a, b) {
+                    Some((_, true)) => (Lattice::Unknown, Targets::Labels(vec![overflow])),
+                    Some((value, false)) => (Lattice::Constant(value), Targets::Labels(vec![cont])),
+                    None => (Lattice::Overdefined, Targets::All),
+                };
+            }
+            _ if instr.is_branching() => return (Lattice::Overdefined, Targets::All),
+            ("select", [cond, a, b], _) => match self.operand(cond) {
+                Lattice::Constant(Val::Int(0)) => self.operand(b),
+                Lattice::Constant(_) => self.operand(a),
+                Lattice::Unknown => Lattice::Unknown,
+                // Both alternatives might still be the same constant
+                Lattice::Overdefined => match (self.operand(a), self.operand(b)) {
+                    (Lattice::Constant(a), Lattice::Constant(b)) if a == b => Lattice::Constant(a),
+                    (Lattice::Unknown, _) | (_, Lattice::Unknown) => Lattice::Unknown,
+                    _ => Lattice::Overdefined,
+                },
+            },
+            ("iszero" | "isnotzero" | "isnull" | "isnotnull", [value], _) => {
+                let [(_, value)] = constants!(value);
+                let is_zero = match value {
+                    Val::Float(v) => v == 0.0,
+                    v => v == Val::Int(0),
+                };
+                Lattice::Constant(Val::Int((is_zero != opcode.contains("not")) as u128))
+            }
+            ("zext" | "sext" | "trunc", [value], _) => {
+                let [(type_, value)] = constants!(value);
+                let (Some(target), Val::Int(value)) = (&instr.assignment_type, value) else {
+                    return (Lattice::Overdefined, Targets::All);
+                };
+                let value = match opcode {
+                    "sext" => sign_extend(value, bits_of(&type_)) as u128,
+                    _ => value,
+                };
+                Lattice::Constant(Val::Int(mask(
+                    value,
+                    bits_of(&IrType::from_name(&target.0)),
+                )))
+            }
+            (_, [a, b], _) if shape == Some(OperandShape::Values(2)) => {
+                let [(type_, a), (_, b)] = constants!(a, b);
+                binary_operation(opcode, &type_, a, b)
+                    .map_or(Lattice::Overdefined, Lattice::Constant)
+            }
+            _ => Lattice::Overdefined,
+        };
+        (value, Targets::All)
+    }
+
+    /// Combines the incoming values of a `phi` along the executable edges
+    fn evaluate_phi(&self, instr: &Instruction, executable_preds: &HashSet<&str>) -> Lattice {
+        instr
+            .basic_block_refs
+            .iter()
+            .zip(split_operands(&instr.operands))
+            .filter(|(label, _)| executable_preds.contains(label.0.as_str()))
+            .fold(Lattice::Unknown, |acc, (_, incoming)| match incoming {
+                [(Token::Punctuation('['), _), _, (Token::Punctuation(','), _), value @ .., (Token::Punctuation(']'), _)] => {
+                    acc.meet(self.operand(value))
+                }
+                _ => Lattice::Overdefined,
+            })
+    }
+}
+
+/// Sparse conditional constant propagation: finds the values which are constant and the
+/// branches whose direction is known, assuming that blocks are unreachable until proven
+/// otherwise. Memory, calls and function arguments are never constant.
+pub fn propagate_constants(basic_blocks: &[BasicBlock]) -> ConstantPropagation {
+    let cfg = ControlFlowGraph::new(basic_blocks);
+    let labels = basic_blocks
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, bb)| Some((bb.label.as_ref()?.0.as_str(), idx)))
+        .collect::<HashMap<_, _>>();
+    let mut propagator = Propagator {
+        values: basic_blocks
+            .iter()
+            .flat_map(|bb| &bb.instructions)
+            .filter_map(|i| Some((i.assignment_target.as_ref()?.0.as_str(), Lattice::Unknown)))
+            .collect(),
+    };
+    let mut executable = vec![false; basic_blocks.len()];
+    if let Some(entry) = executable.first_mut() {
+        *entry = true;
+    }
+    // The labels of the predecessors from which each block can be entered
+    let mut executable_preds = vec![HashSet::<&str>::new(); basic_blocks.len()];
+    // The feasible targets of each conditional branch
+    let mut branch_targets = HashMap::<&Span, Vec<&str>>::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (b, bb) in basic_blocks.iter().enumerate() {
+            if !executable[b] {
+                continue;
+            }
+            let mut successors = None;
+            for instr in &bb.instructions {
+                let (value, targets) = if instr.instruction.0 == "phi" {
+                    (
+                        propagator.evaluate_phi(instr, &executable_preds[b]),
+                        Targets::All,
+                    )
+                } else {
+                    propagator.evaluate(instr)
+                };
+                if let Some(target) = &instr.assignment_target {
+                    let old = propagator.values[target.0.as_str()];
+                    // Values only ever move down the lattice, which guarantees termination
+                    let new = match (old, value) {
+                        (Lattice::Unknown, new) => new,
+                        (old, Lattice::Unknown) => old,
+                        (old, new) => old.meet(new),
+                    };
+                    if new != old {
+                        propagator.values.insert(target.0.as_str(), new);
+                        changed = true;
+                    }
+                }
+                if instr.is_branching() {
+                    successors = Some(match targets {
+                        Targets::All => {
+                            // The condition might have been constant in an earlier pass
+                            branch_targets.remove(&instr.span);
+                            cfg.successors[b].clone()
+                        }
+                        Targets::Labels(targets) => {
+                            branch_targets.insert(&instr.span, targets.clone());
+                            targets
+                                .iter()
+                                .filter_map(|t| labels.get(t).copied())
+                                .collect()
+                        }
+                    });
+                }
+            }
+            // Blocks without a terminator fall through into the next block
+            for s in successors.unwrap_or_else(|| cfg.successors[b].clone()) {
+                let pred = bb.label.as_ref().map_or("", |l| l.0.as_str());
+                changed |= executable_preds[s].insert(pred);
+                if !executable[s] {
+                    executable[s] = true;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    let mut result = ConstantPropagation::default();
+    for (b, bb) in basic_blocks.iter().enumerate() {
+        if !executable[b] {
+            result.dead_blocks.push(b);
+            continue;
+        }
+        for instr in &bb.instructions {
+            if let (Some(name), Some(type_)) = (&instr.assignment_target, &instr.assignment_type) {
+                if let Lattice::Constant(value) = propagator.values[name.0.as_str()] {
+                    result.values.push(FoldedValue {
+                        name: name.clone(),
+                        type_: IrType::from_name(&type_.0),
+                        value,
+                    });
+                }
+            }
+            let distinct_refs = instr
+                .basic_block_refs
+                .iter()
+                .map(|r| r.0.as_str())
+                .collect::<HashSet<_>>();
+            if let Some([target]) = branch_targets.get(&instr.span).map(|t| &t[..]) {
+                if instr.is_branching() && distinct_refs.len() > 1 {
+                    result.known_branches.push(KnownBranch {
+                        span: instr.span.clone(),
+                        target: target.to_string(),
+                    });
+                }
+            }
+        }
+    }
+    result
+}
+
+#[test]
+fn test_propagate_constants() {
+    let res = crate::hir_parser::parse_from_str(
+        "
+define int64 @f(int64 %n) {
+body_0:
+  int64 %a = add int64 40, int64 2
+  int1 %c = ult int64 %a, int64 10
+  int64 %unknown = add int64 %n, int64 %a
+  br int1 %c, then_1, join_2
+then_1:
+  int64 %d = add int64 %unknown, int64 1
+  br join_2
+join_2:
+  int64 %e = phi [body_0, int64 %a], [then_1, int64 %d]
+  int64 %neg = sub int64 %e, int64 43
+  int64 %sum = saddbr int64 %e, int64 1, cont=loop_3, overflow=overflow_5
+loop_3:
+  int64 %i = phi [join_2, int64 0], [loop_3, int64 %i]
+  int1 %done = eq int64 %i, int64 0
+  br int1 %done, exit_4, loop_3
+exit_4:
+  ret int64 %sum
+overflow_5:
+  unreachable
+}",
+    );
+    let (_, body) = crate::hir_parser::first_function(&res);
+    let result = propagate_constants(&body.basic_blocks);
+    let values = result
+        .values
+        .iter()
+        .map(|v| (v.name.0.as_str(), v.describe()))
+        .collect::<Vec<_>>();
+    // `%d` is only computed in a dead block, so the `phi` only sees `%a`.
+    // `%i` is constant as well, since it only depends on itself around the loop.
+    assert_eq!(
+        values,
+        [
+            ("%a", "42".to_string()),
+            ("%c", "false".to_string()),
+            ("%e", "42".to_string()),
+            ("%neg", "-1".to_string()),
+            ("%sum", "43".to_string()),
+            ("%i", "0".to_string()),
+            ("%done", "true".to_string()),
+        ]
+    );
+    let branches = result
+        .known_branches
+        .iter()
+        .map(|b| b.target.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(branches, ["join_2", "loop_3", "exit_4"]);
+    // `then_1` and `overflow_5`
+    assert_eq!(result.dead_blocks, [1, 5]);
+}
+
+#[cfg(test)]
+fn folded_values(result: &ConstantPropagation) -> Vec<(&str, String)> {
+    result
+        .values
+        .iter()
+        .map(|v| (v.name.0.as_str(), v.describe()))
+        .collect()
+}
+
+#[test]
+fn test_propagate_constants_through_loops_and_phis() {
+    let res = crate::hir_parser::parse_from_str(
+        "
+define int64 @f(int1 %flag) {
+body_0:
+  br int1 %flag, left_1, right_2
+left_1:
+  br join_3
+right_2:
+  br join_3
+join_3:
+  int64 %same = phi [left_1, int64 7], [right_2, int64 7]
+  int64 %differs = phi [left_1, int64 1], [right_2, int64 2]
+  br loop_4
+loop_4:
+  int64 %i = phi [join_3, int64 0], [loop_4, int64 %next]
+  int64 %next = add int64 %i, int64 1
+  int64 %twice = mul int64 %same, int64 2
+  int1 %done = eq int64 %next, int64 10
+  br int1 %done, exit_5, loop_4
+exit_5:
+  ret int64 %twice
+}",
+    );
+    let (_, body) = crate::hir_parser::first_function(&res);
+    let result = propagate_constants(&body.basic_blocks);
+    // The induction variable `%i` merges `0` with `%next` along the back edge
+    assert_eq!(
+        folded_values(&result),
+        [("%same", "7".to_string()), ("%twice", "14".to_string())]
+    );
+    assert_eq!(result.known_branches, []);
+    assert_eq!(result.dead_blocks, Vec::<usize>::new());
+}
+
+#[test]
+fn test_propagate_constants_folds_branches() {
+    let src = "
+define int32 @g() {
+body_0:
+  int32 %v = add int32 1, int32 1
+  switch int32 %v, default=default_1, int32 0 label=zero_2, int32 2 label=two_3
+default_1:
+  ret int32 0
+zero_2:
+  ret int32 1
+two_3:
+  int1 %t = isnotzero int32 %v
+  br int1 %t, done_5, skipped_4
+skipped_4:
+  int32 %x = add int32 3, int32 4
+  br done_5
+done_5:
+  int32 %r = phi [two_3, int32 %v], [skipped_4, int32 %x]
+  ret int32 %r
+unreferenced_6:
+  int32 %never = add int32 5, int32 6
+  ret int32 %never
+}";
+    let res = crate::hir_parser::parse_from_str(src);
+    let (_, body) = crate::hir_parser::first_function(&res);
+    let result = propagate_constants(&body.basic_blocks);
+    // Values in dead blocks are not reported, even if they are constant
+    assert_eq!(
+        folded_values(&result),
+        [
+            ("%v", "2".to_string()),
+            ("%t", "true".to_string()),
+            ("%r", "2".to_string()),
+        ]
+    );
+    let branches = result
+        .known_branches
+        .iter()
+        .map(|b| {
+            (
+                src[b.span.clone()].split_whitespace().next().unwrap(),
+                b.target.as_str(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(branches, [("switch", "two_3"), ("br", "done_5")]);
+    assert_eq!(result.dead_blocks, [1, 2, 4, 6]);
+}
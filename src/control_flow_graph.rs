@@ -1,20 +1,248 @@
-use crate::hir_index::FunctionBody;
+use std::collections::{HashMap, HashSet};
+
+use crate::{hir_index::FunctionBody, hir_parser::BasicBlock, liveness::BlockLiveness};
+
+/// The control flow graph of a function. Basic blocks are identified by their position.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    pub successors: Vec<Vec<usize>>,
+    pub predecessors: Vec<Vec<usize>>,
+}
+
+impl ControlFlowGraph {
+    pub fn new(basic_blocks: &[BasicBlock]) -> ControlFlowGraph {
+        let labels = basic_blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, bb)| Some((bb.label.as_ref()?.0.as_str(), idx)))
+            .collect::<HashMap<_, _>>();
+        let mut successors = vec![vec![]; basic_blocks.len()];
+        for (idx, bb) in basic_blocks.iter().enumerate() {
+            let terminated = bb
+                .instructions
+                .iter()
+                .any(|i| i.opcode().is_some_and(|spec| spec.terminator));
+            if !terminated {
+                // E.g., the unlabeled block with the `alloca`s falls through into the next block
+                successors[idx].extend((idx + 1 < basic_blocks.len()).then_some(idx + 1));
+                continue;
+            }
+            for target in bb
+                .instructions
+                .iter()
+                .filter(|i| i.is_branching())
+                .flat_map(|i| &i.basic_block_refs)
+            {
+                if let Some(&target) = labels.get(target.0.as_str()) {
+                    if !successors[idx].contains(&target) {
+                        successors[idx].push(target);
+                    }
+                }
+            }
+        }
+        let mut predecessors = vec![vec![]; basic_blocks.len()];
+        for (idx, succs) in successors.iter().enumerate() {
+            for &succ in succs {
+                predecessors[succ].push(idx);
+            }
+        }
+        ControlFlowGraph {
+            successors,
+            predecessors,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.successors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.successors.is_empty()
+    }
+
+    /// The blocks reachable from the entry block, in reverse postorder
+    pub fn reverse_postorder(&self) -> Vec<usize> {
+        let mut order = vec![];
+        let mut visited = vec![false; self.len()];
+        // Iterative DFS, such that deeply nested functions don't overflow the stack
+        let mut stack = vec![(0, 0)];
+        if self.is_empty() {
+            return order;
+        }
+        visited[0] = true;
+        while let Some((block, next_succ)) = stack.pop() {
+            if let Some(&succ) = self.successors[block].get(next_succ) {
+                stack.push((block, next_succ + 1));
+                if !visited[succ] {
+                    visited[succ] = true;
+                    stack.push((succ, 0));
+                }
+            } else {
+                order.push(block);
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// For each block, the set of blocks dominating it (including itself).
+    /// Unreachable blocks are dominated by all blocks.
+    pub fn dominators(&self) -> Vec<HashSet<usize>> {
+        let all = (0..self.len()).collect::<HashSet<_>>();
+        let mut dom = (0..self.len())
+            .map(|b| {
+                if b == 0 {
+                    HashSet::from([0])
+                } else {
+                    all.clone()
+                }
+            })
+            .collect::<Vec<_>>();
+        let order = self.reverse_postorder();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in order.iter().skip(1) {
+                let mut preds = self.predecessors[b].iter();
+                let Some(first) = preds.next() else {
+                    continue;
+                };
+                let mut new = dom[*first].clone();
+                for p in preds {
+                    new.retain(|x| dom[*p].contains(x));
+                }
+                new.insert(b);
+                if new != dom[b] {
+                    dom[b] = new;
+                    changed = true;
+                }
+            }
+        }
+        dom
+    }
+
+    /// The natural loops, by their header and the blocks of their body (including the
+    /// header). Back edges to the same header are merged into one loop.
+    pub fn natural_loops(&self) -> Vec<(usize, HashSet<usize>)> {
+        let dom = self.dominators();
+        let reachable = self.reverse_postorder();
+        let mut loops = Vec::<(usize, HashSet<usize>)>::new();
+        for &b in &reachable {
+            for &header in &self.successors[b] {
+                if !dom[b].contains(&header) {
+                    continue;
+                }
+                // Collect the blocks which reach the back edge without passing the header
+                let mut body = HashSet::from([header, b]);
+                let mut stack = vec![b];
+                while let Some(n) = stack.pop() {
+                    if n == header {
+                        continue;
+                    }
+                    for &p in &self.predecessors[n] {
+                        if body.insert(p) {
+                            stack.push(p);
+                        }
+                    }
+                }
+                match loops.iter_mut().find(|l| l.0 == header) {
+                    Some(l) => l.1.extend(body),
+                    None => loops.push((header, body)),
+                }
+            }
+        }
+        loops
+    }
+
+    /// For each block, the set of blocks post-dominating it (including itself).
+    /// Blocks which cannot reach an exit are post-dominated by all blocks.
+    pub fn post_dominators(&self) -> Vec<HashSet<usize>> {
+        let all = (0..self.len()).collect::<HashSet<_>>();
+        let mut pdom = (0..self.len())
+            .map(|b| {
+                if self.successors[b].is_empty() {
+                    HashSet::from([b])
+                } else {
+                    all.clone()
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in (0..self.len()).rev() {
+                let Some((first, rest)) = self.successors[b].split_first() else {
+                    continue;
+                };
+                let mut new = pdom[*first].clone();
+                for s in rest {
+                    new.retain(|x| pdom[*s].contains(x));
+                }
+                new.insert(b);
+                if new != pdom[b] {
+                    pdom[b] = new;
+                    changed = true;
+                }
+            }
+        }
+        pdom
+    }
+
+    /// For each block, the blocks whose execution depends on the direction taken
+    /// by its terminator
+    pub fn control_dependents(&self) -> Vec<HashSet<usize>> {
+        let pdom = self.post_dominators();
+        (0..self.len())
+            .map(|b| {
+                let mut dependents = HashSet::new();
+                for s in &self.successors[b] {
+                    dependents.extend(pdom[*s].iter().filter(|x| **x == b || !pdom[b].contains(x)));
+                }
+                dependents
+            })
+            .collect()
+    }
+}
 
 fn escape_dot_label(str: &str) -> String {
     format!("\"{}\"", str.replace('\"', "\"\""))
 }
 
-pub fn create_cfg_dot_visualization(func_body: &FunctionBody) -> String {
+/// Renders the control flow graph. Given the liveness of the basic blocks, the nodes are
+/// labeled with the register pressure and colored from white (no live values) to red
+/// (the maximum pressure within the function).
+pub fn create_cfg_dot_visualization(
+    func_body: &FunctionBody,
+    liveness: Option<&[BlockLiveness]>,
+) -> String {
     let mut graph = "".to_string();
     graph.push_str(format!("// Control flow graph for {}\n", func_body.name.0).as_str());
     graph.push_str("// In VSCode, I recommend installing the `Graphviz Interactive Preview` extension to view this file.\n");
     graph.push_str("strict digraph D {\n");
     graph.push_str("  node [shape=box];\n");
+    if let Some(liveness) = liveness {
+        let max_pressure = liveness.iter().map(|l| l.max_pressure).max().unwrap_or(0);
+        for (bb, l) in func_body.basic_blocks.iter().zip(liveness) {
+            let Some(label) = &bb.label else {
+                continue;
+            };
+            let saturation = l.max_pressure as f64 / max_pressure.max(1) as f64;
+            graph.push_str(
+                format!(
+                    "  {} [label={}, style=filled, fillcolor=\"0.000 {:.3} 1.000\"]\n",
+                    escape_dot_label(&label.0),
+                    escape_dot_label(&format!("{}\\nlive: {}", label.0, l.max_pressure)),
+                    saturation
+                )
+                .as_str(),
+            );
+        }
+    }
     for bb in &func_body.basic_blocks {
         for i in &bb.instructions {
-            if i.is_branching() && bb.label.is_some() {
+            if let (true, Some(label)) = (i.is_branching(), &bb.label) {
                 for target in &i.basic_block_refs {
-                    let from_lbl = escape_dot_label(&bb.label.as_ref().unwrap().0);
+                    let from_lbl = escape_dot_label(&label.0);
                     let to_lbl = escape_dot_label(&target.0);
                     graph.push_str(format!("  {} -> {}\n", from_lbl, to_lbl).as_str());
                 }
@@ -24,3 +252,67 @@ pub fn create_cfg_dot_visualization(func_body: &FunctionBody) -> String {
     graph.push_str("}\n");
     graph.to_string()
 }
+
+#[cfg(test)]
+fn cfg_from_str(src: &str) -> ControlFlowGraph {
+    let res = crate::hir_parser::parse_from_str(src);
+    let (_, body) = crate::hir_parser::first_function(&res);
+    ControlFlowGraph::new(&body.basic_blocks)
+}
+
+#[test]
+fn test_pressure_dot() {
+    let src = "
+define void @f(int64 %n) {
+body_0:
+  int64 %a = add int64 %n, int64 1
+  br exit_1
+exit_1:
+  ret
+}";
+    let res = crate::hir_parser::parse_from_str(src);
+    let index = crate::hir_index::create_index(src, &res.tokens, &res.stmts);
+    let func_body = &index.function_bodies[0];
+    let liveness = crate::liveness::liveness(&func_body.basic_blocks);
+    let dot = create_cfg_dot_visualization(func_body, Some(&liveness));
+    assert!(dot.contains(
+        "  \"body_0\" [label=\"body_0\\nlive: 1\", style=filled, fillcolor=\"0.000 1.000 1.000\"]\n"
+    ));
+    assert!(dot.contains(
+        "  \"exit_1\" [label=\"exit_1\\nlive: 0\", style=filled, fillcolor=\"0.000 0.000 1.000\"]\n"
+    ));
+    assert!(dot.contains("  \"body_0\" -> \"exit_1\"\n"));
+    assert!(!create_cfg_dot_visualization(func_body, None).contains("live"));
+}
+
+#[test]
+fn test_control_flow_graph() {
+    let cfg = cfg_from_str(
+        "
+define void @f(int1 %c) {
+  ptr %a = alloca int32 align 4
+body_0:
+  br int1 %c, then_1, join_2
+then_1:
+  br join_2
+join_2:
+  br int1 %c, body_0, exit_3
+exit_3:
+  ret
+}",
+    );
+    assert_eq!(
+        cfg.successors,
+        [vec![1], vec![2, 3], vec![3], vec![1, 4], vec![]]
+    );
+    assert_eq!(cfg.predecessors[1], [0, 3]);
+    assert_eq!(cfg.reverse_postorder(), [0, 1, 2, 3, 4]);
+    let pdom = cfg.post_dominators();
+    assert_eq!(pdom[1], HashSet::from([1, 3, 4]));
+    assert_eq!(cfg.dominators()[4], HashSet::from([0, 1, 3, 4]));
+    assert_eq!(cfg.natural_loops(), [(1, HashSet::from([1, 2, 3]))]);
+    let cd = cfg.control_dependents();
+    // `then_1` depends on the branch in `body_0`, the loop body on the loop condition
+    assert_eq!(cd[1], HashSet::from([2]));
+    assert_eq!(cd[3], HashSet::from([1, 3]));
+}
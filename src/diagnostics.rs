@@ -1,13 +1,281 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
 use chumsky::prelude::Simple;
 use ropey::Rope;
-use tower_lsp::lsp_types::{Diagnostic, DiagnosticRelatedInformation, Location, Url};
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::{
+    CodeDescription, Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, DiagnosticTag,
+    Location, NumberOrString, Range, TextEdit, Url,
+};
 
 use crate::{
-    hir_index::HIRIndex,
-    hir_parser::{FuncBody, Statement},
+    backtrace::{find_source_references, SourceReference},
+    config::Config,
+    constant_propagation::propagate_constants,
+    hir_globals::{parse_initializer, InitializerError},
+    hir_index::{create_index, HIRIndex},
+    hir_opcodes::ResultType,
+    hir_parser::{parse_from_str, FuncBody, ParserResult, Statement},
+    hir_types::IrType,
     lsp_utils::range_to_lsp,
+    path_resolver::PathResolver,
+    validation::validate_statements,
 };
 
+/// The `source` of all our diagnostics
+pub const DIAGNOSTIC_SOURCE: &str = "hyper-ir";
+
+pub const DIAGNOSTICS_DOCUMENTATION_URL: &str =
+    "https://github.com/salesforce-misc/hyper-ir-lsp/blob/main/docs/diagnostics.md";
+
+/// The kinds of diagnostics we report. The codes are stable, such that users can look them up
+/// and suppress them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiagnosticCode {
+    UndefinedSymbol,
+    DuplicateDefinition,
+    SyntaxError,
+    UnknownBranchTargets,
+    UnexpectedOperands,
+    UnexpectedResult,
+    ResultTypeMismatch,
+    MissingTerminator,
+    EmptyBasicBlock,
+    MisplacedTerminator,
+    DuplicateTerminator,
+    UnresolvedSourcePath,
+    MalformedInitializer,
+    InitializerLengthMismatch,
+    InitializerTypeMismatch,
+    SignatureMismatch,
+    CallArgumentMismatch,
+    ConstantBranch,
+    DeadBlock,
+}
+
+pub const DIAGNOSTIC_CODES: &[DiagnosticCode] = &[
+    DiagnosticCode::UndefinedSymbol,
+    DiagnosticCode::DuplicateDefinition,
+    DiagnosticCode::SyntaxError,
+    DiagnosticCode::UnknownBranchTargets,
+    DiagnosticCode::UnexpectedOperands,
+    DiagnosticCode::UnexpectedResult,
+    DiagnosticCode::ResultTypeMismatch,
+    DiagnosticCode::MissingTerminator,
+    DiagnosticCode::EmptyBasicBlock,
+    DiagnosticCode::MisplacedTerminator,
+    DiagnosticCode::DuplicateTerminator,
+    DiagnosticCode::UnresolvedSourcePath,
+    DiagnosticCode::MalformedInitializer,
+    DiagnosticCode::InitializerLengthMismatch,
+    DiagnosticCode::InitializerTypeMismatch,
+    DiagnosticCode::SignatureMismatch,
+    DiagnosticCode::CallArgumentMismatch,
+    DiagnosticCode::ConstantBranch,
+    DiagnosticCode::DeadBlock,
+];
+
+impl DiagnosticCode {
+    pub fn code(self) -> &'static str {
+        match self {
+            DiagnosticCode::UndefinedSymbol => "HIR001",
+            DiagnosticCode::DuplicateDefinition => "HIR002",
+            DiagnosticCode::SyntaxError => "HIR003",
+            DiagnosticCode::UnknownBranchTargets => "HIR004",
+            DiagnosticCode::UnexpectedOperands => "HIR005",
+            DiagnosticCode::UnexpectedResult => "HIR006",
+            DiagnosticCode::ResultTypeMismatch => "HIR007",
+            DiagnosticCode::MissingTerminator => "HIR008",
+            DiagnosticCode::EmptyBasicBlock => "HIR009",
+            DiagnosticCode::MisplacedTerminator => "HIR010",
+            DiagnosticCode::DuplicateTerminator => "HIR011",
+            DiagnosticCode::UnresolvedSourcePath => "HIR012",
+            DiagnosticCode::MalformedInitializer => "HIR013",
+            DiagnosticCode::InitializerLengthMismatch => "HIR014",
+            DiagnosticCode::InitializerTypeMismatch => "HIR015",
+            DiagnosticCode::SignatureMismatch => "HIR016",
+            DiagnosticCode::CallArgumentMismatch => "HIR017",
+            DiagnosticCode::ConstantBranch => "HIR018",
+            DiagnosticCode::DeadBlock => "HIR019",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DiagnosticCode::UndefinedSymbol => "undefined-symbol",
+            DiagnosticCode::DuplicateDefinition => "duplicate-definition",
+            DiagnosticCode::SyntaxError => "syntax-error",
+            DiagnosticCode::UnknownBranchTargets => "unknown-branch-targets",
+            DiagnosticCode::UnexpectedOperands => "unexpected-operands",
+            DiagnosticCode::UnexpectedResult => "unexpected-result",
+            DiagnosticCode::ResultTypeMismatch => "result-type-mismatch",
+            DiagnosticCode::MissingTerminator => "missing-terminator",
+            DiagnosticCode::EmptyBasicBlock => "empty-basic-block",
+            DiagnosticCode::MisplacedTerminator => "misplaced-terminator",
+            DiagnosticCode::DuplicateTerminator => "duplicate-terminator",
+            DiagnosticCode::UnresolvedSourcePath => "unresolved-source-path",
+            DiagnosticCode::MalformedInitializer => "malformed-initializer",
+            DiagnosticCode::InitializerLengthMismatch => "initializer-length-mismatch",
+            DiagnosticCode::InitializerTypeMismatch => "initializer-type-mismatch",
+            DiagnosticCode::SignatureMismatch => "signature-mismatch",
+            DiagnosticCode::CallArgumentMismatch => "call-argument-mismatch",
+            DiagnosticCode::ConstantBranch => "constant-branch",
+            DiagnosticCode::DeadBlock => "dead-block",
+        }
+    }
+
+    pub fn severity(self) -> DiagnosticSeverity {
+        match self {
+            DiagnosticCode::UndefinedSymbol
+            | DiagnosticCode::DuplicateDefinition
+            | DiagnosticCode::SyntaxError
+            | DiagnosticCode::MissingTerminator
+            | DiagnosticCode::EmptyBasicBlock
+            | DiagnosticCode::MisplacedTerminator
+            | DiagnosticCode::DuplicateTerminator => DiagnosticSeverity::ERROR,
+            DiagnosticCode::UnknownBranchTargets
+            | DiagnosticCode::UnexpectedOperands
+            | DiagnosticCode::UnexpectedResult
+            | DiagnosticCode::ResultTypeMismatch
+            | DiagnosticCode::MalformedInitializer
+            | DiagnosticCode::InitializerLengthMismatch
+            | DiagnosticCode::InitializerTypeMismatch
+            | DiagnosticCode::SignatureMismatch
+            | DiagnosticCode::CallArgumentMismatch => DiagnosticSeverity::WARNING,
+            DiagnosticCode::UnresolvedSourcePath => DiagnosticSeverity::INFORMATION,
+            DiagnosticCode::ConstantBranch | DiagnosticCode::DeadBlock => DiagnosticSeverity::HINT,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            DiagnosticCode::UndefinedSymbol => {
+                "A function, variable, label or debug annotation is used, but never declared or defined."
+            }
+            DiagnosticCode::DuplicateDefinition => {
+                "A symbol is defined more than once within the same scope."
+            }
+            DiagnosticCode::SyntaxError => "The module could not be parsed.",
+            DiagnosticCode::UnknownBranchTargets => {
+                "The basic blocks targeted by a branching instruction could not be extracted from its operands."
+            }
+            DiagnosticCode::UnexpectedOperands => {
+                "The operands of an instruction don't match the instruction's syntax."
+            }
+            DiagnosticCode::UnexpectedResult => {
+                "The result of an instruction is assigned to a variable, although the instruction does not produce a value."
+            }
+            DiagnosticCode::ResultTypeMismatch => {
+                "The declared type of the result differs from the type produced by the instruction."
+            }
+            DiagnosticCode::MissingTerminator => {
+                "A basic block does not end with a terminator, such as `br` or `ret`."
+            }
+            DiagnosticCode::EmptyBasicBlock => "A basic block contains no instructions.",
+            DiagnosticCode::MisplacedTerminator => {
+                "A terminator is followed by further instructions within the same basic block."
+            }
+            DiagnosticCode::DuplicateTerminator => "A basic block contains multiple terminators.",
+            DiagnosticCode::UnresolvedSourcePath => {
+                "A source location in a debug annotation could not be found in the workspace. See the `hyperIr.pathSubstitutions` setting."
+            }
+            DiagnosticCode::MalformedInitializer => {
+                "The initializer of a global variable could not be parsed."
+            }
+            DiagnosticCode::InitializerLengthMismatch => {
+                "The number of elements in the initializer of a global variable differs from the array length or the number of struct fields."
+            }
+            DiagnosticCode::InitializerTypeMismatch => {
+                "A value in the initializer of a global variable does not fit its type."
+            }
+            DiagnosticCode::SignatureMismatch => {
+                "The modifiers, return type or arguments of a function definition differ from its declaration."
+            }
+            DiagnosticCode::CallArgumentMismatch => {
+                "The number or types of the arguments passed to a function differ from its signature."
+            }
+            DiagnosticCode::ConstantBranch => {
+                "The condition of a branch is constant, such that it always goes to the same basic block. Hyper could have emitted an unconditional branch instead."
+            }
+            DiagnosticCode::DeadBlock => {
+                "A basic block is never executed, e.g., because the conditions of all branches towards it are constant."
+            }
+        }
+    }
+
+    /// Whether `code_or_name` refers to this code, e.g. `HIR001` or `undefined-symbol`
+    pub fn matches(self, code_or_name: &str) -> bool {
+        self.code().eq_ignore_ascii_case(code_or_name) || self.name() == code_or_name
+    }
+
+    pub fn documentation_url(self) -> String {
+        format!(
+            "{}#{}-{}",
+            DIAGNOSTICS_DOCUMENTATION_URL,
+            self.code().to_lowercase(),
+            self.name()
+        )
+    }
+
+    pub fn from_diagnostic(diagnostic: &Diagnostic) -> Option<DiagnosticCode> {
+        let Some(NumberOrString::String(code)) = &diagnostic.code else {
+            return None;
+        };
+        DIAGNOSTIC_CODES.iter().copied().find(|c| c.code() == code)
+    }
+}
+
+/// A text edit which fixes a diagnostic. Stored in the diagnostic's `data`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuickFix {
+    pub title: String,
+    pub edit: TextEdit,
+}
+
+pub fn create_diagnostic(code: DiagnosticCode, range: Range, message: String) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(code.severity()),
+        code: Some(NumberOrString::String(code.code().to_string())),
+        code_description: Some(CodeDescription {
+            href: Url::parse(&code.documentation_url()).unwrap(),
+        }),
+        source: Some(DIAGNOSTIC_SOURCE.to_string()),
+        message,
+        ..Default::default()
+    }
+}
+
+/// Renders the reference of all diagnostics, published as `docs/diagnostics.md`
+pub fn diagnostic_reference() -> String {
+    let mut out = String::new();
+    out.push_str("# Hyper IR diagnostics\n\n");
+    out.push_str(
+        "<!-- Generated from `src/diagnostics.rs`. Run `UPDATE_DOCS=1 cargo test` to update. -->\n\n",
+    );
+    out.push_str(
+        "Diagnostics can be suppressed through the `hyperIr.suppressedDiagnostics` setting, using either their code or their name.\n",
+    );
+    for code in DIAGNOSTIC_CODES {
+        let severity = match code.severity() {
+            DiagnosticSeverity::ERROR => "error",
+            DiagnosticSeverity::WARNING => "warning",
+            DiagnosticSeverity::INFORMATION => "information",
+            _ => "hint",
+        };
+        out.push_str(&format!(
+            "\n## {} {}\n\n{}\n\n* Severity: {}\n",
+            code.code(),
+            code.name(),
+            code.description(),
+            severity
+        ));
+    }
+    out
+}
+
 pub fn diagnostics_from_parser<'a>(
     rope: &'a Rope,
     errors: &'a [Simple<String>],
@@ -43,7 +311,11 @@ pub fn diagnostics_from_parser<'a>(
         };
 
         || -> Option<Diagnostic> {
-            Some(Diagnostic::new_simple(range_to_lsp(rope, &span)?, message))
+            Some(create_diagnostic(
+                DiagnosticCode::SyntaxError,
+                range_to_lsp(rope, &span)?,
+                message,
+            ))
         }()
     })
 }
@@ -64,19 +336,190 @@ pub fn diagnostics_from_statements<'a>(
         .flatten()
         .flat_map(move |bb| bb.instructions.iter())
         .filter_map(move |i| {
+            let spec = i.opcode()?;
             let inst_name = &i.instruction.0;
-            if (i.is_branching() || inst_name == "phi") && i.basic_block_refs.is_empty() {
-                let message = format!(
-                    "Failed to extract basic block references from `{}` instruction",
-                    inst_name
-                );
-                Some(Diagnostic::new_simple(
-                    range_to_lsp(rope, &i.instruction.1)?,
-                    message,
+            let mut quick_fix = None;
+            let (code, message) = if spec.basic_block_refs(&i.operands).is_none() {
+                (
+                    DiagnosticCode::UnknownBranchTargets,
+                    format!(
+                        "Failed to extract basic block references from `{}` instruction",
+                        inst_name
+                    ),
+                )
+            } else if !spec.check_operands(&i.operands) {
+                (
+                    DiagnosticCode::UnexpectedOperands,
+                    format!(
+                        "Unexpected operands for `{}`, expected `{}`",
+                        inst_name,
+                        spec.operand_syntax()
+                    ),
+                )
+            } else {
+                let declared = i.assignment_type.as_ref()?;
+                match (spec.result, spec.result_type(&i.operands)) {
+                    (ResultType::None, _) => {
+                        quick_fix = Some(QuickFix {
+                            title: "Remove the assignment".to_string(),
+                            edit: TextEdit {
+                                range: range_to_lsp(
+                                    rope,
+                                    &(declared.1.start..i.instruction.1.start),
+                                )?,
+                                new_text: "".to_string(),
+                            },
+                        });
+                        (
+                            DiagnosticCode::UnexpectedResult,
+                            format!("`{}` does not produce a value", inst_name),
+                        )
+                    }
+                    (_, Some(result)) if result != IrType::from_name(&declared.0) => {
+                        quick_fix = Some(QuickFix {
+                            title: format!("Change the result type to `{}`", result),
+                            edit: TextEdit {
+                                range: range_to_lsp(rope, &declared.1)?,
+                                new_text: result.to_string(),
+                            },
+                        });
+                        (
+                            DiagnosticCode::ResultTypeMismatch,
+                            format!(
+                                "`{}` produces a value of type `{}`, but the result is declared as `{}`",
+                                inst_name, result, declared.0
+                            ),
+                        )
+                    }
+                    _ => return None,
+                }
+            };
+            Some(Diagnostic {
+                data: quick_fix.and_then(|f| serde_json::to_value(f).ok()),
+                ..create_diagnostic(code, range_to_lsp(rope, &i.instruction.1)?, message)
+            })
+        })
+}
+
+pub fn diagnostics_from_validation<'a>(
+    rope: &'a Rope,
+    uri: &'a Url,
+    stmts: &'a [Statement],
+) -> impl Iterator<Item = Diagnostic> + 'a {
+    validate_statements(stmts).into_iter().filter_map(move |e| {
+        let related_information = e
+            .related
+            .iter()
+            .map(|(span, message)| {
+                Some(DiagnosticRelatedInformation {
+                    location: Location {
+                        uri: uri.clone(),
+                        range: range_to_lsp(rope, span)?,
+                    },
+                    message: message.clone(),
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Diagnostic {
+            related_information: Some(related_information).filter(|r| !r.is_empty()),
+            ..create_diagnostic(e.code, range_to_lsp(rope, &e.span)?, e.message)
+        })
+    })
+}
+
+pub fn diagnostics_from_globals<'a>(
+    rope: &'a Rope,
+    stmts: &'a [Statement],
+) -> impl Iterator<Item = Diagnostic> + 'a {
+    stmts
+        .iter()
+        .flat_map(|s| match s {
+            Statement::GlobalVar { name, def } => match parse_initializer(def) {
+                Some(init) => init.check(),
+                None => vec![InitializerError {
+                    code: DiagnosticCode::MalformedInitializer,
+                    span: def.first().map_or(name.1.clone(), |first| {
+                        first.1.start..def[def.len() - 1].1.end
+                    }),
+                    message: format!("Failed to parse the initializer of `{}`", name.0),
+                }],
+            },
+            _ => vec![],
+        })
+        .filter_map(move |e| {
+            Some(create_diagnostic(
+                e.code,
+                range_to_lsp(rope, &e.span)?,
+                e.message,
+            ))
+        })
+}
+
+pub fn diagnostics_from_constant_propagation<'a>(
+    rope: &'a Rope,
+    stmts: &'a [Statement],
+) -> impl Iterator<Item = Diagnostic> + 'a {
+    stmts
+        .iter()
+        .filter_map(|s| match s {
+            Statement::FuncDef { body, .. } => Some(body),
+            _ => None,
+        })
+        .flat_map(move |body| {
+            let result = propagate_constants(&body.basic_blocks);
+            let branches = result.known_branches.into_iter().filter_map(move |b| {
+                Some(create_diagnostic(
+                    DiagnosticCode::ConstantBranch,
+                    range_to_lsp(rope, &b.span)?,
+                    format!("The branch always goes to `{}`", b.target),
                 ))
+            });
+            let dead_blocks = result.dead_blocks.into_iter().filter_map(move |idx| {
+                let bb = &body.basic_blocks[idx];
+                Some(Diagnostic {
+                    tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+                    ..create_diagnostic(
+                        DiagnosticCode::DeadBlock,
+                        range_to_lsp(rope, &bb.span)?,
+                        match &bb.label {
+                            Some(label) => format!("`{}` is never executed", label.0),
+                            None => "The basic block is never executed".to_string(),
+                        },
+                    )
+                })
+            });
+            branches.chain(dead_blocks).collect::<Vec<_>>()
+        })
+}
+
+pub fn diagnostics_from_source_references<'a>(
+    rope: &'a Rope,
+    source_refs: &'a [SourceReference],
+    resolver: &'a PathResolver,
+) -> impl Iterator<Item = Diagnostic> + 'a {
+    let root_paths = &resolver.root_paths;
+    source_refs
+        .iter()
+        .filter(|r| resolver.resolve(&r.filepath).is_none())
+        .filter_map(move |r| {
+            let message = if root_paths.is_empty() {
+                format!(
+                    "Could not resolve `{}`: no workspace folder is open",
+                    r.filepath
+                )
             } else {
-                None
-            }
+                let roots = root_paths
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("Could not resolve `{}`, tried {}", r.filepath, roots)
+            };
+            Some(create_diagnostic(
+                DiagnosticCode::UnresolvedSourcePath,
+                range_to_lsp(rope, &r.span)?,
+                message,
+            ))
         })
 }
 
@@ -107,7 +550,11 @@ pub fn diagnostics_from_index<'a>(
                     .iter()
                     .filter_map(move |use_| {
                         let message = format!("Use of undefined symbol `{}`", name);
-                        Some(Diagnostic::new_simple(range_to_lsp(rope, use_)?, message))
+                        Some(create_diagnostic(
+                            DiagnosticCode::UndefinedSymbol,
+                            range_to_lsp(rope, use_)?,
+                            message,
+                        ))
                     })
                     .collect::<Vec<_>>();
                 Some(diags)
@@ -126,15 +573,12 @@ pub fn diagnostics_from_index<'a>(
                             message: "Previously defined here".to_string(),
                         }]);
                         Some(Diagnostic {
-                            range: range_to_lsp(rope, use_)?,
-                            message,
-                            severity: None,
-                            code: None,
-                            code_description: None,
-                            source: None,
                             related_information,
-                            tags: None,
-                            data: None,
+                            ..create_diagnostic(
+                                DiagnosticCode::DuplicateDefinition,
+                                range_to_lsp(rope, use_)?,
+                                message,
+                            )
                         })
                     })
                     .collect::<Vec<_>>();
@@ -145,3 +589,284 @@ pub fn diagnostics_from_index<'a>(
         })
         .flatten()
 }
+
+/// A result id for pull diagnostics, which changes whenever `value` changes
+pub fn hash_result_id<T: Serialize>(value: &T) -> String {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(value).unwrap().hash(&mut hasher);
+    format!("{:x}", hasher.finish())
+}
+
+/// Collects the diagnostics from all our analyses, without the ones suppressed by `config`
+#[allow(clippy::too_many_arguments)]
+pub fn collect_diagnostics(
+    uri: &Url,
+    rope: &Rope,
+    errors: &[Simple<String>],
+    stmts: &[Statement],
+    index: &HIRIndex,
+    source_refs: &[SourceReference],
+    resolver: &PathResolver,
+    config: &Config,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::<Diagnostic>::new();
+    diagnostics.extend(diagnostics_from_parser(rope, errors));
+    diagnostics.extend(diagnostics_from_statements(rope, stmts));
+    diagnostics.extend(diagnostics_from_validation(rope, uri, stmts));
+    diagnostics.extend(diagnostics_from_globals(rope, stmts));
+    diagnostics.extend(diagnostics_from_constant_propagation(rope, stmts));
+    diagnostics.extend(diagnostics_from_index(rope, uri, index));
+    diagnostics.extend(diagnostics_from_source_references(
+        rope,
+        source_refs,
+        resolver,
+    ));
+    diagnostics.retain(|d| !config.is_suppressed(d));
+    diagnostics
+}
+
+/// Analyzes a file which is not opened in the editor. Returns a result id which changes
+/// whenever the file, the workspace files or the configuration change, and the diagnostics unless the result id
+/// is still `previous_result_id`. This reads the file, so don't call it on the async executor.
+pub fn file_diagnostics(
+    path: &Path,
+    previous_result_id: Option<&str>,
+    resolver: &PathResolver,
+    config: &Config,
+) -> Option<(String, Option<Vec<Diagnostic>>)> {
+    let uri = Url::from_file_path(path).ok()?;
+    let metadata = std::fs::metadata(path).ok()?;
+    let result_id = hash_result_id(&(
+        metadata.modified().ok(),
+        metadata.len(),
+        resolver.generation(),
+        config,
+    ));
+    if previous_result_id == Some(&result_id) {
+        return Some((result_id, None));
+    }
+
+    let rope = Rope::from_str(&std::fs::read_to_string(path).ok()?);
+    let src = rope.to_string();
+    let ParserResult {
+        tokens,
+        stmts,
+        errors,
+    } = parse_from_str(&src);
+    let index = create_index(&src, &tokens, &stmts);
+    let source_refs = find_source_references(&stmts);
+    let diagnostics = collect_diagnostics(
+        &uri,
+        &rope,
+        &errors,
+        &stmts,
+        &index,
+        &source_refs,
+        resolver,
+        config,
+    );
+    Some((result_id, Some(diagnostics)))
+}
+
+#[cfg(test)]
+fn statement_diagnostics(src: &str) -> Vec<String> {
+    let res = crate::hir_parser::parse_from_str(src);
+    let rope = Rope::from_str(src);
+    diagnostics_from_statements(&rope, &res.stmts)
+        .chain(diagnostics_from_globals(&rope, &res.stmts))
+        .map(|d| d.message)
+        .collect()
+}
+
+#[test]
+fn test_instruction_diagnostics() {
+    let src = "
+define void @foo(ptr %p) {
+body_0:
+  int32 %v1 = load int32 ptr %p
+  int32 %v2 = ult int32 %v1, int32 0
+  int32 %v3 = add int32 %v1
+  int32 %v4 = store int32 ptr %p, int32 %v1
+  int64 %v5 = add int64 %v1, int64 1
+  br int1 %v2 body_0, body_0
+}";
+    assert_eq!(
+        statement_diagnostics(src),
+        [
+            "`ult` produces a value of type `int1`, but the result is declared as `int32`",
+            "Unexpected operands for `add`, expected `add <type> <value>, <type> <value>`",
+            "`store` does not produce a value",
+            "Failed to extract basic block references from `br` instruction",
+        ]
+    );
+}
+
+#[test]
+fn test_global_diagnostics() {
+    let src = "
+@a = int8 [1, 2, 300]
+@b = int32[3] [1, 2]
+@c = int8 [1, 2
+";
+    assert_eq!(
+        statement_diagnostics(src),
+        [
+            "`300` does not fit into `int8`",
+            "Expected 3 elements, found 2",
+            "Failed to parse the initializer of `@c`",
+        ]
+    );
+}
+
+#[test]
+fn test_constant_propagation_diagnostics() {
+    let src = "
+define void @f() {
+body_0:
+  int1 %c = eq int32 1, int32 2
+  br int1 %c, then_1, exit_2
+then_1:
+  br exit_2
+exit_2:
+  ret
+}";
+    let res = crate::hir_parser::parse_from_str(src);
+    let rope = Rope::from_str(src);
+    let diagnostics = diagnostics_from_constant_propagation(&rope, &res.stmts).collect::<Vec<_>>();
+    let messages = diagnostics
+        .iter()
+        .map(|d| d.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        [
+            "The branch always goes to `exit_2`",
+            "`then_1` is never executed"
+        ]
+    );
+    assert_eq!(diagnostics[1].tags, Some(vec![DiagnosticTag::UNNECESSARY]));
+}
+
+#[test]
+fn examples_have_no_instruction_diagnostics() {
+    for path in [
+        "examples/fcf.hir",
+        "examples/insert.hir",
+        "examples/query.hir",
+        "examples/relation.hir",
+    ] {
+        let src = std::fs::read_to_string(path).unwrap();
+        assert_eq!(
+            statement_diagnostics(&src),
+            Vec::<String>::new(),
+            "{}",
+            path
+        );
+    }
+}
+
+#[test]
+fn test_quick_fixes() {
+    let src = "
+define void @foo(ptr %p) {
+body_0:
+  int32 %v1 = load int32 ptr %p
+  int32 %v2 = ult int32 %v1, int32 0
+  int32 %v4 = store int32 ptr %p, int32 %v1
+  ret
+}";
+    let res = crate::hir_parser::parse_from_str(src);
+    let rope = Rope::from_str(src);
+    let fixes = diagnostics_from_statements(&rope, &res.stmts)
+        .map(|d| {
+            assert_eq!(d.source.as_deref(), Some(DIAGNOSTIC_SOURCE));
+            let code = DiagnosticCode::from_diagnostic(&d).unwrap();
+            let fix = serde_json::from_value::<QuickFix>(d.data.unwrap()).unwrap();
+            (code.name(), fix.title, fix.edit.new_text)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        fixes,
+        [
+            (
+                "result-type-mismatch",
+                "Change the result type to `int1`".to_string(),
+                "int1".to_string()
+            ),
+            (
+                "unexpected-result",
+                "Remove the assignment".to_string(),
+                "".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn diagnostic_codes_are_unique() {
+    for (i, a) in DIAGNOSTIC_CODES.iter().enumerate() {
+        for b in &DIAGNOSTIC_CODES[i + 1..] {
+            assert_ne!(a.code(), b.code());
+            assert_ne!(a.name(), b.name());
+        }
+    }
+    assert!(DiagnosticCode::UndefinedSymbol.matches("HIR001"));
+    assert!(DiagnosticCode::UndefinedSymbol.matches("undefined-symbol"));
+    assert!(!DiagnosticCode::UndefinedSymbol.matches("HIR002"));
+}
+
+#[test]
+fn diagnostic_docs_are_up_to_date() {
+    let path = "docs/diagnostics.md";
+    if std::env::var("UPDATE_DOCS").is_ok() {
+        std::fs::write(path, diagnostic_reference()).unwrap();
+    }
+    // Git might check out the docs with CRLF line endings on Windows
+    let docs = std::fs::read_to_string(path).unwrap().replace("\r\n", "\n");
+    assert_eq!(docs, diagnostic_reference());
+}
+
+#[test]
+fn test_file_diagnostics() {
+    let dir = std::env::temp_dir().join(format!("hyper-ir-lsp-diagnostics-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("test.hir");
+    std::fs::write(&path, "define void @f() {\nbody_0:\n  ret\n  ret\n}\n").unwrap();
+    let resolver = PathResolver::default();
+    let config = Config::default();
+    let messages = |diagnostics: Option<Vec<Diagnostic>>| {
+        diagnostics.map(|d| d.into_iter().map(|d| d.message).collect::<Vec<_>>())
+    };
+
+    let (result_id, diagnostics) = file_diagnostics(&path, None, &resolver, &config).unwrap();
+    assert_eq!(
+        messages(diagnostics),
+        Some(vec![
+            "Basic block `body_0` already ended with `ret`".to_string()
+        ])
+    );
+    // Unchanged files are not analyzed again
+    assert_eq!(
+        file_diagnostics(&path, Some(&result_id), &resolver, &config),
+        Some((result_id.clone(), None))
+    );
+    // Changing the file or the configuration changes the result id
+    std::fs::write(&path, "define void @f() {\nbody_0:\n  ret\n}\n").unwrap();
+    let (changed_id, diagnostics) =
+        file_diagnostics(&path, Some(&result_id), &resolver, &config).unwrap();
+    assert_ne!(changed_id, result_id);
+    assert_eq!(messages(diagnostics), Some(vec![]));
+    let config = Config {
+        suppressed_diagnostics: vec!["HIR001".to_string()],
+        ..Config::default()
+    };
+    let (config_id, _) = file_diagnostics(&path, Some(&changed_id), &resolver, &config).unwrap();
+    assert_ne!(config_id, changed_id);
+    // Created or deleted workspace files might change how source paths resolve
+    resolver.invalidate();
+    let (workspace_id, _) = file_diagnostics(&path, Some(&config_id), &resolver, &config).unwrap();
+    assert_ne!(workspace_id, config_id);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(file_diagnostics(&path, None, &resolver, &config), None);
+}
@@ -0,0 +1,457 @@
+use std::ops::Range;
+
+use crate::{
+    hir_parser::Statement,
+    hir_printer::print_token,
+    hir_tokenizer::{Span, Spanned, Token},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriviaKind {
+    Whitespace,
+    Newline,
+    Comment,
+    // Characters skipped by the tokenizer's error recovery
+    Skipped,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+    pub span: Span,
+}
+
+/// A significant token together with its surrounding trivia.
+///
+/// Trailing trivia extends up to the next token on the same line, or up to and including the
+/// line break. All other trivia is attached as leading trivia to the following token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CstToken {
+    pub token: Spanned<Token>,
+    pub leading_trivia: Vec<Trivia>,
+    pub trailing_trivia: Vec<Trivia>,
+}
+
+impl CstToken {
+    fn ends_line(&self) -> bool {
+        self.trailing_trivia
+            .iter()
+            .any(|t| t.kind == TriviaKind::Newline)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CstNodeKind {
+    GlobalVar,
+    FuncDecl,
+    FuncDef,
+    FuncDependencies,
+    DbgAnnotation,
+    BasicBlock,
+    Instruction,
+    // Tokens which are not part of any successfully parsed statement
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CstNode {
+    pub kind: CstNodeKind,
+    /// The span covered by the node's tokens, excluding trivia
+    pub span: Span,
+    /// The node's tokens, as indices into `Cst::tokens`
+    pub tokens: Range<usize>,
+    pub children: Vec<CstNode>,
+}
+
+/// A lossless concrete syntax tree.
+///
+/// The tokens are stored as a flat list, with all whitespace and comments attached to them as
+/// trivia. The nodes group those tokens according to the parsed statements.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct Cst {
+    pub tokens: Vec<CstToken>,
+    pub nodes: Vec<CstNode>,
+    /// Trivia after the last token
+    pub eof_trivia: Vec<Trivia>,
+}
+
+impl Cst {
+    /// Reconstructs the original source text
+    pub fn to_source(&self) -> String {
+        let mut out = String::new();
+        self.write_tokens(&mut out, 0..self.tokens.len());
+        push_trivia_verbatim(&mut out, &self.eof_trivia);
+        out
+    }
+
+    /// Reconstructs the source text of a node, including its leading and trailing trivia
+    pub fn node_source(&self, node: &CstNode) -> String {
+        let mut out = String::new();
+        self.write_tokens(&mut out, node.tokens.clone());
+        out
+    }
+
+    fn write_tokens(&self, out: &mut String, tokens: Range<usize>) {
+        for t in &self.tokens[tokens] {
+            push_trivia_verbatim(out, &t.leading_trivia);
+            out.push_str(&print_token(&t.token.0));
+            push_trivia_verbatim(out, &t.trailing_trivia);
+        }
+    }
+
+    /// The tokens on the line containing `offset`
+    pub fn line_tokens(&self, offset: usize) -> Range<usize> {
+        line_range(&self.tokens, offset)
+    }
+
+    /// Finds the innermost node of the given kind containing `offset`
+    pub fn find_node_at(&self, offset: usize, kind: CstNodeKind) -> Option<&CstNode> {
+        let mut nodes = &self.nodes;
+        let mut found = None;
+        while let Some(node) = nodes.iter().find(|n| n.span.contains(&offset)) {
+            if node.kind == kind {
+                found = Some(node);
+            }
+            nodes = &node.children;
+        }
+        found
+    }
+
+    /// The comments on the lines directly preceding the node.
+    /// An empty line separates unrelated comments.
+    pub fn leading_comments(&self, node: &CstNode) -> Vec<&str> {
+        let Some(first) = self.tokens.get(node.tokens.start) else {
+            return vec![];
+        };
+        let mut comments = vec![];
+        let mut newlines = 0;
+        for trivia in first.leading_trivia.iter().rev() {
+            match trivia.kind {
+                TriviaKind::Whitespace => {}
+                TriviaKind::Newline => {
+                    newlines += 1;
+                    if newlines > 1 {
+                        break;
+                    }
+                }
+                TriviaKind::Comment => {
+                    comments.push(trivia.text.as_str());
+                    newlines = 0;
+                }
+                TriviaKind::Skipped => break,
+            }
+        }
+        comments.reverse();
+        comments
+    }
+
+    /// The comment at the end of the node's last line
+    pub fn trailing_comment(&self, node: &CstNode) -> Option<&str> {
+        let last = self.tokens.get(node.tokens.end.checked_sub(1)?)?;
+        last.trailing_trivia
+            .iter()
+            .find(|t| t.kind == TriviaKind::Comment)
+            .map(|t| t.text.as_str())
+    }
+}
+
+fn push_trivia_verbatim(out: &mut String, trivia: &[Trivia]) {
+    for t in trivia {
+        out.push_str(&t.text);
+    }
+}
+
+/// Our spans count chars, not bytes. This cursor translates between both while
+/// moving forward through the source text.
+struct CharCursor<'a> {
+    src: &'a str,
+    char_pos: usize,
+    byte_pos: usize,
+}
+
+impl<'a> CharCursor<'a> {
+    fn byte_offset(&mut self, char_pos: usize) -> usize {
+        let skipped = self.src[self.byte_pos..]
+            .chars()
+            .take(char_pos - self.char_pos)
+            .map(char::len_utf8)
+            .sum::<usize>();
+        self.char_pos = char_pos;
+        self.byte_pos += skipped;
+        self.byte_pos
+    }
+
+    fn text(&mut self, span: &Span) -> &'a str {
+        let start = self.byte_offset(span.start);
+        let end = self.byte_offset(span.end);
+        &self.src[start..end]
+    }
+}
+
+#[derive(Default)]
+struct TriviaAttacher {
+    tokens: Vec<CstToken>,
+    pending: Vec<Trivia>,
+    attach_trailing: bool,
+}
+
+impl TriviaAttacher {
+    fn push_trivia(&mut self, trivia: Trivia) {
+        if self.attach_trailing {
+            self.attach_trailing = trivia.kind != TriviaKind::Newline;
+            self.tokens.last_mut().unwrap().trailing_trivia.push(trivia);
+        } else {
+            self.pending.push(trivia);
+        }
+    }
+
+    /// Splits the text between two tokens into whitespace and skipped characters
+    fn push_gap(&mut self, text: &str, start: usize) {
+        let mut pos = start;
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            let is_ws = |c: char| c == ' ' || c == '\t';
+            let run = rest
+                .find(|n: char| is_ws(n) != is_ws(c))
+                .unwrap_or(rest.len());
+            let (run_text, remaining) = rest.split_at(run);
+            let run_len = run_text.chars().count();
+            self.push_trivia(Trivia {
+                kind: if is_ws(c) {
+                    TriviaKind::Whitespace
+                } else {
+                    TriviaKind::Skipped
+                },
+                text: run_text.to_string(),
+                span: pos..pos + run_len,
+            });
+            pos += run_len;
+            rest = remaining;
+        }
+    }
+
+    fn push_token(&mut self, token: Spanned<Token>) {
+        self.tokens.push(CstToken {
+            token,
+            leading_trivia: std::mem::take(&mut self.pending),
+            trailing_trivia: vec![],
+        });
+        self.attach_trailing = true;
+    }
+}
+
+/// The tokens on the line containing the token at `offset`
+fn line_range(tokens: &[CstToken], offset: usize) -> Range<usize> {
+    let mut start = tokens.partition_point(|t| t.token.1.start < offset);
+    if start == tokens.len() {
+        return start..start;
+    }
+    while start > 0 && !tokens[start - 1].ends_line() {
+        start -= 1;
+    }
+    let mut end = start + 1;
+    while end < tokens.len() && !tokens[end - 1].ends_line() {
+        end += 1;
+    }
+    start..end
+}
+
+fn create_node(
+    tokens: &[CstToken],
+    kind: CstNodeKind,
+    range: Range<usize>,
+    children: Vec<CstNode>,
+) -> CstNode {
+    let span = match (tokens.get(range.start), range.end.checked_sub(1)) {
+        (Some(first), Some(last)) if !range.is_empty() => {
+            first.token.1.start..tokens[last].token.1.end
+        }
+        _ => 0..0,
+    };
+    CstNode {
+        kind,
+        span,
+        tokens: range,
+        children,
+    }
+}
+
+fn create_statement_node(tokens: &[CstToken], stmt: &Statement) -> CstNode {
+    let single_line = |kind, offset| create_node(tokens, kind, line_range(tokens, offset), vec![]);
+    match stmt {
+        Statement::GlobalVar { name, .. } => single_line(CstNodeKind::GlobalVar, name.1.start),
+        Statement::FuncDecl { signature, .. } => {
+            single_line(CstNodeKind::FuncDecl, signature.ret_type.1.start)
+        }
+        Statement::FuncDependencies { dependent, .. } => {
+            single_line(CstNodeKind::FuncDependencies, dependent.1.start)
+        }
+        Statement::DbgAnnotation { name, .. } => {
+            single_line(CstNodeKind::DbgAnnotation, name.1.start)
+        }
+        Statement::FuncDef {
+            define_kw, body, ..
+        } => {
+            let bbs = body
+                .basic_blocks
+                .iter()
+                .map(|bb| {
+                    let instructions = bb
+                        .instructions
+                        .iter()
+                        .map(|i| single_line(CstNodeKind::Instruction, i.span.start))
+                        .collect::<Vec<_>>();
+                    let first = line_range(tokens, bb.span.start);
+                    let end = instructions.last().map_or(first.end, |i| i.tokens.end);
+                    create_node(
+                        tokens,
+                        CstNodeKind::BasicBlock,
+                        first.start..end,
+                        instructions,
+                    )
+                })
+                .collect::<Vec<_>>();
+            let header = line_range(tokens, define_kw.start);
+            let footer = line_range(tokens, body.closing_bracket.start);
+            create_node(tokens, CstNodeKind::FuncDef, header.start..footer.end, bbs)
+        }
+    }
+}
+
+/// Creates one error node per line for the given tokens
+fn push_error_nodes(tokens: &[CstToken], nodes: &mut Vec<CstNode>, range: Range<usize>) {
+    let mut next = range.start;
+    while next < range.end {
+        let end = line_range(tokens, tokens[next].token.1.start)
+            .end
+            .min(range.end);
+        nodes.push(create_node(tokens, CstNodeKind::Error, next..end, vec![]));
+        next = end;
+    }
+}
+
+/// Builds the concrete syntax tree from the tokenizer's and the parser's output
+pub fn build_cst(src: &str, tokens: &[Spanned<Token>], stmts: &[Statement]) -> Cst {
+    let mut cursor = CharCursor {
+        src,
+        char_pos: 0,
+        byte_pos: 0,
+    };
+    let mut attacher = TriviaAttacher::default();
+    let mut pos = 0;
+    for (token, span) in tokens {
+        let gap = cursor.text(&(pos..span.start));
+        attacher.push_gap(gap, pos);
+        let kind = match token {
+            Token::Comment(_) => Some(TriviaKind::Comment),
+            Token::Newline => Some(TriviaKind::Newline),
+            _ => None,
+        };
+        let text = cursor.text(span);
+        match kind {
+            Some(kind) => attacher.push_trivia(Trivia {
+                kind,
+                text: text.to_string(),
+                span: span.clone(),
+            }),
+            None => {
+                // After recovering from an error, the tokenizer includes the skipped
+                // characters in the span of the next token
+                let printed = print_token(token);
+                let skipped = text.strip_suffix(printed.as_str()).unwrap_or("");
+                let token_start = span.start + skipped.chars().count();
+                attacher.push_gap(skipped, span.start);
+                attacher.push_token((token.clone(), token_start..span.end));
+            }
+        }
+        pos = span.end;
+    }
+    let rest = &src[cursor.byte_offset(pos)..];
+    attacher.push_gap(rest, pos);
+
+    // Group the tokens into nodes. Tokens between the statements become error nodes.
+    let cst_tokens = attacher.tokens;
+    let mut nodes = vec![];
+    let mut next_token = 0;
+    for stmt in stmts {
+        let node = create_statement_node(&cst_tokens, stmt);
+        push_error_nodes(&cst_tokens, &mut nodes, next_token..node.tokens.start);
+        next_token = next_token.max(node.tokens.end);
+        nodes.push(node);
+    }
+    push_error_nodes(&cst_tokens, &mut nodes, next_token..cst_tokens.len());
+
+    Cst {
+        tokens: cst_tokens,
+        nodes,
+        eof_trivia: attacher.pending,
+    }
+}
+
+#[cfg(test)]
+fn cst_from_str(src: &str) -> Cst {
+    let res = crate::hir_parser::parse_from_str(src);
+    build_cst(src, &res.tokens, &res.stmts)
+}
+
+#[test]
+fn test_lossless() {
+    for src in [
+        "",
+        "  \n# only a comment",
+        "@a = [1]\r\n\tbroken  line ? äöü\n\n@b = [2] # comment\n   ",
+        &std::fs::read_to_string("examples/fcf.hir").unwrap(),
+        &std::fs::read_to_string("examples/insert.hir").unwrap(),
+        &std::fs::read_to_string("examples/query.hir").unwrap(),
+        &std::fs::read_to_string("examples/relation.hir").unwrap(),
+    ] {
+        assert_eq!(cst_from_str(src).to_source(), src);
+    }
+}
+
+#[test]
+fn test_cst_structure() {
+    let src = "\
+# Hyper IR for a test
+@a = [1]
+broken line
+define void @foo() {
+body_0:
+  # a comment
+  # spanning two lines
+  ret !1  # generateBinaryOperatorFcf
+}
+";
+    let cst = cst_from_str(src);
+    let kinds = cst.nodes.iter().map(|n| n.kind).collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        [
+            CstNodeKind::GlobalVar,
+            CstNodeKind::Error,
+            CstNodeKind::FuncDef
+        ]
+    );
+    assert_eq!(
+        cst.leading_comments(&cst.nodes[0]),
+        ["# Hyper IR for a test"]
+    );
+    assert_eq!(cst.node_source(&cst.nodes[1]), "broken line\n");
+
+    let bb = &cst.nodes[2].children[0];
+    assert_eq!(bb.kind, CstNodeKind::BasicBlock);
+    let ret = cst
+        .find_node_at(src.find("ret").unwrap(), CstNodeKind::Instruction)
+        .unwrap();
+    assert_eq!(ret, &bb.children[0]);
+    assert_eq!(
+        cst.leading_comments(ret),
+        ["# a comment", "# spanning two lines"]
+    );
+    assert_eq!(
+        cst.trailing_comment(ret),
+        Some("# generateBinaryOperatorFcf")
+    );
+    assert_eq!(cst.trailing_comment(&cst.nodes[0]), None);
+}
@@ -0,0 +1,453 @@
+use crate::{
+    diagnostics::DiagnosticCode,
+    hir_operands::{parse_value, split_operands, Value},
+    hir_tokenizer::{Span, Spanned, Token},
+    hir_types::{parse_type, IrType},
+};
+
+/// A constant used to initialize a global variable
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Constant {
+    /// `[1, 2, 3]`
+    Array(Vec<Spanned<Constant>>),
+    /// `{1, null}`
+    Struct(Vec<Spanned<Constant>>),
+    /// `"abc"`
+    String(String),
+    Value(Value),
+}
+
+/// The initializer of a global variable, e.g. `int8 [0, 0, 4, 1]`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GlobalInitializer {
+    /// The type as written. For arrays, Hyper only prints the element type.
+    pub declared_type: Spanned<IrType>,
+    pub value: Spanned<Constant>,
+}
+
+/// A problem with the initializer of a global variable
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InitializerError {
+    pub code: DiagnosticCode,
+    pub span: Span,
+    pub message: String,
+}
+
+/// Splits off a bracketed token list, returning the tokens inside, the complete span and the rest
+#[allow(clippy::type_complexity)]
+fn split_delimited(
+    tokens: &[Spanned<Token>],
+    open: char,
+    close: char,
+) -> Option<(&[Spanned<Token>], Span, &[Spanned<Token>])> {
+    let [(Token::Punctuation(c), first), ..] = tokens else {
+        return None;
+    };
+    if *c != open {
+        return None;
+    }
+    let mut depth = 0;
+    for (i, (token, span)) in tokens.iter().enumerate() {
+        match token {
+            Token::Punctuation(c) if *c == open => depth += 1,
+            Token::Punctuation(c) if *c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((&tokens[1..i], first.start..span.end, &tokens[i + 1..]));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_elements(tokens: &[Spanned<Token>]) -> Option<Vec<Spanned<Constant>>> {
+    split_operands(tokens)
+        .into_iter()
+        .map(|element| match parse_constant(element) {
+            Some((constant, [])) => Some(constant),
+            // Elements might also be prefixed with their type, e.g. `{int32 1, ptr null}`
+            _ => match parse_constant(parse_type(element)?.1)? {
+                (constant, []) => Some(constant),
+                _ => None,
+            },
+        })
+        .collect()
+}
+
+/// Parses a constant from the beginning of a token soup, returning the remaining tokens
+pub fn parse_constant(tokens: &[Spanned<Token>]) -> Option<(Spanned<Constant>, &[Spanned<Token>])> {
+    if let Some((inner, span, rest)) = split_delimited(tokens, '[', ']') {
+        return Some(((Constant::Array(parse_elements(inner)?), span), rest));
+    }
+    if let Some((inner, span, rest)) = split_delimited(tokens, '{', '}') {
+        return Some(((Constant::Struct(parse_elements(inner)?), span), rest));
+    }
+    if let [(Token::Str(s), span), rest @ ..] = tokens {
+        return Some(((Constant::String(s.clone()), span.clone()), rest));
+    }
+    let ((value, span), rest) = parse_value(tokens)?;
+    Some(((Constant::Value(value), span), rest))
+}
+
+/// Parses the initializer of a global variable, i.e. everything after the `=`
+pub fn parse_initializer(def: &[Spanned<Token>]) -> Option<GlobalInitializer> {
+    let with_type = |(type_, rest): (IrType, &[Spanned<Token>])| {
+        let type_span = def.first()?.1.start..def[def.len() - rest.len() - 1].1.end;
+        match parse_constant(rest)? {
+            (value, []) => Some(GlobalInitializer {
+                declared_type: (type_, type_span),
+                value,
+            }),
+            _ => None,
+        }
+    };
+    let (type_, rest) = parse_type(def)?;
+    let type_len = def.len() - rest.len();
+    // `int8 [5]` is a single-element array, not the type `int8[5]` without a value.
+    // Likewise, `int8[3] [1]` is a one-element literal of type `int8[3]`.
+    with_type((type_, rest)).or_else(|| {
+        (1..type_len)
+            .rev()
+            .filter(|i| def[*i].0 == Token::Punctuation('['))
+            .find_map(|i| match parse_type(&def[..i])? {
+                (type_, []) => with_type((type_, &def[i..])),
+                _ => None,
+            })
+    })
+}
+
+impl GlobalInitializer {
+    /// The type of the global variable, e.g. `int8[4]` for `int8 [0, 0, 4, 1]`
+    pub fn type_(&self) -> IrType {
+        match (&self.declared_type.0, &self.value.0) {
+            (IrType::Array(..), _) => self.declared_type.0.clone(),
+            (elem, Constant::Array(elems)) => {
+                IrType::Array(Box::new(elem.clone()), elems.len() as u64)
+            }
+            (type_, _) => type_.clone(),
+        }
+    }
+
+    /// Checks that the constant matches the type
+    pub fn check(&self) -> Vec<InitializerError> {
+        let mut errors = vec![];
+        check_constant(&self.type_(), &self.value, &mut errors);
+        errors
+    }
+
+    /// The bytes of the initialized memory, in little-endian byte order.
+    /// Not available if the initializer refers to other globals or is larger than
+    /// [`MAX_INITIALIZER_BYTES`].
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        let type_ = self.type_();
+        if type_.size_of()? > MAX_INITIALIZER_BYTES {
+            return None;
+        }
+        let mut bytes = vec![];
+        write_bytes(&type_, &self.value.0, &mut bytes)?;
+        Some(bytes)
+    }
+}
+
+fn describe_constant(c: &Constant) -> &'static str {
+    match c {
+        Constant::Array(_) => "an array",
+        Constant::Struct(_) => "a struct",
+        Constant::String(_) => "a string",
+        Constant::Value(Value::Local(_)) => "a local variable",
+        Constant::Value(Value::Global(_)) => "a global",
+        Constant::Value(Value::Int(_)) => "an integer",
+        Constant::Value(Value::Float(_)) => "a floating point number",
+        Constant::Value(Value::Bool(_)) => "a boolean",
+        Constant::Value(Value::Null) => "`null`",
+    }
+}
+
+/// The declared length of arrays comes from the file, so we never materialize the
+/// expected element types
+fn check_elements<'a>(
+    expected: impl Iterator<Item = &'a IrType>,
+    expected_len: u64,
+    elems: &[Spanned<Constant>],
+    span: &Span,
+    what: &str,
+    errors: &mut Vec<InitializerError>,
+) {
+    if expected_len != elems.len() as u64 {
+        errors.push(InitializerError {
+            code: DiagnosticCode::InitializerLengthMismatch,
+            span: span.clone(),
+            message: format!("Expected {} {}, found {}", expected_len, what, elems.len()),
+        });
+    }
+    for (type_, elem) in expected.zip(elems) {
+        check_constant(type_, elem, errors);
+    }
+}
+
+fn check_constant(
+    type_: &IrType,
+    (constant, span): &Spanned<Constant>,
+    errors: &mut Vec<InitializerError>,
+) {
+    let fits = match (type_, constant) {
+        (IrType::Array(elem, len), Constant::Array(elems)) => {
+            check_elements(
+                std::iter::repeat(elem.as_ref()),
+                *len,
+                elems,
+                span,
+                "elements",
+                errors,
+            );
+            true
+        }
+        (IrType::Array(elem, len), Constant::String(s)) if **elem == IrType::Int(8) => {
+            if s.len() as u64 > *len {
+                errors.push(InitializerError {
+                    code: DiagnosticCode::InitializerLengthMismatch,
+                    span: span.clone(),
+                    message: format!("The string has {} bytes, but only {} fit", s.len(), len),
+                });
+            }
+            true
+        }
+        (IrType::Struct { fields, .. }, Constant::Struct(elems)) => {
+            check_elements(
+                fields.iter(),
+                fields.len() as u64,
+                elems,
+                span,
+                "fields",
+                errors,
+            );
+            true
+        }
+        (IrType::Int(bits), Constant::Value(Value::Int(v))) => {
+            if *bits < 128 && *v >> bits != 0 {
+                errors.push(InitializerError {
+                    code: DiagnosticCode::InitializerTypeMismatch,
+                    span: span.clone(),
+                    message: format!("`{}` does not fit into `{}`", v, type_),
+                });
+            }
+            true
+        }
+        (IrType::Int(_) | IrType::Data128, Constant::Value(Value::Int(_) | Value::Bool(_))) => true,
+        (IrType::Float(_), Constant::Value(Value::Int(_) | Value::Float(_))) => true,
+        (IrType::Ptr, Constant::Value(Value::Null | Value::Global(_) | Value::Int(_))) => true,
+        // We don't know the layout of types defined outside the module
+        (IrType::Named(_), _) => true,
+        _ => false,
+    };
+    if !fits {
+        errors.push(InitializerError {
+            code: DiagnosticCode::InitializerTypeMismatch,
+            span: span.clone(),
+            message: format!(
+                "Expected a value of type `{}`, found {}",
+                type_,
+                describe_constant(constant)
+            ),
+        });
+    }
+}
+
+fn write_bytes(type_: &IrType, constant: &Constant, bytes: &mut Vec<u8>) -> Option<()> {
+    let start = bytes.len();
+    match (type_, constant) {
+        (IrType::Array(elem, _), Constant::Array(elems)) => {
+            for e in elems {
+                write_bytes(elem, &e.0, bytes)?;
+            }
+        }
+        (IrType::Array(..), Constant::String(s)) => bytes.extend(s.as_bytes()),
+        (IrType::Struct { fields, .. }, Constant::Struct(elems)) => {
+            for (idx, (field, elem)) in fields.iter().zip(elems).enumerate() {
+                bytes.resize(start + type_.field_offset(idx)? as usize, 0);
+                write_bytes(field, &elem.0, bytes)?;
+            }
+        }
+        (IrType::Float(32), Constant::Value(Value::Float(f))) => {
+            bytes.extend(f.parse::<f32>().ok()?.to_le_bytes())
+        }
+        (IrType::Float(_), Constant::Value(Value::Float(f))) => {
+            bytes.extend(f.parse::<f64>().ok()?.to_le_bytes())
+        }
+        (_, Constant::Value(Value::Int(v))) => {
+            let size = type_.size_of()? as usize;
+            bytes.extend(&v.to_le_bytes()[..size.min(16)])
+        }
+        (_, Constant::Value(Value::Bool(b))) => bytes.push(*b as u8),
+        (IrType::Ptr, Constant::Value(Value::Null)) => bytes.extend(0u64.to_le_bytes()),
+        _ => return None,
+    }
+    // Pad to the size of the type, e.g., for strings or for padding at the end of a struct
+    if let Some(size) = type_.size_of() {
+        if bytes.len() < start + size as usize {
+            bytes.resize(start + size as usize, 0);
+        }
+    }
+    Some(())
+}
+
+/// Larger initializers are not converted to bytes, since their length is taken from the file
+pub const MAX_INITIALIZER_BYTES: u64 = 1 << 20;
+
+/// Maximum number of bytes shown on hover
+const MAX_DECODED_BYTES: usize = 256;
+
+/// Decodes the bytes of a global as little-endian words and ASCII, since type descriptors
+/// and other data are often stored as byte arrays
+pub fn describe_bytes(bytes: &[u8]) -> String {
+    let shown = &bytes[..bytes.len().min(MAX_DECODED_BYTES)];
+    let words = |size: usize| {
+        shown
+            .chunks_exact(size)
+            .map(|w| {
+                let mut buf = [0u8; 8];
+                buf[..size].copy_from_slice(w);
+                u64::from_le_bytes(buf).to_string()
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let hex = shown
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let ascii = shown
+        .iter()
+        .map(|b| {
+            if b.is_ascii_graphic() || *b == b' ' {
+                *b as char
+            } else {
+                '.'
+            }
+        })
+        .collect::<String>();
+    let mut out = format!("bytes: {}\n", hex);
+    if shown.len() >= 4 {
+        out.push_str(&format!("int32: {}\n", words(4)));
+    }
+    if shown.len() >= 8 {
+        out.push_str(&format!("int64: {}\n", words(8)));
+    }
+    out.push_str(&format!("ascii: {}\n", ascii));
+    if bytes.len() > shown.len() {
+        out.push_str(&format!("... {} more bytes\n", bytes.len() - shown.len()));
+    }
+    out
+}
+
+#[cfg(test)]
+fn initializer_from_str(src: &str) -> GlobalInitializer {
+    use chumsky::Parser;
+    let tokens = crate::hir_tokenizer::tokenizer().parse(src).unwrap();
+    parse_initializer(&tokens).unwrap()
+}
+
+#[test]
+fn test_parse_initializer() {
+    let init = initializer_from_str("int8 [0,0,0,0,0,0,4,1]");
+    assert_eq!(init.declared_type.0, IrType::Int(8));
+    assert_eq!(init.type_().to_string(), "int8[8]");
+    assert_eq!(init.to_bytes().unwrap(), [0, 0, 0, 0, 0, 0, 4, 1]);
+    assert_eq!(init.check(), []);
+
+    let init = initializer_from_str("{int32, ptr, int8[4]} {7, null, \"ab\"}");
+    assert_eq!(init.check(), []);
+    assert_eq!(
+        init.to_bytes().unwrap(),
+        [7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, b'a', b'b', 0, 0, 0, 0, 0, 0]
+    );
+
+    let init = initializer_from_str("int64 [0,11,22]");
+    assert_eq!(init.to_bytes().unwrap().len(), 24);
+
+    // References to other globals can't be converted to bytes
+    let init = initializer_from_str("{ptr, int32} {ptr @data_0, int32 1}");
+    assert_eq!(init.check(), []);
+    assert_eq!(init.to_bytes(), None);
+}
+
+#[test]
+fn test_check_initializer() {
+    let messages = |src: &str| {
+        initializer_from_str(src)
+            .check()
+            .into_iter()
+            .map(|e| (src[e.span].to_string(), e.message))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        messages("int8[3] [1, 2]"),
+        [(
+            "[1, 2]".to_string(),
+            "Expected 3 elements, found 2".to_string()
+        )]
+    );
+    // The literal is not mistaken for a part of the type `int8[3][1]`
+    assert_eq!(
+        messages("int8[3] [1]"),
+        [(
+            "[1]".to_string(),
+            "Expected 3 elements, found 1".to_string()
+        )]
+    );
+    // Huge lengths neither allocate nor overflow
+    assert_eq!(
+        messages("int8[2305843009213693952] [1, 2]"),
+        [(
+            "[1, 2]".to_string(),
+            "Expected 2305843009213693952 elements, found 2".to_string()
+        )]
+    );
+    assert_eq!(
+        initializer_from_str("int8[10000000000] [1]").to_bytes(),
+        None
+    );
+    assert_eq!(
+        initializer_from_str("int64[2305843009213693952] [1]").to_bytes(),
+        None
+    );
+    assert_eq!(
+        messages("int8 [1, 300, @g]"),
+        [
+            (
+                "300".to_string(),
+                "`300` does not fit into `int8`".to_string()
+            ),
+            (
+                "@g".to_string(),
+                "Expected a value of type `int8`, found a global".to_string()
+            )
+        ]
+    );
+    assert_eq!(
+        messages("{int32, int1} {1, 1, 1}"),
+        [(
+            "{1, 1, 1}".to_string(),
+            "Expected 2 fields, found 3".to_string()
+        )]
+    );
+    assert_eq!(
+        messages("int8[2] \"abc\""),
+        [(
+            "\"abc\"".to_string(),
+            "The string has 3 bytes, but only 2 fit".to_string()
+        )]
+    );
+}
+
+#[test]
+fn test_describe_bytes() {
+    assert_eq!(
+        describe_bytes(&[0, 0, 0, 0, 0, 0, 4, 1]),
+        "bytes: 00 00 00 00 00 00 04 01\nint32: 0 17039360\nint64: 73183493944770560\nascii: ........\n"
+    );
+    assert_eq!(describe_bytes(b"dog"), "bytes: 64 6f 67\nascii: dog\n");
+}
@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    hir_parser::{BasicBlock, Statement},
+    backtrace::parse_file_location,
+    hir_parser::{BasicBlock, Instruction, Statement},
     hir_tokenizer::{Span, Spanned, Token},
 };
 use std::collections::{BTreeMap, HashMap};
@@ -176,6 +177,21 @@ impl HIRIndex {
         self.add_func_local(func_body_id, k, ud, &spanned.1, &spanned.0)
     }
 
+    /// Finds the instruction at `pos`, together with the id of the function body containing it
+    pub fn find_instruction_at_position(&self, pos: usize) -> Option<(usize, &Instruction)> {
+        self.function_bodies
+            .iter()
+            .enumerate()
+            .filter(|(_, fb)| fb.complete_range.contains(&pos))
+            .flat_map(|(id, fb)| {
+                fb.basic_blocks
+                    .iter()
+                    .flat_map(|bb| bb.instructions.iter())
+                    .map(move |i| (id, i))
+            })
+            .find(|(_, i)| i.span.contains(&pos))
+    }
+
     pub fn find_symbol_at_position(&self, pos: usize) -> Option<&SymbolOccurrence> {
         self.reverse_idx
             .iter()
@@ -188,6 +204,27 @@ impl HIRIndex {
             .filter(|s| s.span.contains(&pos))
         */
     }
+
+    /// All occurrences of the symbol at `pos`, declarations and definitions first
+    pub fn occurrences_at_position(&self, pos: usize) -> Option<Vec<(UseDefKind, Span)>> {
+        let symbol = self.find_symbol_at_position(pos)?;
+        let usedefs = self
+            .get_by_symbol_kind(
+                symbol.symbol_kind,
+                symbol.func_body_id.map(|id| &self.function_bodies[id]),
+            )
+            .get(&symbol.name)?;
+        let occurrences = [UseDefKind::Decl, UseDefKind::Def, UseDefKind::Use]
+            .into_iter()
+            .flat_map(|ud| {
+                usedefs
+                    .get_use_def_kind(ud)
+                    .iter()
+                    .map(move |span| (ud, span.clone()))
+            })
+            .collect();
+        Some(occurrences)
+    }
 }
 
 pub fn create_index(src: &str, tokens: &[Spanned<Token>], stmts: &[Statement]) -> HIRIndex {
@@ -224,18 +261,13 @@ pub fn create_index(src: &str, tokens: &[Spanned<Token>], stmts: &[Statement]) -
                 // Recognize the filenames and numbers associated with function definitions
                 if let Some(funcname) = unresolved_function_dbgrefs.get(&name.0) {
                     if let [(Token::Str(dbgstr), _)] = &def[..] {
-                        if let [filepath, linestr] = dbgstr.split(':').collect::<Vec<_>>()[..] {
-                            if let Ok(line) = linestr.parse::<u32>() {
-                                index
-                                    .functions
-                                    .get_mut(funcname)
-                                    .unwrap()
-                                    .external_defs
-                                    .push(ExternalDef {
-                                        filepath: filepath.to_string(),
-                                        line,
-                                    });
-                            }
+                        if let Some((filepath, line)) = parse_file_location(dbgstr) {
+                            index
+                                .functions
+                                .get_mut(funcname)
+                                .unwrap()
+                                .external_defs
+                                .push(ExternalDef { filepath, line });
                         }
                     }
                 }
@@ -270,22 +302,22 @@ pub fn create_index(src: &str, tokens: &[Spanned<Token>], stmts: &[Statement]) -
                 // Index the labels and local variables defined in each basic block
                 for bb in &body.basic_blocks {
                     // Index the label
-                    if bb.label.is_some() {
+                    if let Some(label) = &bb.label {
                         index.add_func_local_spanned(
                             func_body_id,
                             SymbolKind::Label,
                             UseDefKind::Def,
-                            bb.label.as_ref().unwrap(),
+                            label,
                         )
                     }
                     // Index the variables and label references of all instructions in the basic block
                     for i in &bb.instructions {
-                        if i.assignment_target.is_some() {
+                        if let Some(assignment_target) = &i.assignment_target {
                             index.add_func_local_spanned(
                                 func_body_id,
                                 SymbolKind::LocalVar,
                                 UseDefKind::Def,
-                                i.assignment_target.as_ref().unwrap(),
+                                assignment_target,
                             )
                         }
                         for bb_ref in &i.basic_block_refs {
@@ -295,8 +327,7 @@ pub fn create_index(src: &str, tokens: &[Spanned<Token>], stmts: &[Statement]) -
                                 UseDefKind::Use,
                                 bb_ref,
                             );
-                            if i.is_branching() && bb.label.is_some() {
-                                let label = bb.label.as_ref().unwrap();
+                            if let (true, Some(label)) = (i.is_branching(), &bb.label) {
                                 let func_body = &mut index.function_bodies[func_body_id];
                                 let incoming_list = func_body
                                     .incoming_bb_branches
@@ -358,6 +389,7 @@ pub fn create_index(src: &str, tokens: &[Spanned<Token>], stmts: &[Statement]) -
 }
 
 #[test]
+#[allow(clippy::single_range_in_vec_init)]
 fn test_index() {
     let src = "
         @a = \"test\"
@@ -541,3 +573,72 @@ fn test_index() {
         _ => panic!("Unexpected index contents {:?}", res.stmts),
     };
 }
+
+#[test]
+fn test_find_instruction_at_position() {
+    let src = "
+declare void @bar()
+define void @foo() {
+body_0:
+  call @bar()
+  ret
+}";
+    let res = crate::hir_parser::parse_from_str(src);
+    let idx = create_index(src, &res.tokens, &res.stmts);
+    let find = |needle: &str| {
+        idx.find_instruction_at_position(src.find(needle).unwrap())
+            .map(|(id, i)| (id, i.instruction.0.clone()))
+    };
+    assert_eq!(find("call"), Some((0, "call".to_string())));
+    assert_eq!(find("@bar()\n  ret"), Some((0, "call".to_string())));
+    assert_eq!(find("ret"), Some((0, "ret".to_string())));
+    assert_eq!(find("body_0"), None);
+    assert_eq!(find("declare"), None);
+}
+
+#[test]
+fn test_occurrences_at_position() {
+    let src = "
+define int64 @foo(int64 %n) {
+body_0:
+  int64 %a = add int64 %n, int64 1
+  br loop_1
+loop_1:
+  int64 %b = mul int64 %a, int64 %a
+  br loop_1
+}";
+    let res = crate::hir_parser::parse_from_str(src);
+    let idx = create_index(src, &res.tokens, &res.stmts);
+    let occurrences = |needle: &str| {
+        idx.occurrences_at_position(src.find(needle).unwrap())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(ud, span)| (ud, &src[span.clone()], span.start))
+            .collect::<Vec<_>>()
+    };
+    let a_def = src.find("%a =").unwrap();
+    let a_use = src.find("%a, int64 %a").unwrap();
+    assert_eq!(
+        occurrences("%a ="),
+        [
+            (UseDefKind::Def, "%a", a_def),
+            (UseDefKind::Use, "%a", a_use),
+            (UseDefKind::Use, "%a", a_use + 10),
+        ]
+    );
+    // The same occurrences are found from a use
+    assert_eq!(occurrences("%a, int64 %a"), occurrences("%a ="));
+    let label_def = src.find("loop_1:").unwrap();
+    assert_eq!(
+        occurrences("loop_1:")
+            .into_iter()
+            .map(|(ud, _, start)| (ud, start))
+            .collect::<Vec<_>>(),
+        [
+            (UseDefKind::Def, label_def),
+            (UseDefKind::Use, src.find("loop_1\n").unwrap()),
+            (UseDefKind::Use, src.rfind("loop_1").unwrap()),
+        ]
+    );
+    assert_eq!(occurrences("define"), []);
+}
@@ -0,0 +1,631 @@
+use crate::{
+    hir_operands::{parse_key_value, parse_typed_value, parse_value, split_operands},
+    hir_tokenizer::{Spanned, Token},
+    hir_types::{parse_type, IrType},
+};
+
+/// How the operands of an instruction are structured
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperandShape {
+    /// No operands, e.g. `unreachable`
+    None,
+    /// `ret` or `ret int64 %v`
+    Return,
+    /// A fixed number of typed values, e.g. `add int64 %a, int64 %b`
+    Values(usize),
+    /// `extract.d128 data128 %v, 0`
+    Extract,
+    /// `alloca int8*[2] align 8`
+    Alloca,
+    /// `load int32 ptr %p` or `gep {int32, ptr} ptr %p, int32 0, int32 1`.
+    /// The pointer can be followed by member indices.
+    MemberAccess,
+    /// `store int32 ptr %p, int32 %v`, optionally followed by member indices
+    Store,
+    /// `call @f(ptr %a)`, `calllambda {ptr 0x1234} (ptr %a)` or `callintrinsic memset(...)`
+    Call,
+    /// `br label` or `br int1 %c, then, else`
+    Branch,
+    /// `switch int32 %v, default=label, int32 0 label=label, ...`
+    Switch,
+    /// `phi [label, int32 %v], ...`
+    Phi,
+    /// `saddbr int32 %a, int32 %b, cont=label, overflow=label`, with the given number of values
+    OverflowBranch(usize),
+}
+
+/// How the type of an instruction's result is determined
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResultType {
+    /// The instruction does not produce a value
+    None,
+    /// Same type as the n-th operand
+    SameAsOperand(usize),
+    /// Always the same type, e.g. `int1` for comparisons
+    Fixed(&'static str),
+    /// Determined by the callee, the loaded type or the conversion target.
+    /// Only the assignment tells us the type.
+    Declared,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpcodeSpec {
+    pub name: &'static str,
+    pub shape: OperandShape,
+    pub result: ResultType,
+    /// Terminators end a basic block
+    pub terminator: bool,
+    pub side_effects: bool,
+    /// Markdown documentation, shown on hover
+    pub doc: &'static str,
+}
+
+const fn op(
+    name: &'static str,
+    shape: OperandShape,
+    result: ResultType,
+    terminator: bool,
+    side_effects: bool,
+    doc: &'static str,
+) -> OpcodeSpec {
+    OpcodeSpec {
+        name,
+        shape,
+        result,
+        terminator,
+        side_effects,
+        doc,
+    }
+}
+
+const fn binary(name: &'static str, doc: &'static str) -> OpcodeSpec {
+    let result = ResultType::SameAsOperand(0);
+    op(name, OperandShape::Values(2), result, false, false, doc)
+}
+
+const fn compare(name: &'static str, doc: &'static str) -> OpcodeSpec {
+    let result = ResultType::Fixed("int1");
+    op(name, OperandShape::Values(2), result, false, false, doc)
+}
+
+const fn test(name: &'static str, doc: &'static str) -> OpcodeSpec {
+    let result = ResultType::Fixed("int1");
+    op(name, OperandShape::Values(1), result, false, false, doc)
+}
+
+const fn convert(name: &'static str, doc: &'static str) -> OpcodeSpec {
+    let result = ResultType::Declared;
+    op(name, OperandShape::Values(1), result, false, false, doc)
+}
+
+const fn overflow_branch(name: &'static str, values: usize, doc: &'static str) -> OpcodeSpec {
+    let shape = OperandShape::OverflowBranch(values);
+    op(name, shape, ResultType::SameAsOperand(0), true, false, doc)
+}
+
+const fn call(name: &'static str, doc: &'static str) -> OpcodeSpec {
+    op(
+        name,
+        OperandShape::Call,
+        ResultType::Declared,
+        false,
+        true,
+        doc,
+    )
+}
+
+/// All instructions known to us. To support a new Hyper opcode, add it here.
+pub static OPCODES: &[OpcodeSpec] = &[
+    // Arithmetic
+    binary("add", "Adds two integers, wrapping around on overflow. Use `saddbr` / `uaddbr` to detect overflows."),
+    binary("sub", "Subtracts the second integer from the first one, wrapping around on overflow."),
+    binary("mul", "Multiplies two integers, wrapping around on overflow."),
+    binary("sdiv", "Divides two signed integers, rounding towards zero."),
+    binary("udiv", "Divides two unsigned integers."),
+    binary("srem", "The remainder of a signed division. The result has the sign of the dividend."),
+    binary("urem", "The remainder of an unsigned division."),
+    binary("bitand", "Bitwise `and`. For `int1` values, this is the logical `and`."),
+    binary("bitor", "Bitwise `or`. For `int1` values, this is the logical `or`."),
+    binary("bitxor", "Bitwise `xor`. For `int1` values, this is the logical `xor`."),
+    binary("shl", "Shifts the first operand left by the number of bits given by the second operand."),
+    binary("lshr", "Logical shift right: shifts in zeros."),
+    binary("ashr", "Arithmetic shift right: shifts in copies of the sign bit."),
+    // Comparisons
+    compare("eq", "Compares two values for equality."),
+    compare("neq", "Compares two values for inequality."),
+    compare("slt", "Signed \"less than\": interprets both integers as two's complement."),
+    compare("sle", "Signed \"less than or equal\"."),
+    compare("sgt", "Signed \"greater than\"."),
+    compare("sge", "Signed \"greater than or equal\"."),
+    compare("ult", "Unsigned \"less than\": interprets both integers as unsigned. E.g., `-1` is larger than `0`."),
+    compare("ule", "Unsigned \"less than or equal\"."),
+    compare("ugt", "Unsigned \"greater than\"."),
+    compare("uge", "Unsigned \"greater than or equal\"."),
+    test("iszero", "Checks whether the value is zero."),
+    test("isnotzero", "Checks whether the value is not zero. Used to convert integers to `int1`."),
+    test("isnull", "Checks whether a pointer is null."),
+    test("isnotnull", "Checks whether a pointer is not null."),
+    // Conversions
+    convert("zext", "Zero-extends an integer to the wider type of the assignment."),
+    convert("sext", "Sign-extends an integer to the wider type of the assignment."),
+    convert("trunc", "Truncates an integer to the narrower type of the assignment, dropping the upper bits."),
+    op(
+        "extract.d128",
+        OperandShape::Extract,
+        ResultType::Fixed("int64"),
+        false,
+        false,
+        "Extracts one 64-bit half of a `data128` value. Index `0` is the lower half, `1` the upper half. \
+         Strings are stored as `data128`, with the length in the lower half.",
+    ),
+    op(
+        "select",
+        OperandShape::Values(3),
+        ResultType::SameAsOperand(1),
+        false,
+        false,
+        "Returns the second operand if the condition is true, and the third operand otherwise. \
+         Both values are always evaluated.",
+    ),
+    // Memory
+    op(
+        "alloca",
+        OperandShape::Alloca,
+        ResultType::Fixed("ptr"),
+        false,
+        false,
+        "Allocates memory for a value of the given type on the stack. \
+         The memory is freed when the function returns.",
+    ),
+    op(
+        "load",
+        OperandShape::MemberAccess,
+        ResultType::Declared,
+        false,
+        false,
+        "Loads a value from memory. With additional indices, loads a member of the pointed-to \
+         struct or array, just like `load` on the result of a `gep`. \
+         `load atomic` loads atomically.",
+    ),
+    op(
+        "gep",
+        OperandShape::MemberAccess,
+        ResultType::Fixed("ptr"),
+        false,
+        false,
+        "\"Get element pointer\": computes the address of a member, without accessing memory. \
+         The first index steps over whole elements of the given type, \
+         all further indices select struct fields or array elements.",
+    ),
+    op(
+        "store",
+        OperandShape::Store,
+        ResultType::None,
+        false,
+        true,
+        "Stores a value to memory. With additional indices, stores to a member of the \
+         pointed-to struct or array.",
+    ),
+    // Calls
+    call("call", "Calls a function defined or declared in this module."),
+    call(
+        "calllambda",
+        "Calls a lambda through a function pointer. The first operand is the function pointer, \
+         usually the address of a C++ function inside Hyper.",
+    ),
+    call(
+        "callintrinsic",
+        "Calls an intrinsic, such as `memset` or `memcpy`. Intrinsics are lowered by the backend.",
+    ),
+    // Control flow
+    op(
+        "phi",
+        OperandShape::Phi,
+        ResultType::Declared,
+        false,
+        false,
+        "Selects a value depending on the basic block which branched to the current block. \
+         All phi nodes are at the beginning of a basic block.",
+    ),
+    op(
+        "br",
+        OperandShape::Branch,
+        ResultType::None,
+        true,
+        false,
+        "Branches to a basic block. The conditional form branches to the first label \
+         if the condition is true, and to the second label otherwise.",
+    ),
+    op(
+        "switch",
+        OperandShape::Switch,
+        ResultType::None,
+        true,
+        false,
+        "Branches to the label of the case matching the value, or to the `default` label \
+         if no case matches.",
+    ),
+    overflow_branch(
+        "saddbr",
+        2,
+        "Adds two signed integers. Continues at `cont` with the sum, or branches to `overflow` \
+         if the addition overflows.",
+    ),
+    overflow_branch(
+        "ssubbr",
+        2,
+        "Subtracts two signed integers. Continues at `cont` with the difference, \
+         or branches to `overflow` if the subtraction overflows.",
+    ),
+    overflow_branch(
+        "smulbr",
+        2,
+        "Multiplies two signed integers. Continues at `cont` with the product, \
+         or branches to `overflow` if the multiplication overflows.",
+    ),
+    overflow_branch(
+        "uaddbr",
+        2,
+        "Adds two unsigned integers. Continues at `cont` with the sum, or branches to `overflow` \
+         if the addition overflows.",
+    ),
+    overflow_branch(
+        "usubbr",
+        2,
+        "Subtracts two unsigned integers. Continues at `cont` with the difference, \
+         or branches to `overflow` if the subtraction underflows.",
+    ),
+    overflow_branch(
+        "umulbr",
+        2,
+        "Multiplies two unsigned integers. Continues at `cont` with the product, \
+         or branches to `overflow` if the multiplication overflows.",
+    ),
+    overflow_branch(
+        "longmuldivbr",
+        3,
+        "Computes `a * b / c` with a wide intermediate result, as used for numeric rescaling. \
+         Continues at `cont` with the result, or branches to `overflow` if the result does not fit.",
+    ),
+    op(
+        "ret",
+        OperandShape::Return,
+        ResultType::None,
+        true,
+        true,
+        "Returns from the function, optionally with a value.",
+    ),
+    op(
+        "unreachable",
+        OperandShape::None,
+        ResultType::None,
+        true,
+        true,
+        "Marks code which is never executed, e.g., after a call to a `noreturn` function.",
+    ),
+];
+
+pub fn lookup_opcode(name: &str) -> Option<&'static OpcodeSpec> {
+    OPCODES.iter().find(|spec| spec.name == name)
+}
+
+fn label(operand: &[Spanned<Token>]) -> Option<Spanned<String>> {
+    match operand {
+        [(Token::Ident(label), span)] => Some((label.clone(), span.clone())),
+        _ => None,
+    }
+}
+
+fn key_label(operand: &[Spanned<Token>], key: &str) -> Option<Spanned<String>> {
+    label(std::slice::from_ref(parse_key_value(operand, key)?))
+}
+
+fn is_typed_value(operand: &[Spanned<Token>]) -> bool {
+    matches!(parse_typed_value(operand), Some((_, [])))
+}
+
+/// Checks for `<type> ptr %p`, as used by `load`, `store` and `gep`
+fn is_pointer_access(operand: &[Spanned<Token>]) -> bool {
+    let operand = match operand {
+        [(Token::Ident(kw), _), rest @ ..] if kw == "atomic" => rest,
+        _ => operand,
+    };
+    parse_type(operand).is_some_and(|(_, rest)| is_typed_value(rest))
+}
+
+fn is_call(operand: &[Spanned<Token>]) -> bool {
+    let Some(open) = operand.iter().position(|t| t.0 == Token::Punctuation('(')) else {
+        return false;
+    };
+    let args_ok = match &operand[open + 1..] {
+        [args @ .., (Token::Punctuation(')'), _)] => {
+            args.is_empty() || split_operands(args).into_iter().all(is_typed_value)
+        }
+        _ => false,
+    };
+    let callee_ok = match &operand[..open] {
+        [(Token::GlobalName(_), _)] | [(Token::Ident(_), _)] => true,
+        [(Token::Punctuation('{'), _), callee @ .., (Token::Punctuation('}'), _)] => {
+            is_typed_value(callee)
+        }
+        _ => false,
+    };
+    args_ok && callee_ok
+}
+
+impl OpcodeSpec {
+    pub fn is_branching(&self) -> bool {
+        matches!(
+            self.shape,
+            OperandShape::Branch | OperandShape::Switch | OperandShape::OverflowBranch(_)
+        )
+    }
+
+    /// Extracts the referenced basic blocks. Returns `None` if the operands
+    /// don't have the expected shape.
+    pub fn basic_block_refs(&self, operands: &[Spanned<Token>]) -> Option<Vec<Spanned<String>>> {
+        let ops = split_operands(operands);
+        match (self.shape, ops.as_slice()) {
+            (OperandShape::Branch, [target]) => Some(vec![label(target)?]),
+            (OperandShape::Branch, [_cond, then, else_]) => {
+                Some(vec![label(then)?, label(else_)?])
+            }
+            (OperandShape::Switch, [_cond, default, cases @ ..]) => {
+                let mut refs = vec![key_label(default, "default")?];
+                for case in cases {
+                    let (_, rest) = parse_typed_value(case)?;
+                    refs.push(key_label(rest, "label")?);
+                }
+                Some(refs)
+            }
+            (OperandShape::Phi, incoming) if !incoming.is_empty() => incoming
+                .iter()
+                .map(|op| match op {
+                    [(Token::Punctuation('['), _), target, (Token::Punctuation(','), _), .., (Token::Punctuation(']'), _)] => {
+                        label(std::slice::from_ref(target))
+                    }
+                    _ => None,
+                })
+                .collect(),
+            (OperandShape::OverflowBranch(n), ops) if ops.len() == n + 2 => Some(vec![
+                key_label(ops[n], "cont")?,
+                key_label(ops[n + 1], "overflow")?,
+            ]),
+            (
+                OperandShape::Branch
+                | OperandShape::Switch
+                | OperandShape::Phi
+                | OperandShape::OverflowBranch(_),
+                _,
+            ) => None,
+            _ => Some(vec![]),
+        }
+    }
+
+    /// Checks that the operands have the expected shape
+    pub fn check_operands(&self, operands: &[Spanned<Token>]) -> bool {
+        let ops = split_operands(operands);
+        match (self.shape, ops.as_slice()) {
+            (OperandShape::None, ops) => ops.is_empty(),
+            (OperandShape::Return, []) => true,
+            (OperandShape::Return, [value]) => is_typed_value(value),
+            (OperandShape::Values(n), ops) => {
+                ops.len() == n && ops.iter().all(|o| is_typed_value(o))
+            }
+            (OperandShape::Extract, [value, idx]) => {
+                is_typed_value(value) && matches!(parse_value(idx), Some((_, [])))
+            }
+            (OperandShape::Alloca, [operand]) => parse_type(operand).is_some_and(|(_, rest)| {
+                matches!(rest, [] | [(Token::Ident(_), _), (Token::Num(_), _)])
+            }),
+            (OperandShape::MemberAccess, [pointer, indices @ ..]) => {
+                is_pointer_access(pointer) && indices.iter().all(|o| is_typed_value(o))
+            }
+            (OperandShape::Store, [pointer, value, indices @ ..]) => {
+                is_pointer_access(pointer)
+                    && is_typed_value(value)
+                    && indices.iter().all(|o| is_typed_value(o))
+            }
+            (OperandShape::Call, [operand]) => is_call(operand),
+            (OperandShape::Branch, [cond, _, _]) => {
+                is_typed_value(cond) && self.basic_block_refs(operands).is_some()
+            }
+            (OperandShape::Switch, [cond, ..]) => {
+                is_typed_value(cond) && self.basic_block_refs(operands).is_some()
+            }
+            (OperandShape::OverflowBranch(n), ops) if ops.len() == n + 2 => {
+                ops[..n].iter().all(|o| is_typed_value(o))
+                    && self.basic_block_refs(operands).is_some()
+            }
+            (OperandShape::Branch | OperandShape::Phi, _) => {
+                self.basic_block_refs(operands).is_some()
+            }
+            _ => false,
+        }
+    }
+
+    /// The type of the instruction's result, if it can be derived from the operands
+    pub fn result_type(&self, operands: &[Spanned<Token>]) -> Option<IrType> {
+        match self.result {
+            ResultType::None | ResultType::Declared => None,
+            ResultType::Fixed(name) => Some(IrType::from_name(name)),
+            ResultType::SameAsOperand(n) => {
+                let ops = split_operands(operands);
+                let (value, _) = parse_typed_value(ops.get(n)?)?;
+                Some(value.type_)
+            }
+        }
+    }
+
+    /// A human-readable description of the expected operands
+    pub fn operand_syntax(&self) -> String {
+        let syntax = match self.shape {
+            OperandShape::None => "",
+            OperandShape::Return => "[<type> <value>]",
+            OperandShape::Values(1) => "<type> <value>",
+            OperandShape::Values(2) => "<type> <value>, <type> <value>",
+            OperandShape::Values(_) => "int1 <cond>, <type> <value>, <type> <value>",
+            OperandShape::Extract => "data128 <value>, <index>",
+            OperandShape::Alloca => "<type> align <n>",
+            OperandShape::MemberAccess => "<type> ptr <pointer>[, <type> <index>...]",
+            OperandShape::Store => "<type> ptr <pointer>, <type> <value>[, <type> <index>...]",
+            OperandShape::Call => "<callee>(<type> <value>, ...)",
+            OperandShape::Branch => "<label> | int1 <cond>, <label>, <label>",
+            OperandShape::Switch => {
+                "<type> <value>, default=<label>, <type> <value> label=<label>, ..."
+            }
+            OperandShape::Phi => "[<label>, <type> <value>], ...",
+            OperandShape::OverflowBranch(2) => {
+                "<type> <value>, <type> <value>, cont=<label>, overflow=<label>"
+            }
+            OperandShape::OverflowBranch(_) => {
+                "<type> <value>, <type> <value>, <type> <value>, cont=<label>, overflow=<label>"
+            }
+        };
+        format!("{} {}", self.name, syntax).trim_end().to_string()
+    }
+}
+
+/// The reference documentation for all instructions, generated from `OPCODES`
+pub const DOCUMENTATION_URL: &str =
+    "https://github.com/salesforce-misc/hyper-ir-lsp/blob/main/docs/instructions.md";
+
+impl OpcodeSpec {
+    fn result_description(&self) -> String {
+        match self.result {
+            ResultType::None => "none".to_string(),
+            ResultType::SameAsOperand(n) => format!("same type as operand {}", n + 1),
+            ResultType::Fixed(type_) => format!("`{}`", type_),
+            ResultType::Declared => "the type declared by the assignment".to_string(),
+        }
+    }
+
+    fn branch_description(&self) -> &'static str {
+        match self.shape {
+            OperandShape::Branch | OperandShape::Switch => "yes",
+            OperandShape::OverflowBranch(_) => "yes, to `cont` or `overflow`",
+            _ => "no",
+        }
+    }
+
+    pub fn documentation_url(&self) -> String {
+        // GitHub drops the punctuation when generating anchors for headings
+        format!("{}#{}", DOCUMENTATION_URL, self.name.replace('.', ""))
+    }
+
+    fn markdown_body(&self) -> String {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        format!(
+            "```hir\n{}\n```\n\n{}\n\n* Result: {}\n* Branches: {}\n* Terminator: {}\n* Side effects: {}\n",
+            self.operand_syntax(),
+            self.doc,
+            self.result_description(),
+            self.branch_description(),
+            yes_no(self.terminator),
+            yes_no(self.side_effects),
+        )
+    }
+
+    /// The documentation shown when hovering the instruction name
+    pub fn hover_markdown(&self) -> String {
+        format!(
+            "**{}**\n\n{}\n[Documentation]({})",
+            self.name,
+            self.markdown_body(),
+            self.documentation_url()
+        )
+    }
+}
+
+/// Generates `docs/instructions.md`
+pub fn opcode_reference() -> String {
+    let mut out = "# Hyper IR instructions\n\n\
+        <!-- Generated from `src/hir_opcodes.rs`. Run `UPDATE_DOCS=1 cargo test` to update. -->\n"
+        .to_string();
+    for spec in OPCODES {
+        out.push_str(&format!("\n## `{}`\n\n{}", spec.name, spec.markdown_body()));
+    }
+    out
+}
+
+#[test]
+fn test_opcode_table() {
+    // No duplicates
+    for (i, spec) in OPCODES.iter().enumerate() {
+        assert!(
+            OPCODES[i + 1..].iter().all(|s| s.name != spec.name),
+            "duplicate opcode {}",
+            spec.name
+        );
+    }
+    // All branching instructions are terminators
+    assert!(OPCODES.iter().all(|s| !s.is_branching() || s.terminator));
+    assert!(lookup_opcode("saddbr").unwrap().is_branching());
+    assert!(!lookup_opcode("phi").unwrap().is_branching());
+    assert!(lookup_opcode("ret").unwrap().terminator);
+    assert_eq!(lookup_opcode("foo"), None);
+}
+
+#[test]
+fn test_check_operands() {
+    use chumsky::Parser;
+    let check = |src: &str| {
+        let (name, operands) = src.split_once(' ').unwrap_or((src, ""));
+        let tokens = crate::hir_tokenizer::tokenizer().parse(operands).unwrap();
+        lookup_opcode(name).unwrap().check_operands(&tokens)
+    };
+    assert!(check("add int64 %a, int64 1"));
+    assert!(!check("add int64 %a"));
+    assert!(check("load atomic int8 ptr %x"));
+    assert!(check("gep {int32, ptr} ptr %p, int32 0, int32 1"));
+    assert!(check("store int32 ptr %res_6, int32 %v19"));
+    assert!(!check("store int32 %v19"));
+    assert!(check("call @f(ptr %a, int64 96, ptr lambdaPtr @g)"));
+    assert!(check("call @f()"));
+    assert!(check("calllambda {ptr 0x1234} (ptr 0x5678, ptr %t)"));
+    assert!(check(
+        "callintrinsic memset(ptr %q, int8 0, int64 144, int32 8)"
+    ));
+    assert!(check("alloca int8*[2] align 8"));
+    assert!(check("br int1 %v13, doneIsNull_1, elseIsNull_2"));
+    assert!(!check("br int1 %v13 doneIsNull_1, elseIsNull_2"));
+    assert!(check("ret"));
+    assert!(check("extract.d128 data128 %v11, 0"));
+}
+
+#[test]
+fn test_hover_markdown() {
+    assert_eq!(
+        lookup_opcode("ult").unwrap().hover_markdown(),
+        "**ult**
+
+```hir
+ult <type> <value>, <type> <value>
+```
+
+Unsigned \"less than\": interprets both integers as unsigned. E.g., `-1` is larger than `0`.
+
+* Result: `int1`
+* Branches: no
+* Terminator: no
+* Side effects: no
+
+[Documentation](https://github.com/salesforce-misc/hyper-ir-lsp/blob/main/docs/instructions.md#ult)"
+    );
+    assert!(lookup_opcode("extract.d128")
+        .unwrap()
+        .documentation_url()
+        .ends_with("#extractd128"));
+}
+
+/// The checked-in documentation must match the opcode table
+#[test]
+fn docs_are_up_to_date() {
+    let path = "docs/instructions.md";
+    if std::env::var("UPDATE_DOCS").is_ok() {
+        std::fs::write(path, opcode_reference()).unwrap();
+    }
+    // Git might check out the docs with CRLF line endings on Windows
+    let docs = std::fs::read_to_string(path).unwrap().replace("\r\n", "\n");
+    assert_eq!(docs, opcode_reference());
+}
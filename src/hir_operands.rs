@@ -0,0 +1,270 @@
+use crate::{
+    hir_parser::{function_signatures, Statement},
+    hir_tokenizer::{Span, Spanned, Token},
+    hir_types::{parse_type, IrType},
+};
+
+/// A value used as an operand
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Local(String),
+    Global(String),
+    Int(u128),
+    /// We keep the textual representation, such that `Value` stays `Eq`
+    Float(String),
+    Bool(bool),
+    Null,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypedValue {
+    pub type_: IrType,
+    pub value: Spanned<Value>,
+}
+
+/// Splits the operands of an instruction at all commas which are not nested
+/// within brackets, e.g. `[bb_1, int32 0], int32 %v` is split into two operands.
+pub fn split_operands(tokens: &[Spanned<Token>]) -> Vec<&[Spanned<Token>]> {
+    let mut operands = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, (token, _)) in tokens.iter().enumerate() {
+        match token {
+            Token::Punctuation('(' | '[' | '{' | '<') => depth += 1,
+            Token::Punctuation(')' | ']' | '}' | '>') => depth -= 1,
+            Token::Punctuation(',') if depth == 0 => {
+                operands.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < tokens.len() {
+        operands.push(&tokens[start..]);
+    }
+    operands
+}
+
+fn span_between(first: &Span, last: &Span) -> Span {
+    first.start..last.end
+}
+
+/// Parses a value from the beginning of a token soup, returning the remaining tokens
+pub fn parse_value(tokens: &[Spanned<Token>]) -> Option<(Spanned<Value>, &[Spanned<Token>])> {
+    match tokens {
+        [(Token::Num(a), s1), (Token::Punctuation('.'), _), (Token::Num(b), s2), rest @ ..] => {
+            Some((
+                (Value::Float(format!("{}.{}", a, b)), span_between(s1, s2)),
+                rest,
+            ))
+        }
+        [(Token::Num(n), s), rest @ ..] => Some(((Value::Int(n.parse().ok()?), s.clone()), rest)),
+        [(Token::HexNum(n), s), rest @ ..] => Some((
+            (Value::Int(u128::from_str_radix(n, 16).ok()?), s.clone()),
+            rest,
+        )),
+        [(Token::LocalName(n), s), rest @ ..] => Some(((Value::Local(n.clone()), s.clone()), rest)),
+        [(Token::GlobalName(n), s), rest @ ..] => {
+            Some(((Value::Global(n.clone()), s.clone()), rest))
+        }
+        // Function pointers are written as `lambdaPtr @func`
+        [(Token::Ident(kw), s1), (Token::GlobalName(n), s2), rest @ ..] if kw == "lambdaPtr" => {
+            Some(((Value::Global(n.clone()), span_between(s1, s2)), rest))
+        }
+        [(Token::Ident(kw), s), rest @ ..] => {
+            let value = match kw.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "null" | "nullptr" => Value::Null,
+                _ => return None,
+            };
+            Some(((value, s.clone()), rest))
+        }
+        _ => None,
+    }
+}
+
+/// Parses a value with its type, e.g. `int32 %v1`, returning the remaining tokens
+pub fn parse_typed_value(tokens: &[Spanned<Token>]) -> Option<(TypedValue, &[Spanned<Token>])> {
+    let (type_, rest) = parse_type(tokens)?;
+    let (value, rest) = parse_value(rest)?;
+    Some((TypedValue { type_, value }, rest))
+}
+
+/// Parses a `key=value` operand, such as `cont=add_cont_3`
+pub fn parse_key_value<'a>(tokens: &'a [Spanned<Token>], key: &str) -> Option<&'a Spanned<Token>> {
+    match tokens {
+        [(Token::Ident(k), _), (Token::Punctuation('='), _), value] if k == key => Some(value),
+        _ => None,
+    }
+}
+
+type Operand<'a> = &'a [Spanned<Token>];
+
+/// Splits `callee(arg, arg)` into the callee tokens and the arguments
+pub fn split_call(operands: Operand) -> Option<(Operand, Vec<Operand>)> {
+    let open = operands
+        .iter()
+        .rposition(|t| t.0 == Token::Punctuation('('))?;
+    let [args @ .., (Token::Punctuation(')'), _)] = &operands[open + 1..] else {
+        return None;
+    };
+    Some((&operands[..open], split_operands(args)))
+}
+
+/// A call argument and the parameter it is passed as
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArgumentParameter {
+    /// The span of the argument
+    pub span: Span,
+    /// The parameter name without `%`, or `argN` for the unnamed parameters of declarations
+    pub label: String,
+    /// The span of the parameter name, or of its type for unnamed parameters
+    pub parameter_span: Span,
+    /// E.g. `ptr %queryState_0 of @f`
+    pub description: String,
+}
+
+/// The parameters the arguments of all `call`s are passed as. Arguments named like their
+/// parameter are skipped, e.g. passing `%queryState_0` as `%queryState_0`.
+pub fn argument_parameters(stmts: &[Statement]) -> Vec<ArgumentParameter> {
+    let signatures = function_signatures(stmts);
+    stmts
+        .iter()
+        .filter_map(|s| match s {
+            Statement::FuncDef { body, .. } => Some(body),
+            _ => None,
+        })
+        .flat_map(|body| &body.basic_blocks)
+        .flat_map(|bb| &bb.instructions)
+        .filter(|instr| instr.instruction.0 == "call")
+        .filter_map(|instr| {
+            let (callee, args) = split_call(&instr.operands)?;
+            let [(Token::GlobalName(callee), _)] = callee else {
+                return None;
+            };
+            let signature = signatures.get(callee.as_str())?;
+            Some(
+                args.into_iter()
+                    .zip(&signature.args)
+                    .enumerate()
+                    .filter_map(|(idx, (arg, param))| {
+                        let value = parse_type(arg).and_then(|(_, v)| v.first());
+                        if value.is_some_and(|v| v.0 == Token::LocalName(param.name.0.clone())) {
+                            return None;
+                        }
+                        let (label, parameter_span) = if param.is_named() {
+                            (
+                                param.name.0.trim_start_matches('%').to_string(),
+                                param.name.1.clone(),
+                            )
+                        } else {
+                            (format!("arg{}", idx), param.type_.1.clone())
+                        };
+                        Some(ArgumentParameter {
+                            span: arg.first()?.1.start..arg.last()?.1.end,
+                            label,
+                            parameter_span,
+                            description: format!(
+                                "{} {} of {}",
+                                param.type_.0, param.name.0, callee
+                            ),
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .flatten()
+        .collect()
+}
+
+#[test]
+fn test_split_operands() {
+    use chumsky::Parser;
+    let tokens = crate::hir_tokenizer::tokenizer()
+        .parse("[body_0, int32 0], [loop_3, int32 %v15], cont=bb_1")
+        .unwrap();
+    let operands = split_operands(&tokens);
+    assert_eq!(operands.len(), 3);
+    assert_eq!(operands[0].len(), 6);
+    assert_eq!(
+        parse_key_value(operands[2], "cont"),
+        Some(&(Token::Ident("bb_1".to_string()), 46..50))
+    );
+    assert_eq!(parse_key_value(operands[2], "overflow"), None);
+}
+
+#[test]
+fn test_parse_typed_value() {
+    use chumsky::Parser;
+    let parse = |src: &str| {
+        let tokens = crate::hir_tokenizer::tokenizer().parse(src).unwrap();
+        let (value, rest) = parse_typed_value(&tokens)?;
+        assert!(rest.is_empty());
+        Some((value.type_, value.value.0))
+    };
+    assert_eq!(
+        parse("int32 %v1"),
+        Some((IrType::Int(32), Value::Local("%v1".to_string())))
+    );
+    assert_eq!(parse("int64 0x10"), Some((IrType::Int(64), Value::Int(16))));
+    assert_eq!(
+        parse("float64 1.5"),
+        Some((IrType::Float(64), Value::Float("1.5".to_string())))
+    );
+    assert_eq!(
+        parse("int1 true"),
+        Some((IrType::Int(1), Value::Bool(true)))
+    );
+    assert_eq!(
+        parse("ptr lambdaPtr @foo"),
+        Some((IrType::Ptr, Value::Global("@foo".to_string())))
+    );
+    assert_eq!(parse("int32"), None);
+}
+
+#[test]
+fn test_argument_parameters() {
+    let src = "
+declare void @external(ptr %, int64 %)
+define int64 @helper(int64 %x, int64 %y) {
+body_0:
+  call @external(ptr %x, int64 %y)
+  ret int64 %x
+}
+define void @f(int64 %y) {
+body_0:
+  int64 %v = call @helper(int64 1, int64 %y)
+  ret
+}";
+    let res = crate::hir_parser::parse_from_str(src);
+    let params = argument_parameters(&res.stmts)
+        .into_iter()
+        .map(|p| {
+            (
+                src[p.span].to_string(),
+                p.label,
+                src[p.parameter_span].to_string(),
+                p.description,
+            )
+        })
+        .collect::<Vec<_>>();
+    let param = |arg: &str, label: &str, span: &str, description: &str| {
+        (
+            arg.to_string(),
+            label.to_string(),
+            span.to_string(),
+            description.to_string(),
+        )
+    };
+    assert_eq!(
+        params,
+        [
+            // The parameters of declarations are unnamed
+            param("ptr %x", "arg0", "ptr", "ptr % of @external"),
+            param("int64 %y", "arg1", "int64", "int64 % of @external"),
+            param("int64 1", "x", "%x", "int64 %x of @helper"),
+            // `%y` is passed as `%y`, which needs no hint
+        ]
+    );
+}
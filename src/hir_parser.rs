@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+
+use crate::hir_opcodes::{lookup_opcode, OpcodeSpec};
 use crate::hir_tokenizer::{tokenizer, Span, Spanned, Token};
 use chumsky::{prelude::Simple, Parser};
 use chumsky::{prelude::*, Stream};
@@ -59,19 +62,51 @@ pub struct BasicBlock {
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Instruction {
+    pub assignment_type: Option<Spanned<String>>,
     pub assignment_target: Option<Spanned<String>>,
     pub instruction: Spanned<String>,
+    pub operands: Vec<Spanned<Token>>,
     pub basic_block_refs: Vec<Spanned<String>>,
     pub dbg_ref: Option<Spanned<String>>,
     pub span: Span,
 }
 
 impl Instruction {
+    pub fn opcode(&self) -> Option<&'static OpcodeSpec> {
+        lookup_opcode(&self.instruction.0)
+    }
+
     pub fn is_branching(&self) -> bool {
-        self.instruction.0.ends_with("br") || self.instruction.0 == "switch"
+        self.opcode().is_some_and(|spec| spec.is_branching())
+    }
+}
+
+impl FuncArg {
+    /// Declarations of external functions don't name their arguments, e.g. `ptr %`
+    pub fn is_named(&self) -> bool {
+        self.name.0 != "%"
+    }
+}
+
+/// The signatures of all functions, by name. Definitions take precedence over declarations.
+pub fn function_signatures(stmts: &[Statement]) -> HashMap<&str, &FuncSignature> {
+    let mut signatures = HashMap::<&str, &FuncSignature>::new();
+    for stmt in stmts {
+        match stmt {
+            Statement::FuncDef { signature, .. } => {
+                signatures.insert(&signature.name.0, signature);
+            }
+            Statement::FuncDecl { signature, .. } => {
+                signatures.entry(&signature.name.0).or_insert(signature);
+            }
+            _ => {}
+        }
     }
+    signatures
 }
 
+// `Simple<Token>` is large, but that's how chumsky reports errors
+#[allow(clippy::result_large_err)]
 pub fn parser() -> impl Parser<Token, Vec<Statement>, Error = Simple<Token>> + Clone {
     let eol = just(Token::Newline).or(end().to(Token::Newline));
     let func_modifier = filter_map(|span, token| match token {
@@ -152,173 +187,49 @@ pub fn parser() -> impl Parser<Token, Vec<Statement>, Error = Simple<Token>> + C
             dbgref,
         });
 
-    // An unconditional branch
-    let br_instruction = just(Token::Ident("br".to_string()))
-        .map_with_span(|_, span| ("br".to_string(), span))
-        .then(ident)
-        .then(dbg_ref.or_not())
-        .then_ignore(just(Token::Newline).rewind())
-        .map_with_span(|((instruction, target), dbg_ref), span| Instruction {
-            assignment_target: None,
-            instruction,
-            basic_block_refs: vec![target],
-            dbg_ref,
-            span,
+    // Instruction names can contain dots, e.g. `extract.d128`
+    let opcode = ident
+        .then(just(Token::Punctuation('.')).ignore_then(ident).repeated())
+        .map(|(first, suffixes)| {
+            suffixes.into_iter().fold(
+                first,
+                |(name, span): Spanned<String>, (suffix, suffix_span)| {
+                    (format!("{}.{}", name, suffix), span.start..suffix_span.end)
+                },
+            )
         });
 
-    // A conditional branch
-    let condbr_instruction = just(Token::Ident("br".to_string()))
-        .map_with_span(|_, span| ("br".to_string(), span))
-        .then_ignore(just(Token::Type("int1".to_string())))
-        .then_ignore(any())
-        .then_ignore(just(Token::Punctuation(',')))
-        .then(ident)
-        .then_ignore(just(Token::Punctuation(',')))
-        .then(ident)
-        .then(dbg_ref.or_not())
-        .then_ignore(just(Token::Newline).rewind())
-        .map_with_span(
-            |(((instruction, then_target), else_target), dbg_ref), span| Instruction {
-                assignment_target: None,
-                instruction,
-                basic_block_refs: vec![then_target, else_target],
-                dbg_ref,
-                span,
-            },
-        );
-
-    // Arithmetic instructions with branching overflow checks
-    // int32 %v17 = saddbr int32 %v9, int32 %v11, cont=add_cont_3, overflow=overflow_4    !30
-    let overflowbr_instruction = ident
-        .validate(|x, span, emit| {
-            if !x.0.ends_with("br") {
-                emit(Simple::custom(
-                    span,
-                    "Instruction is not a overflow-branch instruction.",
-                ))
-            }
-            x
-        })
-        .then_ignore(
-            none_of([Token::Punctuation(','), Token::Newline])
-                .or(just(Token::Punctuation(','))
-                    .then_ignore(none_of(Token::Ident("cont".to_string()))))
-                .repeated(),
-        )
-        .then_ignore(just(Token::Punctuation(',')))
-        .then_ignore(just(Token::Ident("cont".to_string())))
-        .then_ignore(just(Token::Punctuation('=')))
-        .then(ident)
-        .then_ignore(just(Token::Punctuation(',')))
-        .then_ignore(just(Token::Ident("overflow".to_string())))
-        .then_ignore(just(Token::Punctuation('=')))
-        .then(ident)
-        .then(dbg_ref.or_not())
-        .then_ignore(just(Token::Newline).rewind())
-        .map_with_span(
-            |(((instruction, target1), target2), dbg_ref), span| Instruction {
-                assignment_target: None,
-                instruction,
-                basic_block_refs: vec![target1, target2],
-                dbg_ref,
-                span,
-            },
-        );
-
-    // Switch instruction
-    // switch int32 %v10, default=unreachable_5, int32 0 label=bb_0, int32 1 label=bb_1, int32 2 label=bb_2
-    let switch_instruction = just(Token::Ident("switch".to_string()))
-        .map_with_span(|_, span| ("switch".to_string(), span))
-        .then_ignore(
-            none_of([Token::Punctuation(','), Token::Newline])
-                .or(just(Token::Punctuation(','))
-                    .then_ignore(none_of(Token::Ident("default".to_string()))))
-                .repeated(),
-        )
-        .then_ignore(just(Token::Punctuation(',')))
-        .then_ignore(just(Token::Ident("default".to_string())))
+    // A single instruction with a potential assignment target. Only the instruction name and the
+    // debug reference are parsed here, the operands are kept as "token soup". The basic block
+    // references are extracted from the operands based on the opcode table.
+    let instruction = type_
+        .then(local_name)
         .then_ignore(just(Token::Punctuation('=')))
-        .then(ident)
-        .then_ignore(just(Token::Punctuation(',')))
-        .then(
-            type_
-                .then(any())
-                .then(just(Token::Ident("label".to_string())))
-                .then(just(Token::Punctuation('=')))
-                .ignore_then(ident)
-                .separated_by(just(Token::Punctuation(','))),
-        )
-        .then(dbg_ref.or_not())
-        .then_ignore(just(Token::Newline).rewind())
-        .map_with_span(
-            |(((instruction, default_target), mut targets), dbg_ref), span| {
-                targets.insert(0, default_target);
-                Instruction {
-                    assignment_target: None,
-                    instruction,
-                    basic_block_refs: targets,
-                    dbg_ref,
-                    span,
-                }
-            },
-        );
-
-    // Phi instructions
-    let phi_instruction = just(Token::Ident("phi".to_string()))
-        .map_with_span(|_, span| ("phi".to_string(), span))
+        .or_not()
+        .then(opcode.then_ignore(none_of(Token::Punctuation(':')).rewind()))
         .then(
-            ident
-                .then_ignore(just(Token::Punctuation(',')))
-                .then_ignore(none_of([Token::Punctuation(']'), Token::Newline]).repeated())
-                .delimited_by(just(Token::Punctuation('[')), just(Token::Punctuation(']')))
-                .separated_by(just(Token::Punctuation(','))),
-        )
-        .then(dbg_ref.or_not())
-        .then_ignore(just(Token::Newline).rewind())
-        .map_with_span(|((instruction, targets), dbg_ref), span| Instruction {
-            assignment_target: None,
-            instruction,
-            basic_block_refs: targets,
-            dbg_ref,
-            span,
-        });
-
-    // Any (unknown) instruction
-    let any_instruction = ident
-        .then_ignore(none_of(Token::Punctuation(':')).rewind())
-        .then_ignore(
             (just(Token::Newline).ignored())
                 .or(dbg_ref.ignored())
                 .not()
+                .map_with_span(|tok, span| (tok, span))
                 .repeated(),
         )
         .then(dbg_ref.or_not())
         .then_ignore(just(Token::Newline).rewind())
-        .map_with_span(|(instruction, dbg_ref), span| Instruction {
-            assignment_target: None,
-            instruction,
-            basic_block_refs: vec![],
-            dbg_ref,
-            span,
-        });
-
-    // A single instruction with a potential assignment target
-    let instruction = type_
-        .ignore_then(local_name)
-        .then_ignore(just(Token::Punctuation('=')))
-        .or_not()
-        .then(
-            br_instruction
-                .or(condbr_instruction)
-                .or(overflowbr_instruction)
-                .or(switch_instruction)
-                .or(phi_instruction)
-                .or(any_instruction),
-        )
-        .map_with_span(|(target, instruction), span| Instruction {
-            assignment_target: target,
-            span,
-            ..instruction
+        .map_with_span(|(((target, instruction), operands), dbg_ref), span| {
+            let (assignment_type, assignment_target) = target.unzip();
+            let basic_block_refs = lookup_opcode(&instruction.0)
+                .and_then(|spec| spec.basic_block_refs(&operands))
+                .unwrap_or_default();
+            Instruction {
+                assignment_type,
+                assignment_target,
+                instruction,
+                operands,
+                basic_block_refs,
+                dbg_ref,
+                span,
+            }
         });
 
     // A basic block
@@ -442,7 +353,10 @@ pub fn parse_from_str(src: &str) -> ParserResult {
         parser().parse_recovery(Stream::from_iter(
             strlen..strlen + 1,
             // TODO: can we somehow avoid this copy?
-            tokens.clone().into_iter().filter(|t| t.0 != Token::Comment),
+            tokens
+                .clone()
+                .into_iter()
+                .filter(|t| !matches!(t.0, Token::Comment(_))),
         ))
     } else {
         (None, Vec::new())
@@ -462,6 +376,17 @@ pub fn parse_from_str(src: &str) -> ParserResult {
     }
 }
 
+/// The signature and body of the first statement, which must be a function definition
+#[cfg(test)]
+pub fn first_function(res: &ParserResult) -> (&FuncSignature, &FuncBody) {
+    match res.stmts.first() {
+        Some(Statement::FuncDef {
+            signature, body, ..
+        }) => (signature, body),
+        _ => panic!("Unexpected parse {:?}", res.stmts),
+    }
+}
+
 #[test]
 fn test_parse_globals() {
     let res = parse_from_str("@var1 = [0,0,4,0]");
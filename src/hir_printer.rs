@@ -0,0 +1,382 @@
+use crate::{
+    hir_cst::{Cst, CstNodeKind, Trivia, TriviaKind},
+    hir_parser::{BasicBlock, FuncSignature, Instruction, Statement},
+    hir_tokenizer::{Spanned, Token},
+};
+
+/// Instructions are indented by two spaces, labels are not indented
+const INDENT: &str = "  ";
+
+/// Like Hyper, we pad instructions up to this column before appending the debug reference.
+/// Longer instructions are separated from their debug reference by `DBG_REF_SEPARATOR`.
+const DBG_REF_COLUMN: usize = 78;
+const DBG_REF_SEPARATOR: &str = "    ";
+
+/// A single printed line, together with the source offset of the construct it was printed from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrintedLine {
+    pub origin: usize,
+    pub text: String,
+}
+
+pub fn print_token(token: &Token) -> String {
+    match token {
+        Token::Comment(s) => s.clone(),
+        Token::Num(s) => s.clone(),
+        Token::HexNum(s) => format!("0x{}", s),
+        Token::Str(s) => format!("\"{}\"", s),
+        Token::LocalName(s) => s.clone(),
+        Token::GlobalName(s) => s.clone(),
+        Token::DebugRef(s) => s.clone(),
+        Token::Type(s) => s.clone(),
+        Token::Ident(s) => s.clone(),
+        Token::FuncModifier(s) => s.clone(),
+        Token::Punctuation(c) => c.to_string(),
+        Token::Newline => "\n".to_string(),
+        Token::Declare => "declare".to_string(),
+        Token::Define => "define".to_string(),
+    }
+}
+
+/// Appends a "token soup" to `out`.
+///
+/// We don't know the grammar of the token soup, so we can't decide where whitespace belongs.
+/// Instead, we keep adjacent tokens adjacent and collapse all other whitespace into a single
+/// space. `prev_end` is the end offset of the token printed right before the soup.
+fn push_token_soup(out: &mut String, mut prev_end: Option<usize>, tokens: &[Spanned<Token>]) {
+    for (token, span) in tokens {
+        if prev_end.map_or(!out.is_empty(), |end| end != span.start) {
+            out.push(' ');
+        }
+        out.push_str(&print_token(token));
+        prev_end = Some(span.end);
+    }
+}
+
+pub fn print_signature(signature: &FuncSignature) -> String {
+    let mut out = String::new();
+    for modifier in &signature.modifiers {
+        out.push_str(&modifier.0);
+        out.push(' ');
+    }
+    let args = signature
+        .args
+        .iter()
+        .map(|a| format!("{} {}", a.type_.0, a.name.0))
+        .collect::<Vec<_>>()
+        .join(", ");
+    out.push_str(&format!(
+        "{} {}({})",
+        signature.ret_type.0, signature.name.0, args
+    ));
+    out
+}
+
+/// Prints an instruction, including its indentation and the aligned debug reference
+pub fn print_instruction(instruction: &Instruction) -> String {
+    let mut out = INDENT.to_string();
+    if let (Some(type_), Some(target)) =
+        (&instruction.assignment_type, &instruction.assignment_target)
+    {
+        out.push_str(&format!("{} {} = ", type_.0, target.0));
+    }
+    out.push_str(&instruction.instruction.0);
+    push_token_soup(
+        &mut out,
+        Some(instruction.instruction.1.end),
+        &instruction.operands,
+    );
+    if let Some(dbg_ref) = &instruction.dbg_ref {
+        if out.chars().count() < DBG_REF_COLUMN {
+            out = format!("{:<width$}", out, width = DBG_REF_COLUMN);
+        }
+        out.push_str(DBG_REF_SEPARATOR);
+        out.push_str(&dbg_ref.0);
+    }
+    out
+}
+
+fn print_basic_block(bb: &BasicBlock, lines: &mut Vec<PrintedLine>) {
+    if let Some(label) = &bb.label {
+        lines.push(PrintedLine {
+            origin: label.1.start,
+            text: format!("{}:", label.0),
+        });
+    }
+    lines.extend(bb.instructions.iter().map(|i| PrintedLine {
+        origin: i.span.start,
+        text: print_instruction(i),
+    }));
+}
+
+/// Prints a single statement. Most statements fit on a single line, only function
+/// definitions span multiple lines.
+pub fn print_statement(stmt: &Statement) -> Vec<PrintedLine> {
+    match stmt {
+        Statement::GlobalVar { name, def } | Statement::DbgAnnotation { name, def } => {
+            let mut text = format!("{} =", name.0);
+            push_token_soup(&mut text, None, def);
+            vec![PrintedLine {
+                origin: name.1.start,
+                text,
+            }]
+        }
+        Statement::FuncDecl {
+            signature,
+            addr,
+            dbgref,
+        } => {
+            let mut text = format!("declare {}", print_signature(signature));
+            if let Some(addr) = addr {
+                text.push_str(&format!(" = 0x{}", addr.0));
+            }
+            if let Some(dbgref) = dbgref {
+                text.push_str(&format!(" {}", dbgref.0));
+            }
+            let origin = signature
+                .modifiers
+                .first()
+                .map_or(signature.ret_type.1.start, |m| m.1.start);
+            vec![PrintedLine { origin, text }]
+        }
+        Statement::FuncDef {
+            define_kw,
+            signature,
+            body,
+        } => {
+            let mut lines = vec![PrintedLine {
+                origin: define_kw.start,
+                text: format!("define {} {{", print_signature(signature)),
+            }];
+            for bb in &body.basic_blocks {
+                print_basic_block(bb, &mut lines);
+            }
+            lines.push(PrintedLine {
+                origin: body.closing_bracket.start,
+                text: "}".to_string(),
+            });
+            lines
+        }
+        Statement::FuncDependencies {
+            dependent,
+            dependencies,
+        } => {
+            let dependencies = dependencies
+                .iter()
+                .map(|d| d.0.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            vec![PrintedLine {
+                origin: dependent.1.start,
+                text: format!("{} depends on {}", dependent.0, dependencies),
+            }]
+        }
+    }
+}
+
+/// Prints a complete module. Statements of the same kind are grouped together, and each
+/// function definition is separated from its neighbors by an empty line.
+pub fn print_statements(stmts: &[Statement]) -> String {
+    let mut out = String::new();
+    let mut prev: Option<&Statement> = None;
+    for stmt in stmts {
+        if let Some(prev) = prev {
+            let is_def = |s: &Statement| matches!(s, Statement::FuncDef { .. });
+            if std::mem::discriminant(prev) != std::mem::discriminant(stmt)
+                || is_def(prev)
+                || is_def(stmt)
+            {
+                out.push('\n');
+            }
+        }
+        for line in print_statement(stmt) {
+            out.push_str(&line.text);
+            out.push('\n');
+        }
+        prev = Some(stmt);
+    }
+    out
+}
+
+fn push_leading_trivia(out: &mut String, trivia: &[Trivia], indent: &str) {
+    for t in trivia {
+        match t.kind {
+            TriviaKind::Whitespace => {}
+            TriviaKind::Newline => out.push_str(&t.text),
+            TriviaKind::Comment => {
+                out.push_str(indent);
+                out.push_str(&t.text);
+            }
+            TriviaKind::Skipped => out.push_str(&t.text),
+        }
+    }
+}
+
+fn push_trailing_trivia(out: &mut String, trivia: &[Trivia]) {
+    let mut terminated = false;
+    for t in trivia {
+        match t.kind {
+            TriviaKind::Whitespace => {}
+            TriviaKind::Newline => {
+                out.push_str(&t.text);
+                terminated = true;
+            }
+            TriviaKind::Comment | TriviaKind::Skipped => {
+                out.push(' ');
+                out.push_str(&t.text);
+            }
+        }
+    }
+    if !terminated {
+        out.push('\n');
+    }
+}
+
+/// Prints a complete module, preserving comments and empty lines.
+///
+/// Lines which we failed to parse are kept as is.
+pub fn print_cst(cst: &Cst, stmts: &[Statement]) -> String {
+    let mut out = String::new();
+    let mut stmts = stmts.iter();
+    for node in &cst.nodes {
+        if node.kind == CstNodeKind::Error {
+            out.push_str(&cst.node_source(node));
+            continue;
+        }
+        let Some(stmt) = stmts.next() else {
+            break;
+        };
+        for line in print_statement(stmt) {
+            let tokens = cst.line_tokens(line.origin);
+            if tokens.is_empty() {
+                continue;
+            }
+            let indent = &line.text[..line.text.len() - line.text.trim_start().len()];
+            push_leading_trivia(&mut out, &cst.tokens[tokens.start].leading_trivia, indent);
+            out.push_str(&line.text);
+            push_trailing_trivia(&mut out, &cst.tokens[tokens.end - 1].trailing_trivia);
+        }
+    }
+    push_leading_trivia(&mut out, &cst.eof_trivia, "");
+    out
+}
+
+#[test]
+fn test_print_instruction() {
+    let res = crate::hir_parser::parse_from_str(
+        "
+    define void @foo(ptr %arg) {
+    body_0:
+        int32 %v1 =  load   int32 ptr %arg  !161
+        int64 %v10 = phi [body_0, int64 0], [loop_3, int64 %v15]
+        switch int32 %v10, default=bb_0, int32 0 label=bb_0
+        call @hyper::QueryResources::updateQueryMetricsAndHandleInterrupt(ptr %queryResources_7) !2397
+        ret
+    }",
+    );
+    assert_eq!(res.errors, []);
+    let lines = print_statement(&res.stmts[0])
+        .into_iter()
+        .map(|l| l.text)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        [
+            "define void @foo(ptr %arg) {",
+            "body_0:",
+            "  int32 %v1 = load int32 ptr %arg                                                 !161",
+            "  int64 %v10 = phi [body_0, int64 0], [loop_3, int64 %v15]",
+            "  switch int32 %v10, default=bb_0, int32 0 label=bb_0",
+            "  call @hyper::QueryResources::updateQueryMetricsAndHandleInterrupt(ptr %queryResources_7)    !2397",
+            "  ret",
+            "}",
+        ]
+    );
+}
+
+#[test]
+fn test_print_statements() {
+    let res = crate::hir_parser::parse_from_str(
+        "
+    @a =   int8 [0,0,4,1]
+    declare exported  int32 @foo::bar(ptr %,int32 %baz) = 0x1234 !f1
+    @foo depends on @bar,@baz
+    !f1 = \"./test.cpp:12\"
+    !21 = {\"some\": \"data\"}",
+    );
+    assert_eq!(res.errors, []);
+    assert_eq!(
+        print_statements(&res.stmts),
+        "\
+@a = int8 [0,0,4,1]
+
+declare exported int32 @foo::bar(ptr %, int32 %baz) = 0x1234 !f1
+
+@foo depends on @bar, @baz
+
+!f1 = \"./test.cpp:12\"
+!21 = {\"some\": \"data\"}
+"
+    );
+}
+
+#[test]
+fn test_print_cst() {
+    let src = "\
+# Hyper IR for a test
+declare   void @bar()  = 0x12
+
+define void @foo(ptr %arg) { # trailing comment
+body_0:
+    # comment on separate line
+      ret   !1 # return
+  broken line
+}";
+    let res = crate::hir_parser::parse_from_str(src);
+    let cst = crate::hir_cst::build_cst(src, &res.tokens, &res.stmts);
+    assert_eq!(
+        print_cst(&cst, &res.stmts),
+        "\
+# Hyper IR for a test
+declare void @bar() = 0x12
+
+define void @foo(ptr %arg) { # trailing comment
+body_0:
+  # comment on separate line
+  ret                                                                             !1 # return
+  broken line
+}
+"
+    );
+}
+
+/// Hyper's own output is already formatted. Printing a parsed module should reproduce it.
+#[cfg(test)]
+fn check_roundtrip(path: &str) {
+    let src = std::fs::read_to_string(path).unwrap();
+    let res = crate::hir_parser::parse_from_str(&src);
+    assert_eq!(res.errors, []);
+    let cst = crate::hir_cst::build_cst(&src, &res.tokens, &res.stmts);
+    assert_eq!(print_cst(&cst, &res.stmts), src);
+    // Without the comments and empty lines, we still get the same instructions
+    let printed = print_statements(&res.stmts);
+    let relevant_lines = |s: &str| {
+        s.lines()
+            .filter(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+            .map(|l| l.to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(relevant_lines(&printed), relevant_lines(&src));
+    // Printing is idempotent
+    let reparsed = crate::hir_parser::parse_from_str(&printed);
+    assert_eq!(reparsed.errors, []);
+    assert_eq!(print_statements(&reparsed.stmts), printed);
+}
+
+#[test]
+fn roundtrips_examples() {
+    check_roundtrip("examples/fcf.hir");
+    check_roundtrip("examples/insert.hir");
+    check_roundtrip("examples/query.hir");
+    check_roundtrip("examples/relation.hir");
+}
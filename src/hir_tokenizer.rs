@@ -8,7 +8,7 @@ pub type Spanned<T> = (T, Span);
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Token {
-    Comment,              // Started by `#`
+    Comment(String),      // Started by `#`
     Num(String),          // Numbers
     HexNum(String),       // Hexadecimal numbers
     Str(String),          // Strings
@@ -27,7 +27,7 @@ pub enum Token {
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Token::Comment => write!(f, "comment"),
+            Token::Comment(_) => write!(f, "comment"),
             Token::DebugRef(x) => write!(f, "!{}", x),
             Token::Num(s) => write!(f, "{}", s),
             Token::HexNum(s) => write!(f, "{}", s),
@@ -53,8 +53,9 @@ pub fn tokenizer() -> impl Parser<char, Vec<Spanned<Token>>, Error = Simple<char
 
     // A comment parser
     let comment = just('#')
-        .chain(take_until(just('\n').or(end().to('\n')).rewind()))
-        .to(Token::Comment);
+        .chain(filter(|c: &char| *c != '\n' && *c != '\r').repeated())
+        .collect::<String>()
+        .map(Token::Comment);
 
     // A parser for hex numbers
     let hexnum = just("0x").ignore_then(text::int(16)).map(Token::HexNum);
@@ -278,10 +279,21 @@ fn test_tokenizer() {
     // Usually, comments are terminated by a line break. The line break is a separate token
     assert_eq!(
         tokens_only("# comment\n"),
-        Ok(Vec::from([Token::Comment, Token::Newline]))
+        Ok(Vec::from([
+            Token::Comment("# comment".to_string()),
+            Token::Newline
+        ]))
     );
     // A comment might not be terminated by a line break
-    assert_eq!(tokens_only("# comment"), Ok(Vec::from([Token::Comment])));
+    assert_eq!(
+        tokens_only("# comment"),
+        Ok(Vec::from([Token::Comment("# comment".to_string())]))
+    );
+    // The `\r` of a Windows line break is not part of the comment
+    assert_eq!(
+        tokens_only("#\r\n"),
+        Ok(Vec::from([Token::Comment("#".to_string()), Token::Newline]))
+    );
 
     // Multiple tokens
     assert_eq!(
@@ -294,7 +306,7 @@ fn test_tokenizer() {
             Token::Type("ptr".to_string()),
             Token::LocalName("%1".to_string()),
             Token::Punctuation(')'),
-            Token::Comment,
+            Token::Comment("# comment".to_string()),
         ]))
     );
 }
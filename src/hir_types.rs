@@ -0,0 +1,252 @@
+use core::fmt;
+
+use crate::hir_tokenizer::{Spanned, Token};
+
+/// A Hyper IR type, as used in the operands of instructions
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum IrType {
+    Void,
+    Int(u32),
+    Float(u32),
+    Data128,
+    Ptr,
+    /// `{int32, ptr}`, or `<{int32, ptr}>` for packed structs
+    Struct {
+        fields: Vec<IrType>,
+        packed: bool,
+    },
+    Array(Box<IrType>, u64),
+    /// Types defined outside of the module, e.g. `hyper::QueryResources`.
+    /// We don't know their layout.
+    Named(String),
+}
+
+impl IrType {
+    pub fn from_name(name: &str) -> IrType {
+        match name {
+            "void" => IrType::Void,
+            "int1" => IrType::Int(1),
+            "int8" => IrType::Int(8),
+            "int16" => IrType::Int(16),
+            "int32" => IrType::Int(32),
+            "int64" => IrType::Int(64),
+            "float32" => IrType::Float(32),
+            "float64" => IrType::Float(64),
+            "data128" => IrType::Data128,
+            "ptr" => IrType::Ptr,
+            _ => IrType::Named(name.to_string()),
+        }
+    }
+
+    /// The size in bytes, if known
+    pub fn size_of(&self) -> Option<u64> {
+        match self {
+            IrType::Void => None,
+            IrType::Int(bits) => Some((*bits as u64).div_ceil(8)),
+            IrType::Float(bits) => Some(*bits as u64 / 8),
+            IrType::Data128 => Some(16),
+            IrType::Ptr => Some(8),
+            IrType::Struct { fields, packed } => {
+                let end = match fields.last() {
+                    Some(last) => self
+                        .field_offset(fields.len() - 1)?
+                        .checked_add(last.size_of()?)?,
+                    None => 0,
+                };
+                if *packed {
+                    Some(end)
+                } else {
+                    end.checked_next_multiple_of(self.align_of()?)
+                }
+            }
+            // Lengths are taken from the file, so they might overflow
+            IrType::Array(elem, len) => elem.size_of()?.checked_mul(*len),
+            IrType::Named(_) => None,
+        }
+    }
+
+    /// The alignment in bytes, if known. We follow the C ABI on x86-64.
+    pub fn align_of(&self) -> Option<u64> {
+        match self {
+            IrType::Struct { packed: true, .. } => Some(1),
+            IrType::Struct { fields, .. } => fields
+                .iter()
+                .map(|f| f.align_of())
+                .try_fold(1, |a, b| Some(a.max(b?))),
+            IrType::Array(elem, _) => elem.align_of(),
+            _ => self.size_of(),
+        }
+    }
+
+    /// The byte offset of the `idx`-th field of a struct
+    pub fn field_offset(&self, idx: usize) -> Option<u64> {
+        let IrType::Struct { fields, packed } = self else {
+            return None;
+        };
+        let mut offset = 0u64;
+        for (i, field) in fields.iter().enumerate() {
+            if !packed {
+                offset = offset.checked_next_multiple_of(field.align_of()?)?;
+            }
+            if i == idx {
+                return Some(offset);
+            }
+            offset = offset.checked_add(field.size_of()?)?;
+        }
+        None
+    }
+
+    /// The type of the `idx`-th member of a struct or array
+    pub fn member_type(&self, idx: u64) -> Option<&IrType> {
+        match self {
+            IrType::Struct { fields, .. } => fields.get(idx as usize),
+            IrType::Array(elem, _) => Some(elem),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for IrType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IrType::Void => write!(f, "void"),
+            IrType::Int(bits) => write!(f, "int{}", bits),
+            IrType::Float(bits) => write!(f, "float{}", bits),
+            IrType::Data128 => write!(f, "data128"),
+            IrType::Ptr => write!(f, "ptr"),
+            IrType::Struct { fields, packed } => {
+                let fields = fields
+                    .iter()
+                    .map(|f| f.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                if *packed {
+                    write!(f, "<{{{}}}>", fields)
+                } else {
+                    write!(f, "{{{}}}", fields)
+                }
+            }
+            IrType::Array(elem, len) => write!(f, "{}[{}]", elem, len),
+            IrType::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+fn parse_struct_fields(tokens: &[Spanned<Token>]) -> Option<(Vec<IrType>, &[Spanned<Token>])> {
+    let mut fields = vec![];
+    let mut rest = tokens;
+    if let [(Token::Punctuation('}'), _), tail @ ..] = rest {
+        return Some((fields, tail));
+    }
+    loop {
+        let (field, tail) = parse_type(rest)?;
+        fields.push(field);
+        match tail {
+            [(Token::Punctuation(','), _), tail @ ..] => rest = tail,
+            [(Token::Punctuation('}'), _), tail @ ..] => return Some((fields, tail)),
+            _ => return None,
+        }
+    }
+}
+
+/// Parses a type from the beginning of a token soup, returning the remaining tokens
+pub fn parse_type(tokens: &[Spanned<Token>]) -> Option<(IrType, &[Spanned<Token>])> {
+    let (mut type_, mut rest) = match tokens {
+        [(Token::Type(name), _), rest @ ..] | [(Token::Ident(name), _), rest @ ..] => {
+            (IrType::from_name(name), rest)
+        }
+        [(Token::Punctuation('{'), _), rest @ ..] => {
+            let (fields, rest) = parse_struct_fields(rest)?;
+            (
+                IrType::Struct {
+                    fields,
+                    packed: false,
+                },
+                rest,
+            )
+        }
+        [(Token::Punctuation('<'), _), (Token::Punctuation('{'), _), rest @ ..] => {
+            let (fields, rest) = parse_struct_fields(rest)?;
+            let [(Token::Punctuation('>'), _), rest @ ..] = rest else {
+                return None;
+            };
+            (
+                IrType::Struct {
+                    fields,
+                    packed: true,
+                },
+                rest,
+            )
+        }
+        _ => return None,
+    };
+    // Pointers (`int8*`) and arrays (`int32[4]`)
+    loop {
+        match rest {
+            [(Token::Punctuation('*'), _), tail @ ..] => {
+                type_ = IrType::Ptr;
+                rest = tail;
+            }
+            [(Token::Punctuation('['), _), (Token::Num(len), _), (Token::Punctuation(']'), _), tail @ ..] =>
+            {
+                type_ = IrType::Array(Box::new(type_), len.parse().ok()?);
+                rest = tail;
+            }
+            _ => return Some((type_, rest)),
+        }
+    }
+}
+
+pub fn parse_type_from_str(src: &str) -> Option<IrType> {
+    use chumsky::Parser;
+    let tokens = crate::hir_tokenizer::tokenizer().parse(src).ok()?;
+    match parse_type(&tokens)? {
+        (type_, []) => Some(type_),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_parse_type() {
+    assert_eq!(parse_type_from_str("int32"), Some(IrType::Int(32)));
+    assert_eq!(
+        parse_type_from_str("hyper::QueryResources*").unwrap(),
+        IrType::Ptr
+    );
+    assert_eq!(
+        parse_type_from_str("int8*[2]").unwrap(),
+        IrType::Array(Box::new(IrType::Ptr), 2)
+    );
+    assert_eq!(
+        parse_type_from_str("<{int32, int8[1]}>").unwrap(),
+        IrType::Struct {
+            fields: vec![IrType::Int(32), IrType::Array(Box::new(IrType::Int(8)), 1)],
+            packed: true
+        }
+    );
+    assert_eq!(
+        parse_type_from_str("{hyper::DebugStringStream, <{}>}")
+            .unwrap()
+            .to_string(),
+        "{hyper::DebugStringStream, <{}>}"
+    );
+}
+
+#[test]
+fn test_layout() {
+    let t = parse_type_from_str("{ptr, int32, int1, ptr, int64, int1, <{int32, int8[1]}>, <{}>}")
+        .unwrap();
+    assert_eq!(t.field_offset(0), Some(0));
+    assert_eq!(t.field_offset(1), Some(8));
+    assert_eq!(t.field_offset(2), Some(12));
+    assert_eq!(t.field_offset(3), Some(16));
+    assert_eq!(t.field_offset(5), Some(32));
+    assert_eq!(t.field_offset(6), Some(33));
+    assert_eq!(t.field_offset(7), Some(38));
+    assert_eq!(t.size_of(), Some(40));
+    assert_eq!(t.align_of(), Some(8));
+    // We don't know the layout of types defined outside the module
+    let t = parse_type_from_str("{int32, hyper::QueryResources, int64}").unwrap();
+    assert_eq!(t.field_offset(0), Some(0));
+    assert_eq!(t.field_offset(1), None);
+}
@@ -0,0 +1,1247 @@
+use core::fmt;
+use std::collections::HashMap;
+
+use crate::{
+    hir_globals::parse_initializer,
+    hir_opcodes::OperandShape,
+    hir_operands::{
+        parse_key_value, parse_typed_value, parse_value, split_call, split_operands, TypedValue,
+        Value,
+    },
+    hir_parser::{FuncBody, FuncSignature, Instruction, Statement},
+    hir_tokenizer::{Span, Spanned, Token},
+    hir_types::{parse_type, IrType},
+};
+
+/// A runtime value. Integers, pointers and `data128` values are zero-extended to 128 bits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Val {
+    Void,
+    Int(u128),
+    Float(f64),
+}
+
+impl fmt::Display for Val {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Val::Void => write!(f, "void"),
+            Val::Int(v) => write!(f, "{}", v),
+            Val::Float(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InterpreterError {
+    UnknownFunction(String),
+    ArgumentCount {
+        function: String,
+        expected: usize,
+        got: usize,
+    },
+    UndefinedValue(String),
+    UnknownLabel(String),
+    MissingTerminator(String),
+    UnsupportedInstruction {
+        instruction: String,
+        span: Span,
+    },
+    MalformedInstruction {
+        instruction: String,
+        span: Span,
+    },
+    UnknownLayout(IrType),
+    InvalidMemoryAccess(u64),
+    /// An allocation of this many bytes exceeds the memory limit
+    OutOfMemory(u64),
+    Unreachable(Span),
+    /// A `noreturn` function was called, e.g. `@hyper::RuntimeException::throwOverflow`
+    NoReturn(String),
+    StepLimitExceeded,
+    CallDepthExceeded,
+    External(String),
+}
+
+impl fmt::Display for InterpreterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InterpreterError::UnknownFunction(name) => write!(f, "unknown function {}", name),
+            InterpreterError::ArgumentCount {
+                function,
+                expected,
+                got,
+            } => write!(
+                f,
+                "{} expects {} arguments, but got {}",
+                function, expected, got
+            ),
+            InterpreterError::UndefinedValue(name) => write!(f, "undefined value {}", name),
+            InterpreterError::UnknownLabel(name) => write!(f, "unknown basic block {}", name),
+            InterpreterError::MissingTerminator(name) => {
+                write!(f, "basic block {} has no terminator", name)
+            }
+            InterpreterError::UnsupportedInstruction { instruction, span } => write!(
+                f,
+                "unsupported instruction `{}` at offset {}",
+                instruction, span.start
+            ),
+            InterpreterError::MalformedInstruction { instruction, span } => write!(
+                f,
+                "malformed `{}` instruction at offset {}",
+                instruction, span.start
+            ),
+            InterpreterError::UnknownLayout(type_) => write!(f, "unknown layout of {}", type_),
+            InterpreterError::InvalidMemoryAccess(addr) => {
+                write!(f, "invalid memory access at 0x{:x}", addr)
+            }
+            InterpreterError::OutOfMemory(size) => {
+                write!(f, "out of memory allocating {} bytes", size)
+            }
+            InterpreterError::Unreachable(span) => {
+                write!(f, "reached `unreachable` at offset {}", span.start)
+            }
+            InterpreterError::NoReturn(name) => write!(f, "called noreturn function {}", name),
+            InterpreterError::StepLimitExceeded => write!(f, "step limit exceeded"),
+            InterpreterError::CallDepthExceeded => write!(f, "call depth exceeded"),
+            InterpreterError::External(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+/// Simulated memory. Addresses start at `MEMORY_BASE`, such that null pointers stay invalid.
+#[derive(Debug)]
+pub struct Memory {
+    bytes: Vec<u8>,
+    /// Allocations beyond this many bytes in total fail, since sizes are taken from the IR
+    pub max_bytes: u64,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory {
+            bytes: vec![],
+            max_bytes: 1 << 30,
+        }
+    }
+}
+
+const MEMORY_BASE: u64 = 0x1000;
+
+/// Functions get fake addresses, such that they can be passed around as pointers
+const FUNCTION_BASE: u64 = 0xf000_0000_0000;
+
+impl Memory {
+    pub fn alloc(&mut self, size: u64, align: u64) -> Result<u64, InterpreterError> {
+        let start = (MEMORY_BASE + self.bytes.len() as u64)
+            .checked_next_multiple_of(align.max(1))
+            .ok_or(InterpreterError::OutOfMemory(size))?;
+        let end = (start - MEMORY_BASE)
+            .checked_add(size)
+            .filter(|end| *end <= self.max_bytes)
+            .ok_or(InterpreterError::OutOfMemory(size))?;
+        self.bytes.resize(end as usize, 0);
+        Ok(start)
+    }
+
+    fn range(&self, addr: u64, size: u64) -> Result<std::ops::Range<usize>, InterpreterError> {
+        let start = addr
+            .checked_sub(MEMORY_BASE)
+            .ok_or(InterpreterError::InvalidMemoryAccess(addr))?;
+        let end = start
+            .checked_add(size)
+            .filter(|end| *end <= self.bytes.len() as u64)
+            .ok_or(InterpreterError::InvalidMemoryAccess(addr))?;
+        Ok(start as usize..end as usize)
+    }
+
+    pub fn read(&self, addr: u64, size: u64) -> Result<&[u8], InterpreterError> {
+        Ok(&self.bytes[self.range(addr, size)?])
+    }
+
+    pub fn write(&mut self, addr: u64, bytes: &[u8]) -> Result<(), InterpreterError> {
+        let range = self.range(addr, bytes.len() as u64)?;
+        self.bytes[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Fills `len` bytes starting at `addr` with `value`
+    pub fn fill(&mut self, addr: u64, len: u64, value: u8) -> Result<(), InterpreterError> {
+        let range = self.range(addr, len)?;
+        self.bytes[range].fill(value);
+        Ok(())
+    }
+
+    /// Reads a little-endian integer of at most 16 bytes
+    pub fn read_int(&self, addr: u64, size: u64) -> Result<u128, InterpreterError> {
+        let mut buf = [0; 16];
+        buf[..size as usize].copy_from_slice(self.read(addr, size)?);
+        Ok(u128::from_le_bytes(buf))
+    }
+
+    /// Writes a little-endian integer of at most 16 bytes
+    pub fn write_int(&mut self, addr: u64, size: u64, value: u128) -> Result<(), InterpreterError> {
+        self.write(addr, &value.to_le_bytes()[..size as usize])
+    }
+}
+
+/// Functions which are only declared, but not defined in the module are implemented
+/// outside of Hyper IR. The embedder decides what calling them does.
+pub trait ExternalFunctions {
+    /// `signature` is `None` for calls through function pointers we don't know,
+    /// in which case `name` is the called address
+    fn call(
+        &mut self,
+        name: &str,
+        signature: Option<&FuncSignature>,
+        args: &[Val],
+        memory: &mut Memory,
+    ) -> Result<Val, InterpreterError>;
+}
+
+/// Stubs out all external functions: they return zero, unless they are `noreturn`
+#[derive(Debug, Default)]
+pub struct StubExternalFunctions;
+
+impl ExternalFunctions for StubExternalFunctions {
+    fn call(
+        &mut self,
+        name: &str,
+        signature: Option<&FuncSignature>,
+        _args: &[Val],
+        _memory: &mut Memory,
+    ) -> Result<Val, InterpreterError> {
+        let Some(signature) = signature else {
+            return Ok(Val::Int(0));
+        };
+        if signature.modifiers.iter().any(|m| m.0 == "noreturn") {
+            return Err(InterpreterError::NoReturn(name.to_string()));
+        }
+        Ok(zero_of(&IrType::from_name(&signature.ret_type.0)))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// A basic block was entered. Unlabeled entry blocks are reported as `<entry>`.
+    Block {
+        function: String,
+        label: String,
+    },
+    ExternalCall {
+        function: String,
+    },
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceEvent::Block { function, label } => write!(f, "{}: {}", function, label),
+            TraceEvent::ExternalCall { function } => write!(f, "  call {}", function),
+        }
+    }
+}
+
+enum Flow<'a> {
+    Continue,
+    Jump(&'a str),
+    Return(Val),
+}
+
+type Frame = HashMap<String, Val>;
+
+fn zero_of(type_: &IrType) -> Val {
+    match type_ {
+        IrType::Void => Val::Void,
+        IrType::Float(_) => Val::Float(0.0),
+        _ => Val::Int(0),
+    }
+}
+
+/// The size of a type loaded or stored as a single value, which must fit into a `u128`
+fn scalar_size(type_: &IrType) -> Result<u64, InterpreterError> {
+    type_
+        .size_of()
+        .filter(|size| *size <= 16)
+        .ok_or_else(|| InterpreterError::UnknownLayout(type_.clone()))
+}
+
+pub fn bits_of(type_: &IrType) -> u32 {
+    match type_ {
+        IrType::Int(bits) => *bits,
+        IrType::Ptr | IrType::Float(64) => 64,
+        IrType::Float(bits) => *bits,
+        _ => 128,
+    }
+}
+
+pub fn mask(value: u128, bits: u32) -> u128 {
+    if bits >= 128 {
+        value
+    } else {
+        value & ((1 << bits) - 1)
+    }
+}
+
+pub fn sign_extend(value: u128, bits: u32) -> i128 {
+    if bits >= 128 {
+        value as i128
+    } else {
+        let shift = 128 - bits;
+        ((value << shift) as i128) >> shift
+    }
+}
+
+fn as_int(value: Val) -> u128 {
+    match value {
+        Val::Int(v) => v,
+        Val::Float(v) => v as u128,
+        Val::Void => 0,
+    }
+}
+
+fn as_float(value: Val) -> f64 {
+    match value {
+        Val::Float(v) => v,
+        Val::Int(v) => v as f64,
+        Val::Void => 0.0,
+    }
+}
+
+fn as_addr(value: Val) -> u64 {
+    as_int(value) as u64
+}
+
+fn label_of(operand: &[Spanned<Token>]) -> Option<&str> {
+    match operand {
+        [(Token::Ident(label), _)] => Some(label),
+        _ => None,
+    }
+}
+
+/// Executes Hyper IR
+pub struct Interpreter<'a, E: ExternalFunctions> {
+    functions: HashMap<&'a str, &'a Statement>,
+    function_addrs: HashMap<&'a str, u64>,
+    globals: HashMap<&'a str, &'a [Spanned<Token>]>,
+    global_addrs: HashMap<&'a str, u64>,
+    pub memory: Memory,
+    pub externals: E,
+    pub trace: Vec<TraceEvent>,
+    pub max_steps: usize,
+    steps: usize,
+    /// Deeper calls fail instead of overflowing the native stack. Each call takes a few
+    /// kilobytes of stack in debug builds, and Tokio worker threads only have 2 MiB.
+    pub max_call_depth: usize,
+    call_depth: usize,
+}
+
+impl<'a, E: ExternalFunctions> Interpreter<'a, E> {
+    pub fn new(stmts: &'a [Statement], externals: E) -> Self {
+        let mut functions = HashMap::new();
+        let mut globals = HashMap::new();
+        for stmt in stmts {
+            match stmt {
+                Statement::FuncDef { signature, .. } => {
+                    functions.insert(signature.name.0.as_str(), stmt);
+                }
+                Statement::FuncDecl { signature, .. } => {
+                    // Definitions take precedence over declarations
+                    functions.entry(signature.name.0.as_str()).or_insert(stmt);
+                }
+                Statement::GlobalVar { name, def } => {
+                    globals.insert(name.0.as_str(), def.as_slice());
+                }
+                _ => {}
+            }
+        }
+        let function_addrs = functions
+            .keys()
+            .enumerate()
+            .map(|(i, name)| (*name, FUNCTION_BASE + 16 * i as u64))
+            .collect();
+        Interpreter {
+            functions,
+            function_addrs,
+            globals,
+            global_addrs: HashMap::new(),
+            memory: Memory::default(),
+            externals,
+            trace: vec![],
+            max_steps: 1_000_000,
+            steps: 0,
+            max_call_depth: 64,
+            call_depth: 0,
+        }
+    }
+
+    pub fn signature(&self, name: &str) -> Option<&'a FuncSignature> {
+        match self.functions.get(name)? {
+            Statement::FuncDef { signature, .. } | Statement::FuncDecl { signature, .. } => {
+                Some(signature)
+            }
+            _ => None,
+        }
+    }
+
+    /// Calls the function `name`, e.g. `@_1_add_1`
+    pub fn call(&mut self, name: &str, args: &[Val]) -> Result<Val, InterpreterError> {
+        let stmt = *self
+            .functions
+            .get(name)
+            .ok_or_else(|| InterpreterError::UnknownFunction(name.to_string()))?;
+        match stmt {
+            Statement::FuncDef {
+                signature, body, ..
+            } => {
+                if self.call_depth >= self.max_call_depth {
+                    return Err(InterpreterError::CallDepthExceeded);
+                }
+                self.call_depth += 1;
+                let result = self.run_function(signature, body, args);
+                self.call_depth -= 1;
+                result
+            }
+            Statement::FuncDecl { signature, .. } => {
+                self.trace.push(TraceEvent::ExternalCall {
+                    function: name.to_string(),
+                });
+                self.externals
+                    .call(name, Some(signature), args, &mut self.memory)
+            }
+            _ => unreachable!("only functions are registered"),
+        }
+    }
+
+    fn call_address(&mut self, addr: u64, args: &[Val]) -> Result<Val, InterpreterError> {
+        let name = self
+            .function_addrs
+            .iter()
+            .find(|(_, a)| **a == addr)
+            .map(|(name, _)| *name);
+        match name {
+            Some(name) => self.call(name, args),
+            None => {
+                let name = format!("0x{:x}", addr);
+                self.trace.push(TraceEvent::ExternalCall {
+                    function: name.clone(),
+                });
+                self.externals.call(&name, None, args, &mut self.memory)
+            }
+        }
+    }
+
+    fn run_function(
+        &mut self,
+        signature: &'a FuncSignature,
+        body: &'a FuncBody,
+        args: &[Val],
+    ) -> Result<Val, InterpreterError> {
+        if signature.args.len() != args.len() {
+            return Err(InterpreterError::ArgumentCount {
+                function: signature.name.0.clone(),
+                expected: signature.args.len(),
+                got: args.len(),
+            });
+        }
+        let mut frame: Frame = signature
+            .args
+            .iter()
+            .zip(args)
+            .map(|(arg, val)| (arg.name.0.clone(), *val))
+            .collect();
+        let blocks = body
+            .basic_blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(i, bb)| Some((bb.label.as_ref()?.0.as_str(), i)))
+            .collect::<HashMap<_, _>>();
+
+        let mut bb_idx = 0;
+        let mut prev_label: Option<&str> = None;
+        loop {
+            let bb = body
+                .basic_blocks
+                .get(bb_idx)
+                .ok_or_else(|| InterpreterError::MissingTerminator(signature.name.0.clone()))?;
+            let label = bb.label.as_ref().map_or("<entry>", |l| l.0.as_str());
+            self.trace.push(TraceEvent::Block {
+                function: signature.name.0.clone(),
+                label: label.to_string(),
+            });
+
+            // All phi nodes at the beginning of a block are evaluated simultaneously
+            let mut instructions = bb.instructions.iter().peekable();
+            let mut phi_values = vec![];
+            while let Some(instr) = instructions.next_if(|i| i.instruction.0 == "phi") {
+                self.step()?;
+                phi_values.push((instr, self.eval_phi(instr, prev_label, &frame)?));
+            }
+            for (instr, value) in phi_values {
+                if let Some(target) = &instr.assignment_target {
+                    frame.insert(target.0.clone(), value);
+                }
+            }
+
+            let mut next = None;
+            for instr in instructions {
+                self.step()?;
+                match self.execute(instr, &mut frame)? {
+                    Flow::Continue => {}
+                    Flow::Jump(target) => {
+                        next = Some(target);
+                        break;
+                    }
+                    Flow::Return(value) => return Ok(value),
+                }
+            }
+            bb_idx = match next {
+                Some(next) => *blocks
+                    .get(next)
+                    .ok_or_else(|| InterpreterError::UnknownLabel(next.to_string()))?,
+                // The unlabeled block with the `alloca`s falls through into the next block
+                None if bb.label.is_none() && bb_idx + 1 < body.basic_blocks.len() => bb_idx + 1,
+                None => return Err(InterpreterError::MissingTerminator(label.to_string())),
+            };
+            prev_label = bb.label.as_ref().map(|l| l.0.as_str());
+        }
+    }
+
+    fn step(&mut self) -> Result<(), InterpreterError> {
+        self.steps += 1;
+        if self.steps > self.max_steps {
+            return Err(InterpreterError::StepLimitExceeded);
+        }
+        Ok(())
+    }
+
+    fn eval(&mut self, value: &TypedValue, frame: &Frame) -> Result<Val, InterpreterError> {
+        let bits = bits_of(&value.type_);
+        let val = match &value.value.0 {
+            Value::Local(name) => *frame
+                .get(name)
+                .ok_or_else(|| InterpreterError::UndefinedValue(name.clone()))?,
+            Value::Global(name) => Val::Int(self.global_address(name)? as u128),
+            Value::Int(v) => Val::Int(*v),
+            Value::Float(v) => Val::Float(v.parse().unwrap_or_default()),
+            Value::Bool(b) => Val::Int(*b as u128),
+            Value::Null => Val::Int(0),
+        };
+        Ok(match (&value.type_, val) {
+            (IrType::Float(_), Val::Int(v)) => Val::Float(v as f64),
+            (_, Val::Int(v)) => Val::Int(mask(v, bits)),
+            (_, val) => val,
+        })
+    }
+
+    /// Evaluates an operand which consists of exactly one typed value
+    fn eval_operand(
+        &mut self,
+        operand: &[Spanned<Token>],
+        frame: &Frame,
+    ) -> Option<Result<(IrType, Val), InterpreterError>> {
+        let (value, []) = parse_typed_value(operand)? else {
+            return None;
+        };
+        Some(self.eval(&value, frame).map(|v| (value.type_, v)))
+    }
+
+    fn global_address(&mut self, name: &str) -> Result<u64, InterpreterError> {
+        if let Some(addr) = self.function_addrs.get(name) {
+            return Ok(*addr);
+        }
+        if let Some(addr) = self.global_addrs.get(name) {
+            return Ok(*addr);
+        }
+        let (&key, &def) = self
+            .globals
+            .get_key_value(name)
+            .ok_or_else(|| InterpreterError::UndefinedValue(name.to_string()))?;
+        let unsupported = || InterpreterError::UnsupportedInstruction {
+            instruction: format!("{} initializer", name),
+            span: def.first().map_or(0..0, |t| t.1.clone()),
+        };
+        // Initializers referring to other globals are not supported
+        let init = parse_initializer(def).ok_or_else(unsupported)?;
+        let bytes = init.to_bytes().ok_or_else(unsupported)?;
+        let addr = self
+            .memory
+            .alloc(bytes.len() as u64, init.type_().align_of().unwrap_or(1))?;
+        self.memory.write(addr, &bytes)?;
+        self.global_addrs.insert(key, addr);
+        Ok(addr)
+    }
+
+    fn eval_phi(
+        &mut self,
+        instr: &Instruction,
+        prev_label: Option<&str>,
+        frame: &Frame,
+    ) -> Result<Val, InterpreterError> {
+        for operand in split_operands(&instr.operands) {
+            let [(Token::Punctuation('['), _), (Token::Ident(label), _), (Token::Punctuation(','), _), value @ .., (Token::Punctuation(']'), _)] =
+                operand
+            else {
+                return Err(malformed(instr));
+            };
+            if Some(label.as_str()) == prev_label {
+                return self
+                    .eval_operand(value, frame)
+                    .ok_or_else(|| malformed(instr))?
+                    .map(|v| v.1);
+            }
+        }
+        Err(InterpreterError::UnknownLabel(
+            prev_label.unwrap_or("<entry>").to_string(),
+        ))
+    }
+
+    /// Computes the address of a member, like `gep`. The first index steps over
+    /// whole elements of `type_`, the following ones select struct fields or array elements.
+    fn member_address(
+        &mut self,
+        type_: &IrType,
+        base: u64,
+        indices: &[&[Spanned<Token>]],
+        frame: &Frame,
+    ) -> Option<Result<(u64, IrType), InterpreterError>> {
+        let mut indices = indices.iter();
+        let mut addr = base;
+        let mut type_ = type_.clone();
+        if let Some(first) = indices.next() {
+            let (idx_type, idx) = match self.eval_operand(first, frame)? {
+                Ok(v) => v,
+                Err(e) => return Some(Err(e)),
+            };
+            let Some(size) = type_.size_of() else {
+                return Some(Err(InterpreterError::UnknownLayout(type_)));
+            };
+            let idx = sign_extend(as_int(idx), bits_of(&idx_type)) as i64;
+            addr = addr.wrapping_add_signed(idx.wrapping_mul(size as i64));
+        }
+        for operand in indices {
+            let (idx_type, idx) = match self.eval_operand(operand, frame)? {
+                Ok(v) => v,
+                Err(e) => return Some(Err(e)),
+            };
+            let idx = sign_extend(as_int(idx), bits_of(&idx_type));
+            let offset = match &type_ {
+                IrType::Struct { .. } => type_.field_offset(idx as usize),
+                IrType::Array(elem, _) => elem.size_of().map(|s| s.wrapping_mul(idx as u64)),
+                _ => return None,
+            };
+            let Some(offset) = offset else {
+                return Some(Err(InterpreterError::UnknownLayout(type_)));
+            };
+            addr = addr.wrapping_add(offset);
+            type_ = type_.member_type(idx as u64)?.clone();
+        }
+        Some(Ok((addr, type_)))
+    }
+
+    /// Parses the common prefix of `load`, `store` and `gep`: `[atomic] type ptr %p`
+    fn pointer_operand(
+        &mut self,
+        operand: &[Spanned<Token>],
+        frame: &Frame,
+    ) -> Option<Result<(IrType, u64), InterpreterError>> {
+        let operand = match operand {
+            [(Token::Ident(kw), _), rest @ ..] if kw == "atomic" => rest,
+            _ => operand,
+        };
+        let (type_, rest) = parse_type(operand)?;
+        Some(
+            self.eval_operand(rest, frame)?
+                .map(|(_, ptr)| (type_, as_addr(ptr))),
+        )
+    }
+
+    /// Loads a value of type `type_` from the simulated memory.
+    /// Aggregates wider than 16 bytes are not supported.
+    pub fn load(&self, addr: u64, type_: &IrType) -> Result<Val, InterpreterError> {
+        let size = scalar_size(type_)?;
+        let raw = self.memory.read_int(addr, size)?;
+        Ok(match type_ {
+            IrType::Float(32) => Val::Float(f32::from_bits(raw as u32) as f64),
+            IrType::Float(_) => Val::Float(f64::from_bits(raw as u64)),
+            _ => Val::Int(mask(raw, bits_of(type_))),
+        })
+    }
+
+    /// Stores a value of type `type_` into the simulated memory
+    pub fn store(&mut self, addr: u64, type_: &IrType, value: Val) -> Result<(), InterpreterError> {
+        let size = scalar_size(type_)?;
+        let raw = match (type_, value) {
+            (IrType::Float(32), v) => (as_float(v) as f32).to_bits() as u128,
+            (IrType::Float(_), v) => as_float(v).to_bits() as u128,
+            (_, v) => as_int(v),
+        };
+        self.memory.write_int(addr, size, raw)
+    }
+
+    fn execute(
+        &mut self,
+        instr: &'a Instruction,
+        frame: &mut Frame,
+    ) -> Result<Flow<'a>, InterpreterError> {
+        let (value, flow) = self.execute_instruction(instr, frame)?;
+        if let (Some(value), Some(target)) = (value, &instr.assignment_target) {
+            frame.insert(target.0.clone(), value);
+        }
+        Ok(flow)
+    }
+
+    /// Executes a single instruction, returning the value it produces, if any
+    fn execute_instruction(
+        &mut self,
+        instr: &'a Instruction,
+        frame: &Frame,
+    ) -> Result<(Option<Val>, Flow<'a>), InterpreterError> {
+        let ops = split_operands(&instr.operands);
+        let result_type = instr
+            .assignment_type
+            .as_ref()
+            .map(|t| IrType::from_name(&t.0));
+        let opcode = instr.instruction.0.as_str();
+        macro_rules! operand {
+            ($op:expr) => {
+                self.eval_operand($op, frame)
+                    .ok_or_else(|| malformed(instr))??
+            };
+        }
+        let value = match (opcode, ops.as_slice()) {
+            ("ret", []) => return Ok((None, Flow::Return(Val::Void))),
+            ("ret", [value]) => return Ok((None, Flow::Return(operand!(value).1))),
+            ("unreachable", []) => return Err(InterpreterError::Unreachable(instr.span.clone())),
+            ("br", [target]) => {
+                return Ok((
+                    None,
+                    Flow::Jump(label_of(target).ok_or_else(|| malformed(instr))?),
+                ));
+            }
+            ("br", [cond, then, else_]) => {
+                let target = if as_int(operand!(cond).1) != 0 {
+                    then
+                } else {
+                    else_
+                };
+                return Ok((
+                    None,
+                    Flow::Jump(label_of(target).ok_or_else(|| malformed(instr))?),
+                ));
+            }
+            ("switch", [cond, cases @ ..]) => {
+                let (type_, cond) = operand!(cond);
+                let mut default = None;
+                for case in cases {
+                    if let Some((Token::Ident(label), _)) = parse_key_value(case, "default") {
+                        default = Some(label.as_str());
+                        continue;
+                    }
+                    let Some((value, rest)) = parse_typed_value(case) else {
+                        return Err(malformed(instr));
+                    };
+                    let Some((Token::Ident(label), _)) = parse_key_value(rest, "label") else {
+                        return Err(malformed(instr));
+                    };
+                    if mask(as_int(self.eval(&value, frame)?), bits_of(&type_)) == as_int(cond) {
+                        return Ok((None, Flow::Jump(label)));
+                    }
+                }
+                return Ok((None, Flow::Jump(default.ok_or_else(|| malformed(instr))?)));
+            }
+            (_, [a, b, cont, overflow])
+                if instr.opcode().map(|s| s.shape) == Some(OperandShape::OverflowBranch(2)) =>
+            {
+                let (type_, a) = operand!(a);
+                let (_, b) = operand!(b);
+                let (Some((Token::Ident(cont), _)), Some((Token::Ident(overflow), _))) = (
+                    parse_key_value(cont, "cont"),
+                    parse_key_value(overflow, "overflow"),
+                ) else {
+                    return Err(malformed(instr));
+                };
+                let (value, overflowed) =
+                    checked_arithmetic(&opcode[..opcode.len() - 2], &type_, a, b)
+                        .ok_or_else(|| unsupported(instr))?;
+                if overflowed {
+                    return Ok((None, Flow::Jump(overflow)));
+                }
+                return Ok((Some(value), Flow::Jump(cont)));
+            }
+            ("select", [cond, a, b]) => {
+                let cond = operand!(cond).1;
+                let a = operand!(a).1;
+                let b = operand!(b).1;
+                if as_int(cond) != 0 {
+                    a
+                } else {
+                    b
+                }
+            }
+            ("iszero" | "isnotzero" | "isnull" | "isnotnull", [value]) => {
+                let is_zero = match operand!(value).1 {
+                    Val::Float(v) => v == 0.0,
+                    v => as_int(v) == 0,
+                };
+                Val::Int((is_zero != opcode.contains("not")) as u128)
+            }
+            ("zext" | "sext" | "trunc", [value]) => {
+                let (type_, value) = operand!(value);
+                let target = result_type.clone().ok_or_else(|| malformed(instr))?;
+                let value = match opcode {
+                    "sext" => sign_extend(as_int(value), bits_of(&type_)) as u128,
+                    _ => as_int(value),
+                };
+                Val::Int(mask(value, bits_of(&target)))
+            }
+            ("extract.d128", [value, idx]) => {
+                let value = as_int(operand!(value).1);
+                let Some(((Value::Int(idx), _), [])) = parse_value(idx) else {
+                    return Err(malformed(instr));
+                };
+                Val::Int(mask(value >> (64 * idx.min(1)), 64))
+            }
+            ("alloca", [operand]) => {
+                let (type_, rest) = parse_type(operand).ok_or_else(|| malformed(instr))?;
+                let align = match rest {
+                    [(Token::Ident(kw), _), (Token::Num(align), _)] if kw == "align" => {
+                        align.parse().map_err(|_| malformed(instr))?
+                    }
+                    _ => type_.align_of().unwrap_or(8),
+                };
+                let size = type_
+                    .size_of()
+                    .ok_or(InterpreterError::UnknownLayout(type_))?;
+                Val::Int(self.memory.alloc(size, align)? as u128)
+            }
+            ("gep", [pointer, indices @ ..]) => {
+                let (type_, base) = self
+                    .pointer_operand(pointer, frame)
+                    .ok_or_else(|| malformed(instr))??;
+                let (addr, _) = self
+                    .member_address(&type_, base, indices, frame)
+                    .ok_or_else(|| malformed(instr))??;
+                Val::Int(addr as u128)
+            }
+            ("load", [pointer, indices @ ..]) => {
+                let (type_, base) = self
+                    .pointer_operand(pointer, frame)
+                    .ok_or_else(|| malformed(instr))??;
+                let (addr, member_type) = if indices.is_empty() {
+                    (base, type_)
+                } else {
+                    self.member_address(&type_, base, indices, frame)
+                        .ok_or_else(|| malformed(instr))??
+                };
+                self.load(addr, result_type.as_ref().unwrap_or(&member_type))?
+            }
+            ("store", [pointer, value, indices @ ..]) => {
+                let (type_, base) = self
+                    .pointer_operand(pointer, frame)
+                    .ok_or_else(|| malformed(instr))??;
+                let (value_type, value) = operand!(value);
+                let addr = if indices.is_empty() {
+                    base
+                } else {
+                    self.member_address(&type_, base, indices, frame)
+                        .ok_or_else(|| malformed(instr))??
+                        .0
+                };
+                self.store(addr, &value_type, value)?;
+                return Ok((None, Flow::Continue));
+            }
+            ("call" | "calllambda" | "callintrinsic", [operand]) => {
+                let (callee, args) = split_call(operand).ok_or_else(|| malformed(instr))?;
+                let args = args
+                    .into_iter()
+                    .map(|arg| Ok(operand!(arg).1))
+                    .collect::<Result<Vec<_>, InterpreterError>>()?;
+                let value = match (opcode, callee) {
+                    ("call", [(Token::GlobalName(name), _)]) => self.call(name, &args)?,
+                    (
+                        "calllambda",
+                        [(Token::Punctuation('{'), _), callee @ .., (Token::Punctuation('}'), _)],
+                    ) => {
+                        let addr = as_addr(operand!(callee).1);
+                        self.call_address(addr, &args)?
+                    }
+                    ("callintrinsic", [(Token::Ident(name), _)]) => self
+                        .call_intrinsic(name, &args)
+                        .ok_or_else(|| unsupported(instr))??,
+                    _ => return Err(malformed(instr)),
+                };
+                return Ok((Some(value), Flow::Continue));
+            }
+            (_, [a, b]) => {
+                let (type_, a) = operand!(a);
+                let (_, b) = operand!(b);
+                binary_operation(opcode, &type_, a, b).ok_or_else(|| unsupported(instr))?
+            }
+            _ => return Err(unsupported(instr)),
+        };
+        Ok((Some(value), Flow::Continue))
+    }
+
+    fn call_intrinsic(
+        &mut self,
+        name: &str,
+        args: &[Val],
+    ) -> Option<Result<Val, InterpreterError>> {
+        let result = match (name, args) {
+            ("memset", [dst, value, len, ..]) => {
+                self.memory
+                    .fill(as_addr(*dst), as_int(*len) as u64, as_int(*value) as u8)
+            }
+            ("memcpy" | "memmove", [dst, src, len, ..]) => self
+                .memory
+                .read(as_addr(*src), as_int(*len) as u64)
+                .map(|bytes| bytes.to_vec())
+                .and_then(|bytes| self.memory.write(as_addr(*dst), &bytes)),
+            _ => return None,
+        };
+        Some(result.map(|_| Val::Void))
+    }
+}
+
+fn malformed(instr: &Instruction) -> InterpreterError {
+    InterpreterError::MalformedInstruction {
+        instruction: instr.instruction.0.clone(),
+        span: instr.span.clone(),
+    }
+}
+
+fn unsupported(instr: &Instruction) -> InterpreterError {
+    InterpreterError::UnsupportedInstruction {
+        instruction: instr.instruction.0.clone(),
+        span: instr.span.clone(),
+    }
+}
+
+/// Computes `add`, `sub` or `mul` with an overflow check, as done by `saddbr` and friends
+pub fn checked_arithmetic(opcode: &str, type_: &IrType, a: Val, b: Val) -> Option<(Val, bool)> {
+    let bits = bits_of(type_);
+    let (signed, op) = opcode.split_at(1);
+    let (a, b) = (as_int(a), as_int(b));
+    let exact = match signed {
+        "s" => {
+            let (a, b) = (sign_extend(a, bits), sign_extend(b, bits));
+            let result = match op {
+                "add" => a.checked_add(b),
+                "sub" => a.checked_sub(b),
+                "mul" => a.checked_mul(b),
+                _ => return None,
+            };
+            result.filter(|r| sign_extend(mask(*r as u128, bits), bits) == *r)
+        }
+        "u" => {
+            let result = match op {
+                "add" => a.checked_add(b),
+                "sub" => a.checked_sub(b),
+                "mul" => a.checked_mul(b),
+                _ => return None,
+            };
+            result.filter(|r| mask(*r, bits) == *r).map(|r| r as i128)
+        }
+        _ => return None,
+    };
+    match exact {
+        Some(r) => Some((Val::Int(mask(r as u128, bits)), false)),
+        None => Some((Val::Void, true)),
+    }
+}
+
+pub fn binary_operation(opcode: &str, type_: &IrType, a: Val, b: Val) -> Option<Val> {
+    if let IrType::Float(_) = type_ {
+        let (a, b) = (as_float(a), as_float(b));
+        let cmp = |c: bool| Some(Val::Int(c as u128));
+        return match opcode {
+            "add" => Some(Val::Float(a + b)),
+            "sub" => Some(Val::Float(a - b)),
+            "mul" => Some(Val::Float(a * b)),
+            "div" | "sdiv" | "udiv" => Some(Val::Float(a / b)),
+            "eq" => cmp(a == b),
+            "neq" => cmp(a != b),
+            "slt" | "ult" => cmp(a < b),
+            "sle" | "ule" => cmp(a <= b),
+            "sgt" | "ugt" => cmp(a > b),
+            "sge" | "uge" => cmp(a >= b),
+            _ => None,
+        };
+    }
+    let bits = bits_of(type_);
+    let (a, b) = (as_int(a), as_int(b));
+    let (sa, sb) = (sign_extend(a, bits), sign_extend(b, bits));
+    let value = match opcode {
+        "add" => a.wrapping_add(b),
+        "sub" => a.wrapping_sub(b),
+        "mul" => a.wrapping_mul(b),
+        "sdiv" => sa.checked_div(sb)? as u128,
+        "udiv" => a.checked_div(b)?,
+        "srem" => sa.checked_rem(sb)? as u128,
+        "urem" => a.checked_rem(b)?,
+        "bitand" => a & b,
+        "bitor" => a | b,
+        "bitxor" => a ^ b,
+        "shl" => a.checked_shl(b as u32)?,
+        "lshr" => a.checked_shr(b as u32)?,
+        "ashr" => sa.checked_shr(b as u32)? as u128,
+        "eq" => return Some(Val::Int((a == b) as u128)),
+        "neq" => return Some(Val::Int((a != b) as u128)),
+        "ult" => return Some(Val::Int((a < b) as u128)),
+        "ule" => return Some(Val::Int((a <= b) as u128)),
+        "ugt" => return Some(Val::Int((a > b) as u128)),
+        "uge" => return Some(Val::Int((a >= b) as u128)),
+        "slt" => return Some(Val::Int((sa < sb) as u128)),
+        "sle" => return Some(Val::Int((sa <= sb) as u128)),
+        "sgt" => return Some(Val::Int((sa > sb) as u128)),
+        "sge" => return Some(Val::Int((sa >= sb) as u128)),
+        _ => return None,
+    };
+    Some(Val::Int(mask(value, bits)))
+}
+
+/// Parses a command line argument for a parameter of type `type_`
+pub fn parse_argument(type_: &IrType, text: &str) -> Option<Val> {
+    if let IrType::Float(_) = type_ {
+        return text.parse().ok().map(Val::Float);
+    }
+    let value = match text.strip_prefix("0x") {
+        Some(hex) => u128::from_str_radix(hex, 16).ok()?,
+        None => match text {
+            "true" => 1,
+            "false" => 0,
+            _ => text.parse::<i128>().ok()? as u128,
+        },
+    };
+    Some(Val::Int(mask(value, bits_of(type_))))
+}
+
+#[cfg(test)]
+fn run(src: &str, func: &str, args: &[Val]) -> (Result<Val, InterpreterError>, Vec<String>) {
+    let res = crate::hir_parser::parse_from_str(src);
+    assert_eq!(res.errors, []);
+    let mut interpreter = Interpreter::new(&res.stmts, StubExternalFunctions);
+    let result = interpreter.call(func, args);
+    let trace = interpreter.trace.iter().map(|e| e.to_string()).collect();
+    (result, trace)
+}
+
+#[test]
+fn test_interpret_fcf() {
+    let src = std::fs::read_to_string("examples/fcf.hir").unwrap();
+    let res = crate::hir_parser::parse_from_str(&src);
+    let mut interpreter = Interpreter::new(&res.stmts, StubExternalFunctions);
+    let mut add = |a: u128, b: u128| {
+        let ptrs = [(32, a), (1, 0), (32, b), (1, 0), (32, 0), (1, 0)].map(|(bits, v)| {
+            let addr = interpreter.memory.alloc(4, 4).unwrap();
+            interpreter
+                .store(addr, &IrType::Int(bits), Val::Int(v))
+                .unwrap();
+            Val::Int(addr as u128)
+        });
+        let mut args = vec![Val::Int(0), Val::Int(0)];
+        args.extend(ptrs);
+        args.push(Val::Int(0));
+        let result = interpreter.call("@_1_add_1", &args);
+        result.and_then(|_| interpreter.load(as_addr(ptrs[4]), &IrType::Int(32)))
+    };
+    assert_eq!(add(2, 3), Ok(Val::Int(5)));
+    // -1 + -2
+    assert_eq!(add(0xffffffff, 0xfffffffe), Ok(Val::Int(0xfffffffd)));
+    assert_eq!(
+        add(0x7fffffff, 1),
+        Err(InterpreterError::NoReturn(
+            "@hyper::RuntimeException::throwOverflow".to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_interpret_loop() {
+    let src = "
+@table = int32 [10,20,30]
+define int64 @sum(int64 %n) {
+  ptr %acc = alloca {int8, int64} align 8
+body_0:
+  store {int8, int64} ptr %acc, int64 0, int32 0, int32 1
+  br loop_1
+loop_1:
+  int64 %i = phi [body_0, int64 0], [loop_1, int64 %next]
+  int64 %old = load {int8, int64} ptr %acc, int32 0, int32 1
+  int64 %new = add int64 %old, int64 %i
+  store {int8, int64} ptr %acc, int64 %new, int32 0, int32 1
+  int64 %next = add int64 %i, int64 1
+  int1 %done = ule int64 %n, int64 %next
+  br int1 %done, exit_2, loop_1
+exit_2:
+  ptr %elem = gep int32 ptr @table, int32 2
+  int32 %x = load int32 ptr %elem
+  int64 %y = zext int32 %x
+  int64 %res = call @add(int64 %new, int64 %y)
+  ret int64 %res
+}
+define int64 @add(int64 %a, int64 %b) {
+  int64 %s = add int64 %a, int64 %b
+  ret int64 %s
+}";
+    let (result, trace) = run(src, "@sum", &[Val::Int(4)]);
+    assert_eq!(result, Ok(Val::Int(36)));
+    assert_eq!(
+        trace,
+        [
+            "@sum: <entry>",
+            "@sum: body_0",
+            "@sum: loop_1",
+            "@sum: loop_1",
+            "@sum: loop_1",
+            "@sum: loop_1",
+            "@sum: exit_2",
+            "@add: <entry>"
+        ]
+    );
+}
+
+#[test]
+fn test_interpret_switch() {
+    let src = "
+declare int32 @external(int32 %x)
+define int32 @classify(int32 %x) {
+body_0:
+  switch int32 %x, default=other_3, int32 0 label=zero_1, int32 1 label=one_2
+zero_1:
+  int1 %c = iszero int32 %x
+  int32 %r = select int1 %c, int32 7, int32 8
+  ret int32 %r
+one_2:
+  int32 %e = call @external(int32 %x)
+  ret int32 %e
+other_3:
+  unreachable
+}";
+    assert_eq!(run(src, "@classify", &[Val::Int(0)]).0, Ok(Val::Int(7)));
+    let (result, trace) = run(src, "@classify", &[Val::Int(1)]);
+    assert_eq!(result, Ok(Val::Int(0)));
+    assert_eq!(
+        trace,
+        ["@classify: body_0", "@classify: one_2", "  call @external"]
+    );
+    assert!(matches!(
+        run(src, "@classify", &[Val::Int(2)]).0,
+        Err(InterpreterError::Unreachable(_))
+    ));
+    assert_eq!(
+        run(src, "@classify", &[]).0,
+        Err(InterpreterError::ArgumentCount {
+            function: "@classify".to_string(),
+            expected: 1,
+            got: 0
+        })
+    );
+}
+
+#[test]
+fn test_arithmetic() {
+    let i32 = IrType::Int(32);
+    assert_eq!(
+        binary_operation("sub", &i32, Val::Int(1), Val::Int(2)),
+        Some(Val::Int(0xffffffff))
+    );
+    assert_eq!(
+        binary_operation("slt", &i32, Val::Int(0xffffffff), Val::Int(0)),
+        Some(Val::Int(1))
+    );
+    assert_eq!(
+        binary_operation("ult", &i32, Val::Int(0xffffffff), Val::Int(0)),
+        Some(Val::Int(0))
+    );
+    assert_eq!(
+        binary_operation("udiv", &i32, Val::Int(1), Val::Int(0)),
+        None
+    );
+    assert_eq!(
+        checked_arithmetic("ssub", &i32, Val::Int(0x80000000), Val::Int(1)),
+        Some((Val::Void, true))
+    );
+    assert_eq!(
+        checked_arithmetic("usub", &i32, Val::Int(1), Val::Int(1)),
+        Some((Val::Int(0), false))
+    );
+    assert_eq!(parse_argument(&IrType::Int(8), "-1"), Some(Val::Int(0xff)));
+}
+
+#[test]
+fn test_wide_memory_access() {
+    let src = "
+define void @wide() {
+  ptr %p = alloca {int64, int64, int64} align 8
+body_0:
+  store {int64, int64, int64} ptr %p, {int64, int64, int64} 0
+  ret
+}
+define void @huge_memset() {
+  ptr %p = alloca int64 align 8
+body_0:
+  callintrinsic memset(ptr %p, int8 0, int64 10000000000000, int32 8)
+  ret
+}";
+    let wide = IrType::Struct {
+        fields: vec![IrType::Int(64); 3],
+        packed: false,
+    };
+    assert_eq!(
+        run(src, "@wide", &[]).0,
+        Err(InterpreterError::UnknownLayout(wide.clone()))
+    );
+    let res = crate::hir_parser::parse_from_str(src);
+    let mut interpreter = Interpreter::new(&res.stmts, StubExternalFunctions);
+    let addr = interpreter.memory.alloc(24, 8).unwrap();
+    assert_eq!(
+        interpreter.load(addr, &wide),
+        Err(InterpreterError::UnknownLayout(wide))
+    );
+    assert!(matches!(
+        run(src, "@huge_memset", &[]).0,
+        Err(InterpreterError::InvalidMemoryAccess(_))
+    ));
+}
+
+#[test]
+fn test_resource_limits() {
+    let src = "
+define void @rec() {
+body_0:
+  call @rec()
+  ret
+}
+define void @huge() {
+  ptr %p = alloca int8[2305843009213693952]
+body_0:
+  ret
+}
+define void @large() {
+  ptr %p = alloca int8[100000000000]
+body_0:
+  ret
+}
+define void @aligned() {
+  ptr %p = alloca int8 align 9223372036854775808
+body_0:
+  ret
+}";
+    assert_eq!(
+        run(src, "@rec", &[]).0,
+        Err(InterpreterError::CallDepthExceeded)
+    );
+    assert_eq!(
+        run(src, "@huge", &[]).0,
+        Err(InterpreterError::OutOfMemory(2305843009213693952))
+    );
+    assert_eq!(
+        run(src, "@large", &[]).0,
+        Err(InterpreterError::OutOfMemory(100000000000))
+    );
+    assert_eq!(
+        run(src, "@aligned", &[]).0,
+        Err(InterpreterError::OutOfMemory(1))
+    );
+}
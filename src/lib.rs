@@ -1,9 +1,27 @@
 pub mod backtrace;
+pub mod config;
+pub mod constant_propagation;
 pub mod control_flow_graph;
 pub mod diagnostics;
+pub mod hir_cst;
+pub mod hir_globals;
 pub mod hir_index;
+pub mod hir_opcodes;
+pub mod hir_operands;
 pub mod hir_parser;
+pub mod hir_printer;
 pub mod hir_tokenizer;
+pub mod hir_types;
+pub mod interpreter;
+pub mod liveness;
 pub mod lsp_utils;
+pub mod member_access;
+pub mod metrics;
+pub mod path_resolver;
+pub mod provenance;
 pub mod rename;
+pub mod selection_range;
 pub mod semantic_token;
+pub mod slice;
+pub mod ssa;
+pub mod validation;
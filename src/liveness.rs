@@ -0,0 +1,203 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    control_flow_graph::ControlFlowGraph,
+    hir_operands::split_operands,
+    hir_parser::{BasicBlock, Instruction},
+    hir_tokenizer::{Spanned, Token},
+};
+
+/// The SSA values live at the borders of a basic block
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockLiveness {
+    /// Live after the `phi`s at the beginning of the block, including the `phi` results
+    pub live_in: BTreeSet<String>,
+    /// Live at the end of the block, including the values the successors' `phi`s select
+    /// when coming from this block
+    pub live_out: BTreeSet<String>,
+    /// The maximum number of simultaneously live values within the block
+    pub max_pressure: usize,
+}
+
+fn local_names(tokens: &[Spanned<Token>]) -> impl Iterator<Item = &str> {
+    tokens.iter().filter_map(|t| match &t.0 {
+        Token::LocalName(name) => Some(name.as_str()),
+        _ => None,
+    })
+}
+
+fn is_phi(instr: &Instruction) -> bool {
+    instr.instruction.0 == "phi"
+}
+
+/// The values used by the `phi`s of a block, by the label of the incoming block
+fn phi_uses(bb: &BasicBlock) -> HashMap<&str, Vec<&str>> {
+    let mut uses = HashMap::<&str, Vec<&str>>::new();
+    for instr in bb.instructions.iter().filter(|i| is_phi(i)) {
+        for (label, incoming) in instr
+            .basic_block_refs
+            .iter()
+            .zip(split_operands(&instr.operands))
+        {
+            uses.entry(label.0.as_str())
+                .or_default()
+                .extend(local_names(incoming));
+        }
+    }
+    uses
+}
+
+/// Computes which SSA values are live at the beginning and end of each basic block.
+///
+/// Following SSA semantics, the operands of a `phi` are live at the end of the
+/// corresponding predecessor, not at the beginning of the `phi`'s block.
+pub fn liveness(basic_blocks: &[BasicBlock]) -> Vec<BlockLiveness> {
+    let cfg = ControlFlowGraph::new(basic_blocks);
+    let phi_uses = basic_blocks.iter().map(phi_uses).collect::<Vec<_>>();
+    let mut live_in = vec![BTreeSet::<&str>::new(); basic_blocks.len()];
+    let mut live_out = vec![BTreeSet::<&str>::new(); basic_blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        // Backwards problem: visit the blocks in reverse order, such that loop-free code
+        // converges in a single pass
+        for (b, bb) in basic_blocks.iter().enumerate().rev() {
+            let mut out = BTreeSet::new();
+            for &s in &cfg.successors[b] {
+                let phi_defs = basic_blocks[s]
+                    .instructions
+                    .iter()
+                    .filter(|i| is_phi(i))
+                    .filter_map(|i| Some(i.assignment_target.as_ref()?.0.as_str()))
+                    .collect::<BTreeSet<_>>();
+                out.extend(live_in[s].difference(&phi_defs));
+                if let Some(label) = &bb.label {
+                    out.extend(phi_uses[s].get(label.0.as_str()).into_iter().flatten());
+                }
+            }
+            let mut live = out.clone();
+            for instr in bb.instructions.iter().rev() {
+                if let Some(target) = &instr.assignment_target {
+                    live.remove(target.0.as_str());
+                }
+                if is_phi(instr) {
+                    live.extend(instr.assignment_target.as_ref().map(|t| t.0.as_str()));
+                } else {
+                    live.extend(local_names(&instr.operands));
+                }
+            }
+            if out != live_out[b] || live != live_in[b] {
+                live_out[b] = out;
+                live_in[b] = live;
+                changed = true;
+            }
+        }
+    }
+
+    basic_blocks
+        .iter()
+        .zip(live_in.iter().zip(&live_out))
+        .map(|(bb, (live_in, live_out))| {
+            // Walk backwards through the block, counting the live values between instructions.
+            // A value occupies a register from its definition on, even if it is never used.
+            let mut live = live_out.clone();
+            let mut max_pressure = live.len().max(live_in.len());
+            for instr in bb.instructions.iter().rev().filter(|i| !is_phi(i)) {
+                if let Some(target) = &instr.assignment_target {
+                    live.insert(target.0.as_str());
+                    max_pressure = max_pressure.max(live.len());
+                    live.remove(target.0.as_str());
+                }
+                live.extend(local_names(&instr.operands));
+                max_pressure = max_pressure.max(live.len());
+            }
+            BlockLiveness {
+                live_in: live_in.iter().map(|v| v.to_string()).collect(),
+                live_out: live_out.iter().map(|v| v.to_string()).collect(),
+                max_pressure,
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_liveness() {
+    let res = crate::hir_parser::parse_from_str(
+        "
+define int64 @f(int64 %n, int64 %m) {
+body_0:
+  int64 %a = add int64 %n, int64 1
+  int64 %unused = mul int64 %a, int64 %a
+  br loop_1
+loop_1:
+  int64 %i = phi [body_0, int64 0], [loop_1, int64 %next]
+  int64 %next = add int64 %i, int64 %a
+  int1 %done = ult int64 %next, int64 %m
+  br int1 %done, loop_1, exit_2
+exit_2:
+  ret int64 %next
+}",
+    );
+    let (_, body) = crate::hir_parser::first_function(&res);
+    let names = |set: &BTreeSet<String>| set.iter().cloned().collect::<Vec<_>>();
+    let blocks = liveness(&body.basic_blocks);
+    assert_eq!(names(&blocks[0].live_in), ["%m", "%n"]);
+    assert_eq!(names(&blocks[0].live_out), ["%a", "%m"]);
+    // `%next` flows into the `phi` along the back edge, but `%i` is not live around the loop
+    assert_eq!(names(&blocks[1].live_in), ["%a", "%i", "%m"]);
+    assert_eq!(names(&blocks[1].live_out), ["%a", "%m", "%next"]);
+    assert_eq!(names(&blocks[2].live_in), ["%next"]);
+    assert!(blocks[2].live_out.is_empty());
+    // `%a`, `%m` and the unused `%unused`
+    assert_eq!(blocks[0].max_pressure, 3);
+    // `%a`, `%m`, `%next` and `%done`
+    assert_eq!(blocks[1].max_pressure, 4);
+    assert_eq!(blocks[2].max_pressure, 1);
+}
+
+#[test]
+fn test_liveness_nested_loops() {
+    let res = crate::hir_parser::parse_from_str(
+        "
+define int64 @f(int64 %n) {
+body_0:
+  int64 %keep = add int64 %n, int64 1
+  br outer_1
+outer_1:
+  int64 %i = phi [body_0, int64 0], [latch_3, int64 %i2]
+  int64 %step = mul int64 %i, int64 2
+  br inner_2
+inner_2:
+  int64 %j = phi [outer_1, int64 0], [inner_2, int64 %j2]
+  int64 %j2 = add int64 %j, int64 %step
+  int1 %c = ult int64 %j2, int64 %n
+  br int1 %c, inner_2, latch_3
+latch_3:
+  int64 %i2 = add int64 %i, int64 1
+  int1 %d = ult int64 %i2, int64 %n
+  br int1 %d, outer_1, exit_4
+exit_4:
+  ret int64 %keep
+}",
+    );
+    let (_, body) = crate::hir_parser::first_function(&res);
+    let names = |set: &BTreeSet<String>| set.iter().cloned().collect::<Vec<_>>();
+    let blocks = liveness(&body.basic_blocks);
+    assert_eq!(names(&blocks[0].live_out), ["%keep", "%n"]);
+    // `%keep` is only used after the loops, but stays live along both back edges
+    assert_eq!(names(&blocks[1].live_in), ["%i", "%keep", "%n"]);
+    assert_eq!(names(&blocks[1].live_out), ["%i", "%keep", "%n", "%step"]);
+    assert_eq!(
+        names(&blocks[2].live_in),
+        ["%i", "%j", "%keep", "%n", "%step"]
+    );
+    assert_eq!(
+        names(&blocks[2].live_out),
+        ["%i", "%j2", "%keep", "%n", "%step"]
+    );
+    assert_eq!(names(&blocks[3].live_out), ["%i2", "%keep", "%n"]);
+    assert_eq!(names(&blocks[4].live_in), ["%keep"]);
+    let pressure = blocks.iter().map(|b| b.max_pressure).collect::<Vec<_>>();
+    // The inner loop additionally holds `%c`
+    assert_eq!(pressure, [2, 4, 6, 4, 1]);
+}
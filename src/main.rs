@@ -1,22 +1,44 @@
 use std::cell::Cell;
-use std::collections::HashMap;
-use std::sync::Mutex;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
 use hyper_ir_lsp::backtrace::{
-    inlay_hint_for_backtrace, parse_backtrace_from_json, resolve_relative_path,
+    find_source_references, inlay_hint_for_backtrace, parse_backtrace_from_json, SourceReference,
 };
+use hyper_ir_lsp::config::Config;
+use hyper_ir_lsp::constant_propagation::propagate_constants;
 use hyper_ir_lsp::control_flow_graph::create_cfg_dot_visualization;
 use hyper_ir_lsp::diagnostics::{
-    diagnostics_from_index, diagnostics_from_parser, diagnostics_from_statements,
+    collect_diagnostics, file_diagnostics, hash_result_id, QuickFix, DIAGNOSTIC_SOURCE,
 };
+use hyper_ir_lsp::hir_cst::{build_cst, Cst, CstNodeKind};
+use hyper_ir_lsp::hir_globals::{describe_bytes, parse_initializer};
 use hyper_ir_lsp::hir_index::{create_index, HIRIndex, SymbolOccurrence, UseDefKind, UseDefList};
-use hyper_ir_lsp::hir_parser::{parse_from_str, BasicBlock, Instruction, ParserResult, Statement};
+use hyper_ir_lsp::hir_operands::{argument_parameters, split_call};
+use hyper_ir_lsp::hir_parser::{
+    parse_from_str, BasicBlock, FuncBody, FuncSignature, Instruction, ParserResult, Statement,
+};
+use hyper_ir_lsp::hir_printer::print_cst;
+use hyper_ir_lsp::hir_tokenizer::{Span, Token};
+use hyper_ir_lsp::hir_types::{parse_type_from_str, IrType};
+use hyper_ir_lsp::interpreter::{parse_argument, Interpreter, StubExternalFunctions, Val};
+use hyper_ir_lsp::liveness::liveness;
 use hyper_ir_lsp::lsp_utils::{lsp_pos_to_offset, offset_to_lsp_pos, range_to_lsp};
+use hyper_ir_lsp::member_access::{parse_member_access, FieldNames};
+use hyper_ir_lsp::metrics::{body_metrics, defined_functions, function_metrics, FunctionMetrics};
+use hyper_ir_lsp::path_resolver::PathResolver;
+use hyper_ir_lsp::provenance::{provenance_tree, ProvenanceKind, ProvenanceNode};
 use hyper_ir_lsp::rename::{extract_number_from_identifier, get_rename_edits, get_shift_edits};
+use hyper_ir_lsp::selection_range::selection_spans;
 use hyper_ir_lsp::semantic_token::{
-    convert_to_lsp_tokens, semantic_tokens_from_tokens, HIRSemanticToken, LEGEND_TYPE,
+    convert_to_lsp_tokens, semantic_tokens_delta, semantic_tokens_from_index,
+    semantic_tokens_in_range, HIRSemanticToken, LEGEND_MODIFIER, LEGEND_TYPE,
 };
+use hyper_ir_lsp::slice::{forward_slice, SliceKind};
+use hyper_ir_lsp::ssa::{value_defs, value_uses, ValueDef};
 use ropey::Rope;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -27,17 +49,80 @@ use tower_lsp::{Client, LanguageServer, LspService, Server};
 
 #[derive(Debug)]
 struct AnalyzedDocument {
+    version: i32,
     rope: Rope,
     semantic_tokens: Vec<HIRSemanticToken>,
+    /// The semantic tokens last sent to the client, used to compute deltas
+    semantic_tokens_result: Option<(String, Vec<SemanticToken>)>,
     stmts: Vec<Statement>,
+    cst: Cst,
     index: HIRIndex,
+    source_refs: Vec<SourceReference>,
+    diagnostics: Vec<Diagnostic>,
+    diagnostics_result_id: String,
+}
+
+/// Parses and analyzes an opened document
+fn analyze_document(
+    uri: &Url,
+    text: &str,
+    version: i32,
+    path_resolver: &PathResolver,
+    config: &Config,
+) -> AnalyzedDocument {
+    let rope = ropey::Rope::from_str(text);
+    let src = rope.to_string();
+    let ParserResult {
+        tokens,
+        stmts,
+        errors,
+    } = parse_from_str(&src);
+    let cst = build_cst(&src, &tokens, &stmts);
+    let index = create_index(&src, &tokens, &stmts);
+    let source_refs = find_source_references(&stmts);
+    let semantic_tokens = semantic_tokens_from_index(&tokens, &stmts, &index);
+    let diagnostics = collect_diagnostics(
+        uri,
+        &rope,
+        &errors,
+        &stmts,
+        &index,
+        &source_refs,
+        path_resolver,
+        config,
+    );
+    let diagnostics_result_id = hash_result_id(&diagnostics);
+    AnalyzedDocument {
+        version,
+        rope,
+        semantic_tokens,
+        semantic_tokens_result: None,
+        stmts,
+        cst,
+        index,
+        source_refs,
+        diagnostics,
+        diagnostics_result_id,
+    }
 }
 
 #[derive(Debug)]
 struct Backend {
     client: Client,
-    root_paths: Mutex<Vec<Url>>,
+    config: Mutex<Config>,
+    path_resolver: Mutex<Arc<PathResolver>>,
     code_actions_lazy_resolve: Mutex<Cell<bool>>,
+    semantic_tokens_result_id: AtomicU64,
+    pull_diagnostics: AtomicBool,
+    /// Whether the client can watch files for us, such that we notice new source files
+    watch_files: AtomicBool,
+    /// Set by file watcher events which are not yet handled
+    files_changed: AtomicBool,
+    refreshing_files: AtomicBool,
+    /// Whether the client supports `workspace/inlayHint/refresh`
+    inlay_hint_refresh: AtomicBool,
+    /// Held for writing while a document change is analyzed, see `wait_for_analysis`
+    analysis_lock: tokio::sync::RwLock<()>,
     document_map: DashMap<String, AnalyzedDocument>,
 }
 
@@ -55,14 +140,46 @@ enum CodeActionData {
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
     async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        let mut root_paths = vec![];
         if let Some(workspace_folders) = params.workspace_folders {
-            self.root_paths
-                .lock()
-                .unwrap()
-                .extend(workspace_folders.iter().map(|f| f.uri.clone()));
+            root_paths.extend(workspace_folders.iter().map(|f| f.uri.clone()));
         } else if let Some(root_uri) = params.root_uri {
-            self.root_paths.lock().unwrap().push(root_uri);
+            root_paths.push(root_uri);
         }
+        let config = params
+            .initialization_options
+            .as_ref()
+            .map(Config::from_settings)
+            .unwrap_or_default();
+        *self.path_resolver.lock().unwrap() = Arc::new(PathResolver::new(
+            root_paths,
+            config.path_substitutions.clone(),
+        ));
+        *self.config.lock().unwrap() = config;
+        let pull_diagnostics = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .is_some_and(|c| c.diagnostic.is_some());
+        self.pull_diagnostics
+            .store(pull_diagnostics, Ordering::Relaxed);
+        let watch_files = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|w| w.did_change_watched_files)
+            .and_then(|c| c.dynamic_registration)
+            .unwrap_or(false);
+        self.watch_files.store(watch_files, Ordering::Relaxed);
+        let inlay_hint_refresh = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|w| w.inlay_hint.as_ref())
+            .and_then(|c| c.refresh_support)
+            .unwrap_or(false);
+        self.inlay_hint_refresh
+            .store(inlay_hint_refresh, Ordering::Relaxed);
         let code_actions_lazy_resolve = params
             .capabilities
             .text_document
@@ -107,10 +224,10 @@ impl LanguageServer for Backend {
                                 work_done_progress_options: WorkDoneProgressOptions::default(),
                                 legend: SemanticTokensLegend {
                                     token_types: LEGEND_TYPE.into(),
-                                    token_modifiers: vec![],
+                                    token_modifiers: LEGEND_MODIFIER.into(),
                                 },
-                                range: None,
-                                full: Some(SemanticTokensFullOptions::Bool(true)),
+                                range: Some(true),
+                                full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
                             },
                             static_registration_options: StaticRegistrationOptions::default(),
                         },
@@ -120,7 +237,23 @@ impl LanguageServer for Backend {
                 definition_provider: Some(OneOf::Left(true)),
                 declaration_provider: Some(DeclarationCapability::Simple(true)),
                 references_provider: Some(OneOf::Left(true)),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: None,
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
+                document_highlight_provider: Some(OneOf::Left(true)),
                 folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                diagnostic_provider: Some(DiagnosticServerCapabilities::Options(
+                    DiagnosticOptions {
+                        identifier: Some("hyper-ir".to_string()),
+                        inter_file_dependencies: false,
+                        workspace_diagnostics: true,
+                        work_done_progress_options: WorkDoneProgressOptions::default(),
+                    },
+                )),
+                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
                 inlay_hint_provider: Some(OneOf::Left(true)),
                 code_lens_provider: Some(CodeLensOptions {
                     resolve_provider: None,
@@ -141,7 +274,10 @@ impl LanguageServer for Backend {
                     },
                 )),
                 execute_command_provider: Some(ExecuteCommandOptions {
-                    commands: vec!["visualize-cfg".to_string()],
+                    commands: vec![
+                        "visualize-cfg".to_string(),
+                        "visualize-register-pressure".to_string(),
+                    ],
                     ..Default::default()
                 }),
                 ..ServerCapabilities::default()
@@ -154,6 +290,21 @@ impl LanguageServer for Backend {
         self.client
             .log_message(MessageType::INFO, format!("initialized (lazy: {})", lazy))
             .await;
+        if self.watch_files.load(Ordering::Relaxed) {
+            let options = DidChangeWatchedFilesRegistrationOptions {
+                watchers: vec![FileSystemWatcher {
+                    glob_pattern: GlobPattern::String("**/*".to_string()),
+                    kind: Some(WatchKind::Create | WatchKind::Delete),
+                }],
+            };
+            let registration = Registration {
+                id: "workspace-files".to_string(),
+                method: "workspace/didChangeWatchedFiles".to_string(),
+                register_options: serde_json::to_value(options).ok(),
+            };
+            let _ = self.client.register_capability(vec![registration]).await;
+        }
+        self.refresh_workspace_files().await;
     }
 
     async fn shutdown(&self) -> Result<()> {
@@ -169,6 +320,42 @@ impl LanguageServer for Backend {
         .await
     }
 
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        let config = Config::from_settings(&params.settings);
+        let root_paths = self.path_resolver().root_paths.clone();
+        *self.path_resolver.lock().unwrap() = Arc::new(PathResolver::new(
+            root_paths,
+            config.path_substitutions.clone(),
+        ));
+        *self.config.lock().unwrap() = config;
+        self.refresh_workspace_files().await;
+        // The inlay hints depend on the configuration
+        if self.inlay_hint_refresh.load(Ordering::Relaxed) {
+            let _ = self.client.inlay_hint_refresh().await;
+        }
+    }
+
+    async fn did_change_watched_files(&self, _: DidChangeWatchedFilesParams) {
+        self.path_resolver().invalidate();
+        self.files_changed.store(true, Ordering::SeqCst);
+        // Builds create and delete many files at once. Only one refresh runs at a time, and
+        // it picks up all events which arrived in the meantime.
+        loop {
+            if self.refreshing_files.swap(true, Ordering::SeqCst) {
+                return;
+            }
+            while self.files_changed.load(Ordering::SeqCst) {
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                self.files_changed.store(false, Ordering::SeqCst);
+                self.refresh_workspace_files().await;
+            }
+            self.refreshing_files.store(false, Ordering::SeqCst);
+            if !self.files_changed.load(Ordering::SeqCst) {
+                return;
+            }
+        }
+    }
+
     async fn did_change(&self, mut params: DidChangeTextDocumentParams) {
         self.on_change(TextDocumentItem {
             uri: params.text_document.uri,
@@ -182,6 +369,7 @@ impl LanguageServer for Backend {
         &self,
         params: GotoDefinitionParams,
     ) -> Result<Option<GotoDefinitionResponse>> {
+        self.wait_for_analysis().await;
         let definition = || -> Option<GotoDefinitionResponse> {
             let (origin_selection_range, mut locations) =
                 self.get_use_def_ranges(&params.text_document_position_params, UseDefKind::Def)?;
@@ -203,6 +391,7 @@ impl LanguageServer for Backend {
         &self,
         params: GotoDefinitionParams,
     ) -> Result<Option<GotoDefinitionResponse>> {
+        self.wait_for_analysis().await;
         let decl = || -> Option<GotoDeclarationResponse> {
             let (origin_selection_range, mut locations) =
                 self.get_use_def_ranges(&params.text_document_position_params, UseDefKind::Decl)?;
@@ -221,35 +410,286 @@ impl LanguageServer for Backend {
     }
 
     async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        self.wait_for_analysis().await;
         let reference_list = self
             .get_use_def_ranges(&params.text_document_position, UseDefKind::Use)
             .map(|x| x.1);
         Ok(reference_list)
     }
 
+    async fn diagnostic(
+        &self,
+        params: DocumentDiagnosticParams,
+    ) -> Result<DocumentDiagnosticReportResult> {
+        self.wait_for_analysis().await;
+        let uri = params.text_document.uri;
+        let previous_result_id = params.previous_result_id;
+        let open_document = self.document_map.get(&uri.to_string()).map(|doc| {
+            if previous_result_id.as_ref() == Some(&doc.diagnostics_result_id) {
+                (doc.diagnostics_result_id.clone(), None)
+            } else {
+                (
+                    doc.diagnostics_result_id.clone(),
+                    Some(doc.diagnostics.clone()),
+                )
+            }
+        });
+        let (result_id, diagnostics) = match open_document {
+            Some(result) => result,
+            None => self
+                .get_file_diagnostics(&uri, previous_result_id)
+                .await
+                .ok_or_else(|| Error::invalid_params("Document not found"))?,
+        };
+        let report = match diagnostics {
+            Some(items) => DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+                related_documents: None,
+                full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                    result_id: Some(result_id),
+                    items,
+                },
+            }),
+            None => DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+                related_documents: None,
+                unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+                    result_id,
+                },
+            }),
+        };
+        Ok(DocumentDiagnosticReportResult::Report(report))
+    }
+
+    async fn workspace_diagnostic(
+        &self,
+        params: WorkspaceDiagnosticParams,
+    ) -> Result<WorkspaceDiagnosticReportResult> {
+        self.wait_for_analysis().await;
+        let previous_result_ids = params
+            .previous_result_ids
+            .into_iter()
+            .map(|p| (p.uri.to_string(), p.value))
+            .collect::<HashMap<_, _>>();
+
+        // Open documents
+        let mut items = vec![];
+        for doc in self.document_map.iter() {
+            let Ok(uri) = Url::parse(doc.key()) else {
+                continue;
+            };
+            let version = Some(doc.version as i64);
+            let result_id = doc.diagnostics_result_id.clone();
+            if previous_result_ids.get(doc.key()) == Some(&result_id) {
+                items.push(WorkspaceDocumentDiagnosticReport::Unchanged(
+                    WorkspaceUnchangedDocumentDiagnosticReport {
+                        uri,
+                        version,
+                        unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+                            result_id,
+                        },
+                    },
+                ));
+            } else {
+                items.push(WorkspaceDocumentDiagnosticReport::Full(
+                    WorkspaceFullDocumentDiagnosticReport {
+                        uri,
+                        version,
+                        full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                            result_id: Some(result_id),
+                            items: doc.diagnostics.clone(),
+                        },
+                    },
+                ));
+            }
+        }
+
+        // All other Hyper IR files in the workspace. We list the files again, such that new
+        // files are reported, and analyze them off the async executor.
+        let open_documents = self
+            .document_map
+            .iter()
+            .map(|doc| doc.key().clone())
+            .collect::<HashSet<_>>();
+        let path_resolver = self.path_resolver();
+        let config = self.config.lock().unwrap().clone();
+        let files = tokio::task::spawn_blocking(move || {
+            path_resolver
+                .list_workspace_files()
+                .iter()
+                .filter(|f| f.extension().is_some_and(|e| e == "hir"))
+                .filter_map(|f| Some((f, Url::from_file_path(f).ok()?)))
+                .filter(|(_, uri)| !open_documents.contains(&uri.to_string()))
+                .filter_map(|(path, uri)| {
+                    let previous_result_id = previous_result_ids.get(&uri.to_string());
+                    let (result_id, diagnostics) = file_diagnostics(
+                        path,
+                        previous_result_id.map(|id| id.as_str()),
+                        &path_resolver,
+                        &config,
+                    )?;
+                    Some((uri, result_id, diagnostics))
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+        for (uri, result_id, diagnostics) in files {
+            items.push(match diagnostics {
+                Some(items) => {
+                    WorkspaceDocumentDiagnosticReport::Full(WorkspaceFullDocumentDiagnosticReport {
+                        uri,
+                        version: None,
+                        full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                            result_id: Some(result_id),
+                            items,
+                        },
+                    })
+                }
+                None => WorkspaceDocumentDiagnosticReport::Unchanged(
+                    WorkspaceUnchangedDocumentDiagnosticReport {
+                        uri,
+                        version: None,
+                        unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+                            result_id,
+                        },
+                    },
+                ),
+            });
+        }
+
+        Ok(WorkspaceDiagnosticReportResult::Report(
+            WorkspaceDiagnosticReport { items },
+        ))
+    }
+
+    async fn document_link(&self, params: DocumentLinkParams) -> Result<Option<Vec<DocumentLink>>> {
+        self.wait_for_analysis().await;
+        let uri = params.text_document.uri.to_string();
+        let Some(refs) = self.document_map.get(&uri).map(|doc| {
+            doc.source_refs
+                .iter()
+                .filter_map(|r| Some((range_to_lsp(&doc.rope, &r.span)?, r.clone())))
+                .collect::<Vec<_>>()
+        }) else {
+            return Ok(None);
+        };
+        // Resolving touches the file system, so keep it off the async executor
+        let path_resolver = self.path_resolver();
+        let links = tokio::task::spawn_blocking(move || {
+            refs.into_iter()
+                .filter_map(|(range, r)| {
+                    let mut target = path_resolver.resolve(&r.filepath)?;
+                    target.set_fragment(Some(&r.line.to_string()));
+                    Some(DocumentLink {
+                        range,
+                        target: Some(target),
+                        tooltip: Some(format!("{}:{}", r.filepath, r.line)),
+                        data: None,
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .ok();
+        Ok(links)
+    }
+
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> Result<Option<Vec<DocumentHighlight>>> {
+        self.wait_for_analysis().await;
+        let pos = params.text_document_position_params;
+        let highlights = || -> Option<Vec<DocumentHighlight>> {
+            let uri_str = pos.text_document.uri.to_string();
+            let doc = self.document_map.get(&uri_str)?;
+            let offset = lsp_pos_to_offset(&doc.rope, &pos.position)?;
+            let highlights = doc
+                .index
+                .occurrences_at_position(offset)?
+                .into_iter()
+                .filter_map(|(ud, span)| {
+                    let kind = match ud {
+                        UseDefKind::Decl | UseDefKind::Def => DocumentHighlightKind::WRITE,
+                        UseDefKind::Use => DocumentHighlightKind::READ,
+                    };
+                    Some(DocumentHighlight {
+                        range: range_to_lsp(&doc.rope, &span)?,
+                        kind: Some(kind),
+                    })
+                })
+                .collect::<Vec<_>>();
+            Some(highlights)
+        }();
+        Ok(highlights)
+    }
+
     async fn semantic_tokens_full(
         &self,
         params: SemanticTokensParams,
     ) -> Result<Option<SemanticTokensResult>> {
+        self.wait_for_analysis().await;
+        let uri = params.text_document.uri.to_string();
+        Ok(self
+            .get_full_semantic_tokens(&uri)
+            .map(SemanticTokensResult::Tokens))
+    }
+
+    async fn semantic_tokens_full_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Result<Option<SemanticTokensFullDeltaResult>> {
+        self.wait_for_analysis().await;
+        let uri = params.text_document.uri.to_string();
+        let delta = || -> Option<SemanticTokensDelta> {
+            let mut doc = self.document_map.get_mut(&uri)?;
+            let (previous_id, previous_tokens) = doc.semantic_tokens_result.as_ref()?;
+            if *previous_id != params.previous_result_id {
+                return None;
+            }
+            let lsp_tokens = convert_to_lsp_tokens(&doc.rope, &doc.semantic_tokens);
+            let edits = semantic_tokens_delta(previous_tokens, &lsp_tokens);
+            let result_id = self.next_result_id();
+            doc.semantic_tokens_result = Some((result_id.clone(), lsp_tokens));
+            Some(SemanticTokensDelta {
+                result_id: Some(result_id),
+                edits,
+            })
+        }();
+        // If we don't know the previous result anymore, fall back to sending all tokens
+        if let Some(delta) = delta {
+            return Ok(Some(SemanticTokensFullDeltaResult::TokensDelta(delta)));
+        }
+        Ok(self
+            .get_full_semantic_tokens(&uri)
+            .map(SemanticTokensFullDeltaResult::Tokens))
+    }
+
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Result<Option<SemanticTokensRangeResult>> {
+        self.wait_for_analysis().await;
         let uri = params.text_document.uri.to_string();
         let lsp_tokens = || -> Option<Vec<SemanticToken>> {
             let doc = self.document_map.get(&uri)?;
-            let lsp_tokens = convert_to_lsp_tokens(&doc.rope, &doc.semantic_tokens);
-            Some(lsp_tokens)
+            let span = lsp_pos_to_offset(&doc.rope, &params.range.start)?
+                ..lsp_pos_to_offset(&doc.rope, &params.range.end)?;
+            let semtoks = semantic_tokens_in_range(&doc.semantic_tokens, &span);
+            Some(convert_to_lsp_tokens(&doc.rope, semtoks))
         }();
-        if let Some(semantic_token) = lsp_tokens {
-            return Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
+        Ok(lsp_tokens.map(|data| {
+            SemanticTokensRangeResult::Tokens(SemanticTokens {
                 result_id: None,
-                data: semantic_token,
-            })));
-        }
-        Ok(None)
+                data,
+            })
+        }))
     }
 
     async fn document_symbol(
         &self,
         params: DocumentSymbolParams,
     ) -> Result<Option<DocumentSymbolResponse>> {
+        self.wait_for_analysis().await;
         let uri = params.text_document.uri.to_string();
         let symbols = || -> Option<DocumentSymbolResponse> {
             let doc = self.document_map.get(&uri)?;
@@ -310,6 +750,7 @@ impl LanguageServer for Backend {
         &self,
         pos: TextDocumentPositionParams,
     ) -> Result<Option<PrepareRenameResponse>> {
+        self.wait_for_analysis().await;
         Ok(|| -> Option<PrepareRenameResponse> {
             let uri_str = pos.text_document.uri.to_string();
             let doc = self.document_map.get(&uri_str)?;
@@ -321,6 +762,7 @@ impl LanguageServer for Backend {
     }
 
     async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        self.wait_for_analysis().await;
         // Find the document
         let pos = params.text_document_position;
         let uri_str = pos.text_document.uri.to_string();
@@ -402,6 +844,7 @@ impl LanguageServer for Backend {
     }
 
     async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        self.wait_for_analysis().await;
         // Find the document
         let uri_str = params.text_document.uri.to_string();
         let doc = self
@@ -409,10 +852,35 @@ impl LanguageServer for Backend {
             .get(&uri_str)
             .ok_or_else(|| Error::invalid_params("Document not found"))?;
 
+        // Quick fixes for our diagnostics. Those carry their edit inside the `data`.
+        let quick_fixes = params
+            .context
+            .diagnostics
+            .iter()
+            .filter(|d| d.source.as_deref() == Some(DIAGNOSTIC_SOURCE))
+            .filter_map(|d| {
+                let fix = serde_json::from_value::<QuickFix>(d.data.clone()?).ok()?;
+                Some(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: fix.title,
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![d.clone()]),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(
+                            params.text_document.uri.clone(),
+                            vec![fix.edit],
+                        )])),
+                        ..Default::default()
+                    }),
+                    is_preferred: Some(true),
+                    ..Default::default()
+                }))
+            })
+            .collect::<Vec<_>>();
+
         // We don't support ranges, yet
         let range = params.range;
         if range.start != range.end {
-            return Ok(None);
+            return Ok(Some(quick_fixes));
         }
 
         let mut actions = vec![];
@@ -456,18 +924,20 @@ impl LanguageServer for Backend {
         }
 
         Ok(Some(
-            actions
-                .drain(..)
-                .map(CodeActionOrCommand::CodeAction)
+            quick_fixes
+                .into_iter()
+                .chain(actions.drain(..).map(CodeActionOrCommand::CodeAction))
                 .collect(),
         ))
     }
 
     async fn code_action_resolve(&self, action: CodeAction) -> Result<CodeAction> {
+        self.wait_for_analysis().await;
         self.do_code_action_resolve(action)
     }
 
     async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        self.wait_for_analysis().await;
         let folding_ranges = || -> Option<Vec<FoldingRange>> {
             let uri = &params.text_document.uri;
             let uri_str = uri.to_string();
@@ -511,11 +981,203 @@ impl LanguageServer for Backend {
         return Ok(folding_ranges);
     }
 
+    async fn selection_range(
+        &self,
+        params: SelectionRangeParams,
+    ) -> Result<Option<Vec<SelectionRange>>> {
+        self.wait_for_analysis().await;
+        let uri = params.text_document.uri.to_string();
+        let selection_ranges = || -> Option<Vec<SelectionRange>> {
+            let doc = self.document_map.get(&uri)?;
+            params
+                .positions
+                .iter()
+                .map(|pos| {
+                    let offset = lsp_pos_to_offset(&doc.rope, pos)?;
+                    let spans = selection_spans(&doc.cst, &doc.index, doc.rope.len_chars(), offset);
+                    // Nest the ranges, starting with the outermost one
+                    spans.iter().rev().try_fold(None, |parent, span| {
+                        Some(Some(SelectionRange {
+                            range: range_to_lsp(&doc.rope, span)?,
+                            parent: parent.map(Box::new),
+                        }))
+                    })?
+                })
+                .collect::<Option<Vec<_>>>()
+        }();
+        Ok(selection_ranges)
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        self.wait_for_analysis().await;
+        let edits = || -> Option<Vec<TextEdit>> {
+            let uri_str = params.text_document.uri.to_string();
+            let doc = self.document_map.get(&uri_str)?;
+            let formatted = print_cst(&doc.cst, &doc.stmts);
+            if doc.rope == formatted.as_str() {
+                return Some(vec![]);
+            }
+            Some(vec![TextEdit {
+                range: Range {
+                    start: Position::new(0, 0),
+                    end: offset_to_lsp_pos(&doc.rope, doc.rope.len_bytes())?,
+                },
+                new_text: formatted,
+            }])
+        }();
+        Ok(edits)
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        self.wait_for_analysis().await;
+        let hover = || -> Option<Hover> {
+            let pos = &params.text_document_position_params;
+            let uri_str = pos.text_document.uri.to_string();
+            let doc = self.document_map.get(&uri_str)?;
+            let offset = lsp_pos_to_offset(&doc.rope, &pos.position)?;
+
+            // Show the documentation of the opcode. Unknown opcodes fall through to the comments.
+            if let Some((_, instruction)) = doc.index.find_instruction_at_position(offset) {
+                if let (true, Some(spec)) = (
+                    instruction.instruction.1.contains(&offset),
+                    instruction.opcode(),
+                ) {
+                    return Some(Hover {
+                        contents: HoverContents::Markup(MarkupContent {
+                            kind: MarkupKind::Markdown,
+                            value: spec.hover_markdown(),
+                        }),
+                        range: range_to_lsp(&doc.rope, &instruction.instruction.1),
+                    });
+                }
+            }
+
+            // Decode the initializer of global variables
+            if let Some(symbol) = doc.index.find_symbol_at_position(offset) {
+                let init = match symbol.symbol_kind {
+                    hyper_ir_lsp::hir_index::SymbolKind::GlobalVar => {
+                        doc.stmts.iter().find_map(|s| match s {
+                            Statement::GlobalVar { name, def } if name.0 == symbol.name => {
+                                parse_initializer(def)
+                            }
+                            _ => None,
+                        })
+                    }
+                    _ => None,
+                };
+                if let Some(init) = init {
+                    let mut value = format!("```hir\n{} : {}\n```\n", symbol.name, init.type_());
+                    if let Some(bytes) = init.to_bytes() {
+                        value.push_str(&format!("```\n{}```", describe_bytes(&bytes)));
+                    }
+                    return Some(Hover {
+                        contents: HoverContents::Markup(MarkupContent {
+                            kind: MarkupKind::Markdown,
+                            value,
+                        }),
+                        range: range_to_lsp(&doc.rope, &symbol.span),
+                    });
+                }
+            }
+
+            // Summarize the size and shape of functions
+            if let Some(symbol) = doc.index.find_symbol_at_position(offset) {
+                let metrics = match symbol.symbol_kind {
+                    hyper_ir_lsp::hir_index::SymbolKind::Function => {
+                        doc.stmts.iter().find_map(|s| match s {
+                            Statement::FuncDef {
+                                signature, body, ..
+                            } if signature.name.0 == symbol.name => Some(body_metrics(
+                                &symbol.name,
+                                body,
+                                &defined_functions(&doc.stmts),
+                            )),
+                            _ => None,
+                        })
+                    }
+                    _ => None,
+                };
+                // Declared functions are implemented inside Hyper, so we know nothing about them
+                if let Some(metrics) = metrics {
+                    return Some(Hover {
+                        contents: HoverContents::Markup(MarkupContent {
+                            kind: MarkupKind::Markdown,
+                            value: format!("`{}`\n\n{}", metrics.name, metrics.hover_markdown()),
+                        }),
+                        range: range_to_lsp(&doc.rope, &symbol.span),
+                    });
+                }
+            }
+
+            // Explain which member is accessed by `gep`, `load` and `store`
+            if let Some((_, instruction)) = doc.index.find_instruction_at_position(offset) {
+                if let Some(access) = parse_member_access(instruction) {
+                    let operands = &instruction.operands;
+                    let operands_span = operands.first()?.1.start..operands.last()?.1.end;
+                    if operands_span.contains(&offset) {
+                        let field_names =
+                            FieldNames::new(&self.config.lock().unwrap().struct_names);
+                        let member = access.resolve(&field_names);
+                        let mut value = format!("`{}`\n\n", member.path);
+                        for (struct_type, idx) in &member.fields {
+                            value.push_str(&format!("* Field {} of `{}`\n", idx, struct_type));
+                        }
+                        if let Some(type_) = &member.type_ {
+                            value.push_str(&format!("* Type: `{}`\n", type_));
+                        }
+                        match member.offset {
+                            Some(offset) => value.push_str(&format!("* Byte offset: {}\n", offset)),
+                            None => value.push_str("* Byte offset: unknown\n"),
+                        }
+                        return Some(Hover {
+                            contents: HoverContents::Markup(MarkupContent {
+                                kind: MarkupKind::Markdown,
+                                value,
+                            }),
+                            range: range_to_lsp(&doc.rope, &operands_span),
+                        });
+                    }
+                }
+            }
+
+            // Show the comments attached to an instruction as its documentation
+            let node = doc.cst.find_node_at(offset, CstNodeKind::Instruction)?;
+            let comments = doc
+                .cst
+                .leading_comments(node)
+                .into_iter()
+                .chain(doc.cst.trailing_comment(node))
+                .map(|c| c.trim_start_matches('#').trim())
+                .filter(|c| !c.is_empty())
+                .collect::<Vec<_>>();
+            if comments.is_empty() {
+                return None;
+            }
+            Some(Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::PlainText,
+                    value: comments.join("\n"),
+                }),
+                range: range_to_lsp(&doc.rope, &node.span),
+            })
+        }();
+        Ok(hover)
+    }
+
     async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        self.wait_for_analysis().await;
         let inlay_hints = || -> Option<Vec<InlayHint>> {
             let uri = &params.text_document.uri;
             let uri_str = uri.to_string();
             let doc = self.document_map.get(&uri_str)?;
+            let path_resolver = self.path_resolver();
+            let (hints_config, field_names) = {
+                let config = self.config.lock().unwrap();
+                (
+                    config.inlay_hints.clone(),
+                    FieldNames::new(&config.struct_names),
+                )
+            };
             let mut inlay_hints: Vec<InlayHint> = Vec::<InlayHint>::new();
 
             // Insert back references for each basic block which point back to the incoming edges
@@ -584,9 +1246,7 @@ impl LanguageServer for Backend {
                         let dbg_ref = stmt.dbg_ref.as_ref()?;
                         let json_txt = doc.index.dgb_annotation_values.get(&dbg_ref.0)?;
                         let json_val = serde_json::from_str::<serde_json::Value>(json_txt).ok()?;
-                        let root_paths: std::sync::MutexGuard<'_, Vec<Url>> =
-                            self.root_paths.lock().unwrap();
-                        let frames = parse_backtrace_from_json(&root_paths, json_val)?;
+                        let frames = parse_backtrace_from_json(&path_resolver, json_val)?;
                         Some(inlay_hint_for_backtrace(
                             offset_to_lsp_pos(&doc.rope, dbg_ref.1.end)?,
                             &frames,
@@ -594,6 +1254,202 @@ impl LanguageServer for Backend {
                     }),
             );
 
+            // Show the parameter names in front of call arguments
+            if hints_config.parameter_names {
+                inlay_hints.extend(argument_parameters(&doc.stmts).into_iter().filter_map(
+                    |param| {
+                        Some(InlayHint {
+                            position: offset_to_lsp_pos(&doc.rope, param.span.start)?,
+                            label: InlayHintLabel::LabelParts(vec![
+                                InlayHintLabelPart {
+                                    value: param.label,
+                                    location: Some(Location {
+                                        uri: uri.clone(),
+                                        range: range_to_lsp(&doc.rope, &param.parameter_span)?,
+                                    }),
+                                    ..Default::default()
+                                },
+                                InlayHintLabelPart {
+                                    value: ":".to_string(),
+                                    ..Default::default()
+                                },
+                            ]),
+                            kind: Some(InlayHintKind::PARAMETER),
+                            text_edits: None,
+                            tooltip: Some(InlayHintTooltip::String(param.description)),
+                            padding_left: None,
+                            padding_right: Some(true),
+                            data: None,
+                        })
+                    },
+                ));
+            }
+
+            // Show the types of SSA values at their uses, unless they are spelled out already.
+            // Pointers computed by `gep` also show the accessed member.
+            if hints_config.value_types {
+                for (signature, body) in doc.stmts.iter().filter_map(|s| match s {
+                    Statement::FuncDef {
+                        signature, body, ..
+                    } => Some((signature, body)),
+                    _ => None,
+                }) {
+                    let defs = value_defs(signature, body);
+                    inlay_hints.extend(value_uses(body).filter_map(|value_use| {
+                        let def = defs.get(value_use.name())?;
+                        let (def_type, def_name) = def.declaration()?;
+                        let mut label = vec![];
+                        if value_use.written_type != Some(def_type.0.as_str()) {
+                            label.push(InlayHintLabelPart {
+                                value: format!(": {}", def_type.0),
+                                location: Some(Location {
+                                    uri: uri.clone(),
+                                    range: range_to_lsp(&doc.rope, &def_name.1)?,
+                                }),
+                                ..Default::default()
+                            });
+                        }
+                        if let ValueDef::Instruction(instr) = def {
+                            if instr.instruction.0 == "gep" {
+                                if let Some(access) = parse_member_access(instr) {
+                                    label.push(InlayHintLabelPart {
+                                        value: format!("→ {}", access.resolve(&field_names).path),
+                                        tooltip: Some(InlayHintLabelPartTooltip::String(
+                                            access.type_.to_string(),
+                                        )),
+                                        ..Default::default()
+                                    });
+                                }
+                            }
+                        }
+                        if label.is_empty() {
+                            return None;
+                        }
+                        Some(InlayHint {
+                            position: offset_to_lsp_pos(&doc.rope, value_use.name.1.end)?,
+                            label: InlayHintLabel::LabelParts(label),
+                            kind: Some(InlayHintKind::TYPE),
+                            text_edits: None,
+                            tooltip: None,
+                            padding_left: Some(true),
+                            padding_right: None,
+                            data: None,
+                        })
+                    }));
+                }
+            }
+
+            // Show the member accessed by `gep`, `load` and `store`
+            if hints_config.member_accesses {
+                inlay_hints.extend(
+                    doc.index
+                        .function_bodies
+                        .iter()
+                        .flat_map(|f| &f.basic_blocks)
+                        .flat_map(|bb| &bb.instructions)
+                        .filter_map(|instr| {
+                            let access = parse_member_access(instr)?;
+                            if access.indices.is_empty() {
+                                return None;
+                            }
+                            let member = access.resolve(&field_names);
+                            let mut label = format!("→ {}", member.path);
+                            if let Some(type_) = &member.type_ {
+                                label.push_str(&format!(": {}", type_));
+                            }
+                            if let Some(offset) = member.offset {
+                                label.push_str(&format!(" @ {}", offset));
+                            }
+                            Some(InlayHint {
+                                position: offset_to_lsp_pos(
+                                    &doc.rope,
+                                    instr.operands.last()?.1.end,
+                                )?,
+                                label: InlayHintLabel::String(label),
+                                kind: None,
+                                text_edits: None,
+                                tooltip: Some(InlayHintTooltip::String(
+                                    "Accessed member: type @ byte offset".to_string(),
+                                )),
+                                padding_left: Some(true),
+                                padding_right: None,
+                                data: None,
+                            })
+                        }),
+                );
+            }
+
+            // Show the register pressure next to the labels of basic blocks
+            if hints_config.register_pressure {
+                for f in &doc.index.function_bodies {
+                    let blocks = liveness(&f.basic_blocks);
+                    inlay_hints.extend(f.basic_blocks.iter().zip(blocks).filter_map(|(bb, l)| {
+                        let describe = |values: &BTreeSet<String>| {
+                            values.iter().cloned().collect::<Vec<_>>().join(", ")
+                        };
+                        Some(InlayHint {
+                            position: offset_to_lsp_pos(
+                                &doc.rope,
+                                bb.label_comma_span.as_ref()?.end,
+                            )?,
+                            label: InlayHintLabel::String(format!("live: {}", l.max_pressure)),
+                            kind: None,
+                            text_edits: None,
+                            tooltip: Some(InlayHintTooltip::MarkupContent(MarkupContent {
+                                kind: MarkupKind::Markdown,
+                                value: format!(
+                                    "At most {} simultaneously live values\n\n**Live-in:** {}\n\n**Live-out:** {}",
+                                    l.max_pressure,
+                                    describe(&l.live_in),
+                                    describe(&l.live_out)
+                                ),
+                            })),
+                            padding_left: Some(true),
+                            padding_right: Some(true),
+                            data: None,
+                        })
+                    }));
+                }
+            }
+
+            // Show the folded values of instructions which always produce the same constant
+            if hints_config.constant_values {
+                for stmt in doc.stmts.iter() {
+                    let Statement::FuncDef { body, .. } = stmt else {
+                        continue;
+                    };
+                    let instrs = body
+                        .basic_blocks
+                        .iter()
+                        .flat_map(|bb| &bb.instructions)
+                        .filter_map(|i| Some((&i.assignment_target.as_ref()?.1, i)))
+                        .collect::<HashMap<_, _>>();
+                    let result = propagate_constants(&body.basic_blocks);
+                    inlay_hints.extend(result.values.iter().filter_map(|v| {
+                        let instr = instrs.get(&v.name.1)?;
+                        let end = instr
+                            .operands
+                            .last()
+                            .map_or(instr.instruction.1.end, |o| o.1.end);
+                        Some(InlayHint {
+                            position: offset_to_lsp_pos(&doc.rope, end)?,
+                            label: InlayHintLabel::String(format!("= {}", v.describe())),
+                            kind: None,
+                            text_edits: None,
+                            tooltip: Some(InlayHintTooltip::String(format!(
+                                "`{}` is always `{} {}`",
+                                v.name.0,
+                                v.type_,
+                                v.describe()
+                            ))),
+                            padding_left: Some(true),
+                            padding_right: None,
+                            data: None,
+                        })
+                    }));
+                }
+            }
+
             // Insert hints at the end of a function body which point back to the beginning
             // of the function definition
             inlay_hints.extend(doc.index.function_bodies.iter().filter_map(|f| {
@@ -622,45 +1478,140 @@ impl LanguageServer for Backend {
     }
 
     async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
+        self.wait_for_analysis().await;
         let codelenses = || -> Option<Vec<CodeLens>> {
             let uri = &params.text_document.uri;
             let uri_str = uri.to_string();
             let doc = self.document_map.get(&uri_str)?;
 
-            let codelenses = doc
+            let mut codelenses = doc
                 .index
                 .functions
                 .iter()
                 .filter_map(|f| {
                     // Only show the code lens for functions with exactly one definition
                     if let [def_range] = &f.1.defs[..] {
-                        Some(CodeLens {
-                            range: range_to_lsp(&doc.rope, def_range)?,
-                            command: Some(Command {
-                                // Potential icons: ⇆⭾⧬⌸✍✒✎🧐
-                                title: "✨ Visualize Controlflow".to_string(),
-                                command: "visualize-cfg".to_string(),
-                                arguments: Some(vec![
-                                    Value::String(uri_str.clone()),
-                                    Value::String(f.0.clone()),
-                                ]),
-                            }),
-                            data: None,
-                        })
+                        Some((range_to_lsp(&doc.rope, def_range)?, f.0))
                     } else {
                         None
                     }
                 })
+                .flat_map(|(range, name)| {
+                    [
+                        // Potential icons: ⇆⭾⧬⌸✍✒✎🧐
+                        ("✨ Visualize Controlflow", "visualize-cfg"),
+                        ("🌡 Register Pressure", "visualize-register-pressure"),
+                    ]
+                    .map(|(title, command)| CodeLens {
+                        range,
+                        command: Some(Command {
+                            title: title.to_string(),
+                            command: command.to_string(),
+                            arguments: Some(vec![
+                                Value::String(uri_str.clone()),
+                                Value::String(name.clone()),
+                            ]),
+                        }),
+                        data: None,
+                    })
+                })
                 .collect::<Vec<_>>();
 
+            let locations = |spans: &mut dyn Iterator<Item = &Span>| {
+                spans
+                    .filter_map(|span| {
+                        Some(Location::new(uri.clone(), range_to_lsp(&doc.rope, span)?))
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            // Reference and caller counts for functions
+            for (_, ud) in doc.index.functions.iter() {
+                let references = locations(&mut ud.uses.iter());
+                // Only calls count, not e.g. function pointers passed as arguments
+                let callers = doc
+                    .index
+                    .function_bodies
+                    .iter()
+                    .filter(|f| {
+                        f.basic_blocks
+                            .iter()
+                            .flat_map(|bb| &bb.instructions)
+                            .filter(|instr| instr.instruction.0 == "call")
+                            .filter_map(|instr| split_call(&instr.operands))
+                            .any(|(callee, _)| match callee {
+                                [(Token::GlobalName(_), span)] => ud.uses.contains(span),
+                                _ => false,
+                            })
+                    })
+                    .map(|f| &f.name)
+                    .collect::<Vec<_>>();
+                let callers = locations(&mut callers.iter().map(|c| &c.1));
+                for span in ud.decls.iter().chain(ud.defs.iter()) {
+                    let Some(range) = range_to_lsp(&doc.rope, span) else {
+                        continue;
+                    };
+                    codelenses.push(show_references_lens(
+                        uri,
+                        range,
+                        count_label(references.len(), "reference"),
+                        references.clone(),
+                    ));
+                    codelenses.push(show_references_lens(
+                        uri,
+                        range,
+                        count_label(callers.len(), "caller"),
+                        callers.clone(),
+                    ));
+                }
+            }
+
+            // Reference counts for global variables
+            for (_, ud) in doc.index.global_vars.iter() {
+                let references = locations(&mut ud.uses.iter());
+                for span in &ud.defs {
+                    let Some(range) = range_to_lsp(&doc.rope, span) else {
+                        continue;
+                    };
+                    codelenses.push(show_references_lens(
+                        uri,
+                        range,
+                        count_label(references.len(), "reference"),
+                        references.clone(),
+                    ));
+                }
+            }
+
+            // Incoming control flow edges for basic blocks
+            for f in &doc.index.function_bodies {
+                for label in f.basic_blocks.iter().filter_map(|bb| bb.label.as_ref()) {
+                    let Some(range) = range_to_lsp(&doc.rope, &label.1) else {
+                        continue;
+                    };
+                    let incoming = f.incoming_bb_branches.get(&label.0);
+                    let incoming =
+                        locations(&mut incoming.iter().flat_map(|i| i.iter().map(|l| &l.1)));
+                    codelenses.push(show_references_lens(
+                        uri,
+                        range,
+                        count_label(incoming.len(), "incoming edge"),
+                        incoming,
+                    ));
+                }
+            }
+
             Some(codelenses)
         }();
         return Ok(codelenses);
     }
 
     async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+        self.wait_for_analysis().await;
         match (params.command.as_str(), &params.arguments[..]) {
-            ("visualize-cfg", [Value::String(doc_uri), Value::String(func_name)]) => {
+            (
+                command @ ("visualize-cfg" | "visualize-register-pressure"),
+                [Value::String(doc_uri), Value::String(func_name)],
+            ) => {
                 let index = &self
                     .document_map
                     .get(doc_uri)
@@ -680,8 +1631,18 @@ impl LanguageServer for Backend {
                         data: None,
                     })?;
 
-                let title = format!("CFG for {}", func_name);
-                let dot_graph = create_cfg_dot_visualization(func_body);
+                let (title, dot_graph) = if command == "visualize-register-pressure" {
+                    let blocks = liveness(&func_body.basic_blocks);
+                    (
+                        format!("Register pressure for {}", func_name),
+                        create_cfg_dot_visualization(func_body, Some(&blocks)),
+                    )
+                } else {
+                    (
+                        format!("CFG for {}", func_name),
+                        create_cfg_dot_visualization(func_body, None),
+                    )
+                };
 
                 // Make this dependent on a client setting / client capability
                 // since it reuqires additional client-siye collabolation
@@ -725,6 +1686,81 @@ impl Request for ShowGraph {
     const METHOD: &'static str = "hyperir/showDot";
 }
 
+/// A node of the `hyperir/provenance` response, directly usable as a tree view item
+#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProvenanceTree {
+    label: String,
+    kind: ProvenanceKind,
+    range: Range,
+    children: Vec<ProvenanceTree>,
+}
+
+impl ProvenanceTree {
+    fn from_node(rope: &Rope, node: ProvenanceNode) -> Option<ProvenanceTree> {
+        Some(ProvenanceTree {
+            label: node.label,
+            kind: node.kind,
+            range: range_to_lsp(rope, &node.span)?,
+            children: node
+                .children
+                .into_iter()
+                .filter_map(|c| ProvenanceTree::from_node(rope, c))
+                .collect(),
+        })
+    }
+}
+
+/// Parameters of the `hyperir/slice` request
+#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SliceParams {
+    #[serde(flatten)]
+    text_document_position_params: TextDocumentPositionParams,
+    /// Also include instructions whose execution depends on branches of the slice
+    #[serde(default)]
+    control_dependencies: bool,
+}
+
+/// Parameters of the `hyperir/functionMetrics` request
+#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionMetricsParams {
+    text_document: TextDocumentIdentifier,
+}
+
+/// Formats a count for code lenses, e.g. `1 reference` or `3 references`
+fn count_label(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("1 {}", noun)
+    } else {
+        format!("{} {}s", count, noun)
+    }
+}
+
+/// A code lens which opens the references list, using the argument shape of VS Code's
+/// `editor.action.showReferences` command
+fn show_references_lens(
+    uri: &Url,
+    range: Range,
+    title: String,
+    locations: Vec<Location>,
+) -> CodeLens {
+    CodeLens {
+        range,
+        command: Some(Command {
+            title,
+            command: "editor.action.showReferences".to_string(),
+            arguments: Some(vec![
+                serde_json::to_value(uri).unwrap(),
+                serde_json::to_value(range.start).unwrap(),
+                serde_json::to_value(locations).unwrap(),
+            ]),
+        }),
+        data: None,
+    }
+}
+
 struct TextDocumentItem {
     uri: Url,
     text: String,
@@ -732,36 +1768,196 @@ struct TextDocumentItem {
 }
 
 impl Backend {
+    /// Waits until all document changes received so far are analyzed. tower-lsp handles
+    /// notifications and requests concurrently, so a request sent right after `didChange`
+    /// would otherwise see the previous version of the document.
+    async fn wait_for_analysis(&self) {
+        drop(self.analysis_lock.read().await);
+    }
+
+    /// Lists the workspace files in the background and re-analyzes all open documents,
+    /// such that their source paths get resolved again
+    async fn refresh_workspace_files(&self) {
+        let path_resolver = self.path_resolver();
+        let _ = tokio::task::spawn_blocking(move || path_resolver.list_workspace_files()).await;
+        let documents = self
+            .document_map
+            .iter()
+            .map(|doc| (doc.key().clone(), doc.rope.to_string(), doc.version))
+            .collect::<Vec<_>>();
+        for (uri, text, version) in documents {
+            let Ok(uri) = Url::parse(&uri) else {
+                continue;
+            };
+            self.on_change(TextDocumentItem { uri, text, version })
+                .await;
+        }
+        if self.pull_diagnostics.load(Ordering::Relaxed) {
+            let _ = self.client.workspace_diagnostic_refresh().await;
+        }
+    }
+
+    /// Finds the definition of the function with the given body in the index
+    fn function_def(
+        doc: &AnalyzedDocument,
+        func_body_id: usize,
+    ) -> Option<(&FuncSignature, &FuncBody)> {
+        let name = &doc.index.function_bodies.get(func_body_id)?.name;
+        doc.stmts.iter().find_map(|s| match s {
+            Statement::FuncDef {
+                signature, body, ..
+            } if signature.name == *name => Some((signature, body)),
+            _ => None,
+        })
+    }
+
+    /// Custom request `hyperir/provenance`: the tree of definitions a local value is computed from
+    async fn provenance(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<ProvenanceTree>> {
+        self.wait_for_analysis().await;
+        let tree = || -> Option<ProvenanceTree> {
+            let doc = self
+                .document_map
+                .get(&params.text_document.uri.to_string())?;
+            let offset = lsp_pos_to_offset(&doc.rope, &params.position)?;
+            let symbol = doc.index.find_symbol_at_position(offset)?;
+            if symbol.symbol_kind != hyper_ir_lsp::hir_index::SymbolKind::LocalVar {
+                return None;
+            }
+            let (signature, body) = Backend::function_def(&doc, symbol.func_body_id?)?;
+            let node = provenance_tree(&doc.rope, signature, body, &symbol.name, &symbol.span);
+            ProvenanceTree::from_node(&doc.rope, node)
+        }();
+        Ok(tree)
+    }
+
+    /// Custom request `hyperir/slice`: the instructions influenced by a local value, as
+    /// highlights. The definition is a write, data dependencies are reads and control
+    /// dependencies are plain text highlights.
+    async fn slice(&self, params: SliceParams) -> Result<Option<Vec<DocumentHighlight>>> {
+        self.wait_for_analysis().await;
+        let pos = params.text_document_position_params;
+        let highlights = || -> Option<Vec<DocumentHighlight>> {
+            let doc = self.document_map.get(&pos.text_document.uri.to_string())?;
+            let offset = lsp_pos_to_offset(&doc.rope, &pos.position)?;
+            let symbol = doc.index.find_symbol_at_position(offset)?;
+            if symbol.symbol_kind != hyper_ir_lsp::hir_index::SymbolKind::LocalVar {
+                return None;
+            }
+            let (signature, body) = Backend::function_def(&doc, symbol.func_body_id?)?;
+            let entries =
+                forward_slice(signature, body, &symbol.name, params.control_dependencies)?;
+            let highlights = entries
+                .iter()
+                .filter_map(|entry| {
+                    Some(DocumentHighlight {
+                        range: range_to_lsp(&doc.rope, &entry.span)?,
+                        kind: Some(match entry.kind {
+                            SliceKind::Seed => DocumentHighlightKind::WRITE,
+                            SliceKind::Data => DocumentHighlightKind::READ,
+                            SliceKind::Control => DocumentHighlightKind::TEXT,
+                        }),
+                    })
+                })
+                .collect();
+            Some(highlights)
+        }();
+        Ok(highlights)
+    }
+
+    /// Custom request `hyperir/functionMetrics`: size and shape of all functions in a document
+    async fn function_metrics(
+        &self,
+        params: FunctionMetricsParams,
+    ) -> Result<Option<Vec<FunctionMetrics>>> {
+        self.wait_for_analysis().await;
+        let metrics = self
+            .document_map
+            .get(&params.text_document.uri.to_string())
+            .map(|doc| function_metrics(&doc.stmts));
+        Ok(metrics)
+    }
+
     async fn on_change(&self, params: TextDocumentItem) {
-        let rope = ropey::Rope::from_str(&params.text);
-        let src = rope.to_string();
-
-        let ParserResult {
-            tokens,
-            stmts,
-            errors,
-        } = parse_from_str(&src);
-        let semantic_tokens = semantic_tokens_from_tokens(&tokens);
-        let index = create_index(&src, &tokens, &stmts);
-
-        let mut diagnostics = Vec::<Diagnostic>::new();
-        diagnostics.extend(diagnostics_from_parser(&rope, &errors));
-        diagnostics.extend(diagnostics_from_statements(&rope, &stmts));
-        diagnostics.extend(diagnostics_from_index(&rope, &params.uri, &index));
-
-        self.document_map.insert(
-            params.uri.to_string(),
-            AnalyzedDocument {
-                rope,
-                semantic_tokens,
-                stmts,
-                index,
-            },
-        );
+        // Taken before the first await, such that later requests wait for this change
+        let analysis_guard = self.analysis_lock.write().await;
+        // Parsing and resolving the source paths take a while for large generated files
+        let uri = params.uri.clone();
+        let path_resolver = self.path_resolver();
+        let config = self.config.lock().unwrap().clone();
+        let Ok(mut doc) = tokio::task::spawn_blocking(move || {
+            analyze_document(&uri, &params.text, params.version, &path_resolver, &config)
+        })
+        .await
+        else {
+            return;
+        };
+        let diagnostics = doc.diagnostics.clone();
+        match self.document_map.entry(params.uri.to_string()) {
+            // A newer version was analyzed in the meantime
+            Entry::Occupied(entry) if entry.get().version > params.version => return,
+            Entry::Occupied(mut entry) => {
+                // Keep the previously sent semantic tokens, such that we can send a delta
+                doc.semantic_tokens_result = entry.get_mut().semantic_tokens_result.take();
+                entry.insert(doc);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(doc);
+            }
+        }
+        drop(analysis_guard);
 
-        self.client
-            .publish_diagnostics(params.uri.clone(), diagnostics, Some(params.version))
-            .await;
+        // Clients supporting pull diagnostics request them on their own
+        if !self.pull_diagnostics.load(Ordering::Relaxed) {
+            self.client
+                .publish_diagnostics(params.uri.clone(), diagnostics, Some(params.version))
+                .await;
+        }
+    }
+
+    /// Analyzes a file which is not opened in the editor, off the async executor
+    async fn get_file_diagnostics(
+        &self,
+        uri: &Url,
+        previous_result_id: Option<String>,
+    ) -> Option<(String, Option<Vec<Diagnostic>>)> {
+        let path = uri.to_file_path().ok()?;
+        let path_resolver = self.path_resolver();
+        let config = self.config.lock().unwrap().clone();
+        tokio::task::spawn_blocking(move || {
+            file_diagnostics(
+                &path,
+                previous_result_id.as_deref(),
+                &path_resolver,
+                &config,
+            )
+        })
+        .await
+        .ok()?
+    }
+
+    fn path_resolver(&self) -> Arc<PathResolver> {
+        self.path_resolver.lock().unwrap().clone()
+    }
+
+    fn next_result_id(&self) -> String {
+        self.semantic_tokens_result_id
+            .fetch_add(1, Ordering::Relaxed)
+            .to_string()
+    }
+
+    /// Computes all semantic tokens and remembers them for future delta requests
+    fn get_full_semantic_tokens(&self, uri: &str) -> Option<SemanticTokens> {
+        let mut doc = self.document_map.get_mut(uri)?;
+        let lsp_tokens = convert_to_lsp_tokens(&doc.rope, &doc.semantic_tokens);
+        let result_id = self.next_result_id();
+        doc.semantic_tokens_result = Some((result_id.clone(), lsp_tokens.clone()));
+        Some(SemanticTokens {
+            result_id: Some(result_id),
+            data: lsp_tokens,
+        })
     }
 
     fn get_use_def_ranges(
@@ -797,9 +1993,9 @@ impl Backend {
 
         // In addition, consider the external_defs
         if ud == UseDefKind::Def {
+            let path_resolver = self.path_resolver();
             ranges.extend(usedefs.external_defs.iter().filter_map(|d| {
-                let root_paths = self.root_paths.lock().unwrap();
-                let uri = resolve_relative_path(&root_paths, &d.filepath)?;
+                let uri = path_resolver.resolve(&d.filepath)?;
 
                 Some(Location {
                     uri,
@@ -853,19 +2049,140 @@ impl Backend {
     }
 }
 
+/// `hyper-ir-lsp run file.hir @func args...` interprets a single function and prints
+/// the executed basic blocks. External functions are stubbed out.
+///
+/// Pointer arguments can be given as `type:value`, e.g. `int32:5`. They point to a fresh
+/// memory location holding that value, and we print the value after the call.
+fn run_command(args: &[String]) -> std::result::Result<(), String> {
+    let [path, func, args @ ..] = args else {
+        return Err("usage: hyper-ir-lsp run <file.hir> <@function> [args...]".to_string());
+    };
+    let src = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let res = parse_from_str(&src);
+    if let Some(err) = res.errors.first() {
+        return Err(format!("{}: {}", path, err));
+    }
+    let mut interpreter = Interpreter::new(&res.stmts, StubExternalFunctions);
+    let signature = interpreter
+        .signature(func)
+        .ok_or_else(|| format!("unknown function {}", func))?;
+    if signature.args.len() != args.len() {
+        return Err(format!(
+            "{} expects {} arguments, but got {}",
+            func,
+            signature.args.len(),
+            args.len()
+        ));
+    }
+    let mut values = vec![];
+    let mut pointees = vec![];
+    for (param, arg) in signature.args.iter().zip(args) {
+        let invalid = || format!("invalid argument for {}: {}", param.name.0, arg);
+        let type_ = IrType::from_name(&param.type_.0);
+        let value = match arg.split_once(':') {
+            Some((pointee, value)) if type_ == IrType::Ptr => {
+                let pointee = parse_type_from_str(pointee).ok_or_else(invalid)?;
+                let size = pointee.size_of().ok_or_else(invalid)?;
+                let addr = interpreter
+                    .memory
+                    .alloc(size, pointee.align_of().unwrap_or(1))
+                    .map_err(|e| e.to_string())?;
+                let value = parse_argument(&pointee, value).ok_or_else(invalid)?;
+                interpreter
+                    .store(addr, &pointee, value)
+                    .map_err(|e| e.to_string())?;
+                pointees.push((&param.name.0, pointee, addr));
+                Val::Int(addr as u128)
+            }
+            _ => parse_argument(&type_, arg).ok_or_else(invalid)?,
+        };
+        values.push(value);
+    }
+    let result = interpreter.call(func, &values);
+    for event in &interpreter.trace {
+        println!("{}", event);
+    }
+    match (result, signature.ret_type.0.as_str()) {
+        (Ok(Val::Int(v)), "ptr") => println!("result: 0x{:x}", v),
+        (Ok(v), _) => println!("result: {}", v),
+        (Err(e), _) => return Err(e.to_string()),
+    }
+    for (name, pointee, addr) in pointees {
+        let value = interpreter
+            .load(addr, &pointee)
+            .map_err(|e| e.to_string())?;
+        println!("{} -> {} {}", name, pointee, value);
+    }
+    Ok(())
+}
+
+/// `hyper-ir-lsp stats file.hir [--json]` prints the metrics of all functions, such that
+/// query plans can be compared
+fn stats_command(args: &[String]) -> std::result::Result<(), String> {
+    let (path, json) = match args {
+        [path] => (path, false),
+        [path, flag] if flag == "--json" => (path, true),
+        _ => return Err("usage: hyper-ir-lsp stats <file.hir> [--json]".to_string()),
+    };
+    let src = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let res = parse_from_str(&src);
+    if let Some(err) = res.errors.first() {
+        return Err(format!("{}: {}", path, err));
+    }
+    let metrics = function_metrics(&res.stmts);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&metrics).unwrap());
+        return Ok(());
+    }
+    for m in &metrics {
+        println!("{}", m.name);
+        for (description, count) in m.counts() {
+            println!("  {:<16}{}", format!("{}:", description), count);
+        }
+        println!("  {:<16}{}", "opcodes:", m.describe_opcodes());
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
+    let args = std::env::args().collect::<Vec<_>>();
+    let result = match args.get(1).map(String::as_str) {
+        Some("run") => Some(run_command(&args[2..])),
+        Some("stats") => Some(stats_command(&args[2..])),
+        _ => None,
+    };
+    if let Some(result) = result {
+        if let Err(err) = result {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
     let (service, socket) = LspService::build(|client| Backend {
         client,
-        root_paths: Default::default(),
+        config: Default::default(),
+        path_resolver: Default::default(),
         code_actions_lazy_resolve: Default::default(),
+        semantic_tokens_result_id: Default::default(),
+        pull_diagnostics: Default::default(),
+        watch_files: Default::default(),
+        files_changed: Default::default(),
+        refreshing_files: Default::default(),
+        inlay_hint_refresh: Default::default(),
+        analysis_lock: Default::default(),
         document_map: DashMap::new(),
     })
+    .custom_method("hyperir/provenance", Backend::provenance)
+    .custom_method("hyperir/slice", Backend::slice)
+    .custom_method("hyperir/functionMetrics", Backend::function_metrics)
     .finish();
 
     Server::new(stdin, stdout, socket).serve(service).await;
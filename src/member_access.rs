@@ -0,0 +1,290 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hir_operands::{parse_typed_value, split_operands, TypedValue, Value},
+    hir_parser::Instruction,
+    hir_tokenizer::Token,
+    hir_types::{parse_type, parse_type_from_str, IrType},
+    interpreter::sign_extend,
+};
+
+/// The memory access of a `gep`, `load` or `store`, such as
+/// `gep {ptr, int32, int64} ptr %queryState_0, int32 0, int32 2`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemberAccess {
+    /// The type the pointer points to
+    pub type_: IrType,
+    pub pointer: TypedValue,
+    /// The first index steps over complete values of `type_`,
+    /// the following indices select struct fields or array elements
+    pub indices: Vec<TypedValue>,
+}
+
+/// Parses the operands of `gep`, `load` and `store`
+pub fn parse_member_access(instr: &Instruction) -> Option<MemberAccess> {
+    let operands = split_operands(&instr.operands);
+    let (pointer, indices) = match (instr.instruction.0.as_str(), &operands[..]) {
+        ("gep" | "load", [pointer, indices @ ..]) => (pointer, indices),
+        ("store", [pointer, _, indices @ ..]) => (pointer, indices),
+        _ => return None,
+    };
+    let pointer = match pointer {
+        [(Token::Ident(kw), _), rest @ ..] if kw == "atomic" => rest,
+        _ => pointer,
+    };
+    let (type_, rest) = parse_type(pointer)?;
+    let (pointer, []) = parse_typed_value(rest)? else {
+        return None;
+    };
+    let indices = indices
+        .iter()
+        .map(|idx| match parse_typed_value(idx)? {
+            (idx, []) => Some(idx),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(MemberAccess {
+        type_,
+        pointer,
+        indices,
+    })
+}
+
+/// The value of a constant index. Indices are signed, e.g. `int64 0xffffffffffffffff` is -1.
+fn constant_index(idx: &TypedValue) -> Option<i128> {
+    match (&idx.type_, &idx.value.0) {
+        (IrType::Int(bits), Value::Int(i)) => Some(sign_extend(*i, *bits)),
+        (_, Value::Int(i)) => i128::try_from(*i).ok(),
+        _ => None,
+    }
+}
+
+/// The byte offset of element `idx` of an array of `size` byte elements, if it doesn't overflow
+fn element_offset(idx: &TypedValue, size: Option<u64>) -> Option<i128> {
+    constant_index(idx)?.checked_mul(size? as i128)
+}
+
+fn describe_index(idx: &TypedValue) -> String {
+    match &idx.value.0 {
+        Value::Int(i) => constant_index(idx).map_or(i.to_string(), |i| i.to_string()),
+        Value::Local(name) | Value::Global(name) => name.clone(),
+        v => format!("{:?}", v),
+    }
+}
+
+/// Names for a struct layout and its fields, configured by the user
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StructNames {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+/// User-provided names of struct layouts, such that `{...}.field 4` becomes
+/// `QueryState.resultTupleCount`
+#[derive(Clone, Debug, Default)]
+pub struct FieldNames(HashMap<IrType, StructNames>);
+
+impl FieldNames {
+    /// The keys are struct layouts as printed by Hyper, e.g. `{hyper::QueryResources*, int32}`
+    pub fn new(names: &BTreeMap<String, StructNames>) -> FieldNames {
+        FieldNames(
+            names
+                .iter()
+                .filter_map(|(layout, names)| Some((parse_type_from_str(layout)?, names.clone())))
+                .collect(),
+        )
+    }
+
+    fn struct_name(&self, type_: &IrType) -> String {
+        match (self.0.get(type_), type_) {
+            (Some(names), _) => names.name.clone(),
+            (None, IrType::Struct { .. }) => "{...}".to_string(),
+            (None, type_) => type_.to_string(),
+        }
+    }
+
+    fn field_name(&self, type_: &IrType, idx: u64) -> String {
+        self.0
+            .get(type_)
+            .and_then(|names| names.fields.get(idx as usize))
+            .cloned()
+            .unwrap_or_else(|| format!("field {}", idx))
+    }
+}
+
+/// The member accessed by a `gep`, `load` or `store`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResolvedMember {
+    /// E.g. `{...}.field 4`, `QueryState.resultTupleCount` or `int8[%v30]`
+    pub path: String,
+    /// The struct fields traversed, by their containing struct type and index
+    pub fields: Vec<(IrType, u64)>,
+    /// The type of the accessed member, if the indices could be followed
+    pub type_: Option<IrType>,
+    /// The byte offset relative to the pointer operand, if all indices are constant
+    /// and the layout of all types is known
+    pub offset: Option<i128>,
+}
+
+impl MemberAccess {
+    /// Follows the indices through the accessed type. Struct types are abbreviated in the
+    /// path, since Hyper spells out their complete layout.
+    pub fn resolve(&self, names: &FieldNames) -> ResolvedMember {
+        let mut path = names.struct_name(&self.type_);
+        let mut fields = vec![];
+        let mut offset = Some(0i128);
+        let mut indices = self.indices.iter();
+        if let Some(first) = indices.next() {
+            if first.value.0 != Value::Int(0) || self.indices.len() == 1 {
+                path.push_str(&format!("[{}]", describe_index(first)));
+            }
+            offset = match first.value.0 {
+                Value::Int(0) => Some(0),
+                _ => element_offset(first, self.type_.size_of()),
+            };
+        }
+        let mut type_ = Some(self.type_.clone());
+        for idx in indices {
+            match (&type_, &idx.value.0) {
+                (
+                    Some(
+                        struct_type @ IrType::Struct {
+                            fields: members, ..
+                        },
+                    ),
+                    Value::Int(i),
+                ) => {
+                    path.push_str(&format!(".{}", names.field_name(struct_type, *i as u64)));
+                    let field = usize::try_from(*i).ok();
+                    offset = offset
+                        .zip(field.and_then(|f| struct_type.field_offset(f)))
+                        .and_then(|(o, f)| o.checked_add(f as i128));
+                    fields.push((struct_type.clone(), *i as u64));
+                    type_ = field.and_then(|f| members.get(f)).cloned();
+                }
+                (Some(IrType::Array(elem, _)), _) => {
+                    path.push_str(&format!("[{}]", describe_index(idx)));
+                    offset = offset
+                        .zip(element_offset(idx, elem.size_of()))
+                        .and_then(|(o, e)| o.checked_add(e));
+                    type_ = Some(elem.as_ref().clone());
+                }
+                // Types defined outside the module, such as `hyper::QueryResources`, can still
+                // have user-provided field names
+                (Some(named), Value::Int(i)) => {
+                    path.push_str(&format!(".{}", names.field_name(named, *i as u64)));
+                    offset = None;
+                    type_ = None;
+                }
+                _ => {
+                    path.push_str(&format!(".{}", describe_index(idx)));
+                    offset = None;
+                    type_ = None;
+                }
+            }
+        }
+        ResolvedMember {
+            path,
+            fields,
+            type_,
+            offset,
+        }
+    }
+
+    /// Describes the accessed member, e.g. `{...}.field 4` or `int8[%v30]`
+    pub fn path(&self) -> String {
+        self.resolve(&FieldNames::default()).path
+    }
+}
+
+#[cfg(test)]
+fn member_access_from_str(src: &str) -> MemberAccess {
+    let res =
+        crate::hir_parser::parse_from_str(&format!("define void @f() {{\nbody_0:\n  {}\n}}", src));
+    let (_, body) = crate::hir_parser::first_function(&res);
+    parse_member_access(&body.basic_blocks[0].instructions[0]).unwrap()
+}
+
+#[test]
+fn test_member_access_path() {
+    let access = member_access_from_str(
+        "ptr %v1 = gep {ptr, int32, {int8, int64[4]}} ptr %queryState_0, int32 0, int32 2, int32 1, int64 %i",
+    );
+    assert_eq!(
+        access.pointer.value.0,
+        Value::Local("%queryState_0".to_string())
+    );
+    assert_eq!(access.path(), "{...}.field 2.field 1[%i]");
+    let access = member_access_from_str("ptr %v2 = gep int8 ptr %v29, int64 %v30");
+    assert_eq!(access.path(), "int8[%v30]");
+    let access =
+        member_access_from_str("ptr %v3 = gep {hyper::DebugStringStream, int64} ptr %v31, int32 1");
+    assert_eq!(access.path(), "{...}[1]");
+    let access = member_access_from_str("store atomic int64 ptr %p, int64 1");
+    assert_eq!(access.path(), "int64");
+}
+
+#[test]
+fn test_resolve_member() {
+    let access = member_access_from_str(
+        "ptr %v1 = gep {hyper::QueryResources*, int32, int1, hyper::ThreadStateContainer*, int64} ptr %queryState_0, int32 0, int32 4",
+    );
+    let member = access.resolve(&FieldNames::default());
+    assert_eq!(member.path, "{...}.field 4");
+    assert_eq!(member.type_, Some(IrType::Int(64)));
+    assert_eq!(member.offset, Some(24));
+    assert_eq!(member.fields, [(access.type_.clone(), 4)]);
+
+    let names = FieldNames::new(&BTreeMap::from([(
+        "{hyper::QueryResources*, int32, int1, hyper::ThreadStateContainer*, int64}".to_string(),
+        StructNames {
+            name: "QueryState".to_string(),
+            fields: vec!["queryResources".to_string()],
+        },
+    )]));
+    assert_eq!(access.resolve(&names).path, "QueryState.field 4");
+    let access = member_access_from_str(
+        "int32 %v2 = load {hyper::QueryResources*, int32, int1, hyper::ThreadStateContainer*, int64} ptr %queryState_0, int32 0, int32 0",
+    );
+    assert_eq!(access.resolve(&names).path, "QueryState.queryResources");
+
+    // Unknown layouts and non-constant indices make the offset unknown
+    let access =
+        member_access_from_str("ptr %v3 = gep {hyper::BulkInsert, int64} ptr %p, int32 0, int32 1");
+    assert_eq!(access.resolve(&names).offset, None);
+    let access =
+        member_access_from_str("ptr %v3 = gep {int64, hyper::BulkInsert} ptr %p, int32 0, int32 0");
+    assert_eq!(access.resolve(&names).offset, Some(0));
+    let access =
+        member_access_from_str("ptr %v3 = load hyper::QueryResources ptr %p, int32 0, int32 4");
+    assert_eq!(access.resolve(&names).path, "hyper::QueryResources.field 4");
+    let access =
+        member_access_from_str("ptr %v4 = gep {int64, int32[4]} ptr %p, int32 1, int32 1, int64 2");
+    let member = access.resolve(&names);
+    assert_eq!(
+        (member.type_, member.offset),
+        (Some(IrType::Int(32)), Some(24 + 8 + 8))
+    );
+    let access = member_access_from_str("ptr %v5 = gep int8 ptr %p, int64 %i");
+    assert_eq!(access.resolve(&names).offset, None);
+
+    // Indices are signed, and overflowing offsets are unknown
+    let access = member_access_from_str("ptr %v6 = gep int64 ptr %p, int64 0xffffffffffffffff");
+    let member = access.resolve(&names);
+    assert_eq!(
+        (member.path.as_str(), member.offset),
+        ("int64[-1]", Some(-8))
+    );
+    let access = member_access_from_str(
+        "ptr %v7 = gep int64[4] ptr %p, int128 0x7fffffffffffffffffffffffffffffff, int64 1",
+    );
+    assert_eq!(access.resolve(&names).offset, None);
+    let access = member_access_from_str(
+        "ptr %v8 = gep {int64, int64} ptr %p, int32 0, int128 0x10000000000000001",
+    );
+    let member = access.resolve(&names);
+    assert_eq!((member.type_, member.offset), (None, None));
+}
@@ -0,0 +1,210 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    control_flow_graph::ControlFlowGraph,
+    hir_operands::split_call,
+    hir_parser::{FuncBody, Statement},
+    hir_tokenizer::Token,
+};
+
+/// Size and shape of a function, used to compare query plans and to spot compile-time outliers
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionMetrics {
+    pub name: String,
+    pub instructions: usize,
+    pub basic_blocks: usize,
+    pub edges: usize,
+    /// Natural loops; irreducible control flow is not counted
+    pub loops: usize,
+    pub max_loop_depth: usize,
+    /// Calls of functions defined within the same module
+    pub internal_calls: usize,
+    /// Calls of declared functions, lambdas and intrinsics
+    pub external_calls: usize,
+    pub phis: usize,
+    pub debug_refs: usize,
+    /// The number of instructions by opcode
+    pub opcodes: BTreeMap<String, usize>,
+}
+
+impl FunctionMetrics {
+    /// The metrics as `(description, value)`, without the opcode histogram
+    pub fn counts(&self) -> [(&'static str, usize); 9] {
+        [
+            ("instructions", self.instructions),
+            ("basic blocks", self.basic_blocks),
+            ("edges", self.edges),
+            ("loops", self.loops),
+            ("max loop depth", self.max_loop_depth),
+            ("internal calls", self.internal_calls),
+            ("external calls", self.external_calls),
+            ("phis", self.phis),
+            ("debug refs", self.debug_refs),
+        ]
+    }
+
+    /// The opcodes, most frequent first, e.g. `br: 12, add: 7`
+    pub fn describe_opcodes(&self) -> String {
+        let mut opcodes = self.opcodes.iter().collect::<Vec<_>>();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1));
+        opcodes
+            .iter()
+            .map(|(opcode, count)| format!("{}: {}", opcode, count))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn hover_markdown(&self) -> String {
+        let mut value = String::new();
+        for (description, count) in self.counts() {
+            value.push_str(&format!("* {}: {}\n", description, count));
+        }
+        value.push_str(&format!("\nOpcodes: {}\n", self.describe_opcodes()));
+        value
+    }
+}
+
+/// The names of all functions defined within the module
+pub fn defined_functions(stmts: &[Statement]) -> HashSet<&str> {
+    stmts
+        .iter()
+        .filter_map(|s| match s {
+            Statement::FuncDef { signature, .. } => Some(signature.name.0.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Computes the metrics of a single function. Calls of the `defined` functions count as
+/// internal calls.
+pub fn body_metrics(name: &str, body: &FuncBody, defined: &HashSet<&str>) -> FunctionMetrics {
+    let cfg = ControlFlowGraph::new(&body.basic_blocks);
+    let loops = cfg.natural_loops();
+    let mut metrics = FunctionMetrics {
+        name: name.to_string(),
+        basic_blocks: body.basic_blocks.len(),
+        edges: cfg.successors.iter().map(Vec::len).sum(),
+        loops: loops.len(),
+        max_loop_depth: (0..cfg.len())
+            .map(|b| loops.iter().filter(|l| l.1.contains(&b)).count())
+            .max()
+            .unwrap_or(0),
+        ..Default::default()
+    };
+    for instr in body.basic_blocks.iter().flat_map(|bb| &bb.instructions) {
+        let opcode = instr.instruction.0.as_str();
+        metrics.instructions += 1;
+        *metrics.opcodes.entry(opcode.to_string()).or_default() += 1;
+        metrics.phis += (opcode == "phi") as usize;
+        metrics.debug_refs += instr.dbg_ref.is_some() as usize;
+        match (opcode, split_call(&instr.operands)) {
+            ("call", Some(([(Token::GlobalName(callee), _)], _)))
+                if defined.contains(callee.as_str()) =>
+            {
+                metrics.internal_calls += 1
+            }
+            ("call" | "calllambda" | "callintrinsic", _) => metrics.external_calls += 1,
+            _ => {}
+        }
+    }
+    metrics
+}
+
+/// Computes the metrics of all functions defined in the module
+pub fn function_metrics(stmts: &[Statement]) -> Vec<FunctionMetrics> {
+    let defined = defined_functions(stmts);
+    stmts
+        .iter()
+        .filter_map(|s| match s {
+            Statement::FuncDef {
+                signature, body, ..
+            } => Some(body_metrics(&signature.name.0, body, &defined)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_function_metrics() {
+    let res = crate::hir_parser::parse_from_str(
+        "
+declare void @external(int64 %)
+define int64 @helper(int64 %x) {
+body_0:
+  ret int64 %x
+}
+define void @f(int64 %n) {
+body_0:
+  br outer_1
+outer_1:
+  int64 %i = phi [body_0, int64 0], [latch_3, int64 %next]
+  br inner_2
+inner_2:
+  int64 %v = call @helper(int64 %i)    !1
+  call @external(int64 %v)    !2
+  int1 %again = ult int64 %v, int64 %n
+  br int1 %again, inner_2, latch_3
+latch_3:
+  int64 %next = add int64 %i, int64 1
+  int1 %done = eq int64 %next, int64 %n
+  br int1 %done, exit_4, outer_1
+exit_4:
+  ret
+}",
+    );
+    let metrics = function_metrics(&res.stmts);
+    assert_eq!(
+        metrics.iter().map(|m| m.name.as_str()).collect::<Vec<_>>(),
+        ["@helper", "@f"]
+    );
+    let f = &metrics[1];
+    assert_eq!(f.counts().map(|c| c.1), [11, 5, 6, 2, 2, 1, 1, 1, 2]);
+    assert_eq!(f.opcodes["br"], 4);
+    assert_eq!(
+        f.describe_opcodes(),
+        "br: 4, call: 2, add: 1, eq: 1, phi: 1, ret: 1, ult: 1"
+    );
+}
+
+#[test]
+fn test_empty_function_metrics() {
+    let res = crate::hir_parser::parse_from_str("define void @empty() {\n}");
+    assert_eq!(res.errors, []);
+    let metrics = function_metrics(&res.stmts);
+    assert_eq!(metrics.len(), 1);
+    assert_eq!(metrics[0].name, "@empty");
+    assert_eq!(metrics[0].counts().map(|c| c.1), [0; 9]);
+    assert_eq!(metrics[0].describe_opcodes(), "");
+    // Declarations have no metrics
+    let res = crate::hir_parser::parse_from_str("declare void @external()");
+    assert_eq!(function_metrics(&res.stmts), []);
+}
+
+#[test]
+fn test_multi_block_function_metrics() {
+    let res = crate::hir_parser::parse_from_str(
+        "
+define void @f(int32 %v, ptr %fn) {
+body_0:
+  switch int32 %v, default=exit_3, int32 0 label=zero_1, int32 1 label=one_2
+zero_1:
+  calllambda {ptr %fn} (int32 %v)
+  br exit_3
+one_2:
+  br exit_3
+exit_3:
+  ret
+}",
+    );
+    let (signature, body) = crate::hir_parser::first_function(&res);
+    let metrics = body_metrics(&signature.name.0, body, &defined_functions(&res.stmts));
+    // No loops, but three edges leave the `switch`
+    assert_eq!(metrics.counts().map(|c| c.1), [5, 4, 5, 0, 0, 0, 1, 0, 0]);
+    assert_eq!(
+        metrics.describe_opcodes(),
+        "br: 2, calllambda: 1, ret: 1, switch: 1"
+    );
+}
@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::Url;
+
+/// We stop listing the workspace files after this many files, to not block
+/// on huge workspaces
+const MAX_WORKSPACE_FILES: usize = 200_000;
+
+/// Source of `PathResolver::generation`. It is shared by all resolvers, so a resolver
+/// replaced after a configuration change never repeats the generation of its predecessor.
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+/// Replaces the prefix `from` of a path by `to`, similar to gdb's `set substitute-path`
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct PathSubstitution {
+    pub from: String,
+    pub to: String,
+}
+
+/// Resolves the file paths mentioned in debug annotations and backtraces to files in the workspace
+#[derive(Debug, Default)]
+pub struct PathResolver {
+    pub root_paths: Vec<Url>,
+    pub substitutions: Vec<PathSubstitution>,
+    /// All files in the workspace, `None` until listed by `list_workspace_files`
+    workspace_files: RwLock<Option<Arc<Vec<PathBuf>>>>,
+    /// The results of `resolve`, by normalized path. Generated files mention the
+    /// same paths thousands of times.
+    resolved: Mutex<HashMap<String, Option<Url>>>,
+    /// Changes whenever the resolved paths might change
+    generation: AtomicU64,
+}
+
+/// Normalizes a path, e.g. `././hyper/codegen/../ir/If.hpp` becomes `hyper/ir/If.hpp`.
+/// Windows path separators are replaced by `/`, drive prefixes such as `C:` are kept.
+pub fn normalize_path(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let (root, rest) = match path.as_bytes() {
+        [b'/', ..] => ("/", &path[1..]),
+        [drive, b':', b'/', ..] if drive.is_ascii_alphabetic() => (&path[..3], &path[3..]),
+        _ => ("", path.as_str()),
+    };
+    let mut segments: Vec<&str> = vec![];
+    for segment in rest.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." if segments.last().is_some_and(|s| *s != "..") => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    format!("{}{}", root, segments.join("/"))
+}
+
+/// Applies the first matching substitution. Prefixes only match at segment boundaries.
+fn substitute_path(substitutions: &[PathSubstitution], path: &str) -> String {
+    for s in substitutions {
+        let from = normalize_path(&s.from);
+        let Some(rest) = path.strip_prefix(&from) else {
+            continue;
+        };
+        if !rest.is_empty() && !rest.starts_with('/') {
+            continue;
+        }
+        let rest = rest.trim_start_matches('/');
+        if s.to.is_empty() {
+            return rest.to_string();
+        }
+        return normalize_path(&format!("{}/{}", s.to, rest));
+    }
+    path.to_string()
+}
+
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries = entries.filter_map(|e| e.ok()).collect::<Vec<_>>();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        if files.len() >= MAX_WORKSPACE_FILES {
+            return;
+        }
+        // Skip hidden folders such as `.git`
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        // `file_type` does not follow symlinks. This way, we don't descend into
+        // Bazel's output folders (`bazel-bin`, ...)
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => list_files(&entry.path(), files),
+            Ok(t) if t.is_file() => files.push(entry.path()),
+            _ => {}
+        }
+    }
+}
+
+impl PathResolver {
+    pub fn new(root_paths: Vec<Url>, substitutions: Vec<PathSubstitution>) -> PathResolver {
+        PathResolver {
+            root_paths,
+            substitutions,
+            workspace_files: Default::default(),
+            resolved: Default::default(),
+            generation: AtomicU64::new(NEXT_GENERATION.fetch_add(1, Ordering::Relaxed)),
+        }
+    }
+
+    /// Identifies the state of the workspace file list. Anything derived from resolved
+    /// paths is outdated once the generation changes.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    fn bump_generation(&self) {
+        self.generation.store(
+            NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Lists all files in the workspace and caches the list for `resolve`.
+    /// This walks the file system, so don't call it on the async executor.
+    pub fn list_workspace_files(&self) -> Arc<Vec<PathBuf>> {
+        let mut files = vec![];
+        for root in &self.root_paths {
+            if let Ok(root) = root.to_file_path() {
+                list_files(&root, &mut files);
+            }
+        }
+        let files = Arc::new(files);
+        *self.workspace_files.write().unwrap() = Some(files.clone());
+        // Previously unresolved paths might be found by the suffix search now
+        self.resolved.lock().unwrap().clear();
+        self.bump_generation();
+        files
+    }
+
+    /// The workspace files last listed by `list_workspace_files`
+    pub fn workspace_files(&self) -> Option<Arc<Vec<PathBuf>>> {
+        self.workspace_files.read().unwrap().clone()
+    }
+
+    /// Forgets the listed workspace files and all resolved paths, e.g. after files were
+    /// created or deleted
+    pub fn invalidate(&self) {
+        *self.workspace_files.write().unwrap() = None;
+        self.resolved.lock().unwrap().clear();
+        self.bump_generation();
+    }
+
+    /// Finds the workspace file sharing the longest suffix with `path`.
+    /// At least the file names must match.
+    fn find_by_suffix<'a>(files: &'a [PathBuf], path: &str) -> Option<&'a Path> {
+        let segments = path
+            .split('/')
+            .filter(|s| !s.is_empty() && *s != "..")
+            .collect::<Vec<_>>();
+        let mut best: Option<(usize, &Path)> = None;
+        for file in files {
+            let matching = file
+                .iter()
+                .rev()
+                .zip(segments.iter().rev())
+                .take_while(|(a, b)| a.to_str() == Some(**b))
+                .count();
+            if matching > 0 && best.is_none_or(|(m, _)| matching > m) {
+                best = Some((matching, file));
+            }
+        }
+        best.map(|b| b.1)
+    }
+
+    /// Resolves a path from the IR to a file. We first apply the substitutions,
+    /// then try the path relative to each workspace root and finally search for
+    /// a workspace file with the same suffix. The suffix search is skipped while
+    /// the workspace files are not listed.
+    pub fn resolve(&self, path: &str) -> Option<Url> {
+        let path = normalize_path(path);
+        if let Some(resolved) = self.resolved.lock().unwrap().get(&path) {
+            return resolved.clone();
+        }
+        let files = self.workspace_files();
+        let resolved = self.resolve_uncached(&path, files.as_ref().map(|f| f.as_slice()));
+        // Without the file list, a later suffix search might still succeed
+        if resolved.is_some() || files.is_some() {
+            self.resolved.lock().unwrap().insert(path, resolved.clone());
+        }
+        resolved
+    }
+
+    fn resolve_uncached(&self, path: &str, files: Option<&[PathBuf]>) -> Option<Url> {
+        let path = substitute_path(&self.substitutions, path);
+        if Path::new(&path).is_absolute() {
+            if Path::new(&path).is_file() {
+                return Url::from_file_path(&path).ok();
+            }
+        } else {
+            let found = self.root_paths.iter().find_map(|root| {
+                let file = root.to_file_path().ok()?.join(&path);
+                file.is_file().then_some(file)
+            });
+            if let Some(file) = found {
+                return Url::from_file_path(file).ok();
+            }
+        }
+        Url::from_file_path(Self::find_by_suffix(files?, &path)?).ok()
+    }
+}
+
+#[test]
+fn test_normalize_path() {
+    assert_eq!(
+        normalize_path("././hyper/codegen/If.hpp"),
+        "hyper/codegen/If.hpp"
+    );
+    assert_eq!(
+        normalize_path("hyper/codegen/../ir//If.hpp"),
+        "hyper/ir/If.hpp"
+    );
+    assert_eq!(normalize_path("../../hyper/If.hpp"), "../../hyper/If.hpp");
+    assert_eq!(normalize_path("/tmp/./x/../y.cpp"), "/tmp/y.cpp");
+    assert_eq!(normalize_path(".\\my\\File.cpp"), "my/File.cpp");
+    assert_eq!(
+        normalize_path("C:\\hyper\\codegen\\..\\If.hpp"),
+        "C:/hyper/If.hpp"
+    );
+}
+
+#[test]
+fn test_substitute_path() {
+    let substitutions = [
+        PathSubstitution {
+            from: "/sandbox/execroot/__main__".to_string(),
+            to: "".to_string(),
+        },
+        PathSubstitution {
+            from: "bazel-bin/".to_string(),
+            to: "/home/me/hyper".to_string(),
+        },
+    ];
+    assert_eq!(
+        substitute_path(&substitutions, "/sandbox/execroot/__main__/hyper/If.hpp"),
+        "hyper/If.hpp"
+    );
+    assert_eq!(
+        substitute_path(&substitutions, "bazel-bin/hyper/If.hpp"),
+        "/home/me/hyper/hyper/If.hpp"
+    );
+    // Prefixes only match complete segments
+    assert_eq!(
+        substitute_path(&substitutions, "/sandbox/execroot/__main__2/If.hpp"),
+        "/sandbox/execroot/__main__2/If.hpp"
+    );
+}
+
+#[test]
+fn test_resolve() {
+    let root = std::env::temp_dir().join(format!("hyper-ir-lsp-resolve-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("src/hyper/codegen")).unwrap();
+    std::fs::create_dir_all(root.join("other/codegen")).unwrap();
+    std::fs::write(root.join("src/hyper/codegen/If.hpp"), "").unwrap();
+    std::fs::write(root.join("other/codegen/If.hpp"), "").unwrap();
+    let file_url = |p: &str| Url::from_file_path(root.join(p)).unwrap();
+
+    let resolver = PathResolver::new(vec![Url::from_file_path(&root).unwrap()], vec![]);
+    assert_eq!(
+        resolver.resolve("././src/hyper/codegen/If.hpp"),
+        Some(file_url("src/hyper/codegen/If.hpp"))
+    );
+    // The suffix search needs the list of workspace files
+    assert_eq!(resolver.resolve("bazel-bin/hyper/codegen/If.hpp"), None);
+    assert_eq!(resolver.list_workspace_files().len(), 2);
+    // Found by suffix search; the file with the longest common suffix wins
+    assert_eq!(
+        resolver.resolve("bazel-bin/hyper/codegen/If.hpp"),
+        Some(file_url("src/hyper/codegen/If.hpp"))
+    );
+    assert_eq!(resolver.resolve("hyper/codegen/Missing.hpp"), None);
+    // Resolved paths are remembered until the resolver is invalidated
+    std::fs::write(root.join("src/hyper/codegen/Missing.hpp"), "").unwrap();
+    assert_eq!(resolver.resolve("hyper/codegen/Missing.hpp"), None);
+    resolver.invalidate();
+    resolver.list_workspace_files();
+    assert_eq!(
+        resolver.resolve("hyper/codegen/Missing.hpp"),
+        Some(file_url("src/hyper/codegen/Missing.hpp"))
+    );
+
+    let resolver = PathResolver::new(
+        vec![],
+        vec![PathSubstitution {
+            from: "/sandbox".to_string(),
+            to: root.to_str().unwrap().to_string(),
+        }],
+    );
+    assert_eq!(
+        resolver.resolve("/sandbox/other/codegen/If.hpp"),
+        Some(file_url("other/codegen/If.hpp"))
+    );
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,276 @@
+use std::collections::{HashMap, HashSet};
+
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hir_operands::{parse_value, Value},
+    hir_parser::{FuncBody, FuncSignature, Instruction},
+    hir_tokenizer::{Span, Token},
+    ssa::{value_defs, ValueDef},
+};
+
+/// Stop expanding the tree after this many nodes, to keep the response small
+const MAX_PROVENANCE_NODES: usize = 2000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ProvenanceKind {
+    Argument,
+    Instruction,
+    Phi,
+    /// The value was loaded from memory. The children describe the address.
+    Load,
+    Call,
+    Constant,
+    Global,
+    /// The value depends on itself, e.g. through a `phi` in a loop
+    Cycle,
+    /// The value was already expanded elsewhere in the tree
+    Repeated,
+    Undefined,
+}
+
+/// Where a value comes from: its definition and, recursively, the definitions of its operands
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProvenanceNode {
+    pub label: String,
+    pub kind: ProvenanceKind,
+    pub span: Span,
+    pub children: Vec<ProvenanceNode>,
+}
+
+impl ProvenanceNode {
+    fn leaf(label: String, kind: ProvenanceKind, span: Span) -> ProvenanceNode {
+        ProvenanceNode {
+            label,
+            kind,
+            span,
+            children: vec![],
+        }
+    }
+}
+
+struct ProvenanceWalker<'a> {
+    rope: &'a Rope,
+    defs: HashMap<&'a str, ValueDef<'a>>,
+    /// The values currently being expanded, to detect cycles
+    stack: Vec<&'a str>,
+    expanded: HashSet<&'a str>,
+    nodes: usize,
+}
+
+/// The source text of an instruction without its debug reference, on a single line
+fn instruction_label(rope: &Rope, instr: &Instruction) -> String {
+    let end = instr
+        .operands
+        .last()
+        .map_or(instr.instruction.1.end, |o| o.1.end);
+    rope.slice(instr.span.start..end)
+        .to_string()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl<'a> ProvenanceWalker<'a> {
+    fn value_node(&mut self, name: &'a str, use_span: &Span) -> ProvenanceNode {
+        self.nodes += 1;
+        let def = self.defs.get(name).copied();
+        let def_span = match def.and_then(|d| d.declaration()) {
+            Some((_, def_name)) => def_name.1.clone(),
+            None => use_span.clone(),
+        };
+        if self.stack.contains(&name) {
+            return ProvenanceNode::leaf(name.to_string(), ProvenanceKind::Cycle, def_span);
+        }
+        if self.expanded.contains(name) || self.nodes >= MAX_PROVENANCE_NODES {
+            return ProvenanceNode::leaf(name.to_string(), ProvenanceKind::Repeated, def_span);
+        }
+        let instr = match def {
+            None => {
+                return ProvenanceNode::leaf(
+                    name.to_string(),
+                    ProvenanceKind::Undefined,
+                    use_span.clone(),
+                )
+            }
+            Some(ValueDef::Argument(arg)) => {
+                return ProvenanceNode::leaf(
+                    format!("{} {}", arg.type_.0, arg.name.0),
+                    ProvenanceKind::Argument,
+                    arg.name.1.clone(),
+                )
+            }
+            Some(ValueDef::Instruction(instr)) => instr,
+        };
+        let kind = match instr.instruction.0.as_str() {
+            "phi" => ProvenanceKind::Phi,
+            "load" => ProvenanceKind::Load,
+            "call" | "calllambda" | "callintrinsic" => ProvenanceKind::Call,
+            _ => ProvenanceKind::Instruction,
+        };
+        self.stack.push(name);
+        let children = self.operand_nodes(instr);
+        self.stack.pop();
+        self.expanded.insert(name);
+        ProvenanceNode {
+            label: instruction_label(self.rope, instr),
+            kind,
+            span: instr.span.clone(),
+            children,
+        }
+    }
+
+    fn operand_nodes(&mut self, instr: &'a Instruction) -> Vec<ProvenanceNode> {
+        let mut children = vec![];
+        let mut rest = &instr.operands[..];
+        while let [(token, span), tail @ ..] = rest {
+            rest = tail;
+            match token {
+                Token::LocalName(name) => children.push(self.value_node(name, span)),
+                Token::GlobalName(name) => children.push(ProvenanceNode::leaf(
+                    name.clone(),
+                    ProvenanceKind::Global,
+                    span.clone(),
+                )),
+                Token::Type(type_) => {
+                    let Some(((value, value_span), tail)) = parse_value(tail) else {
+                        continue;
+                    };
+                    let value = match value {
+                        Value::Int(i) => i.to_string(),
+                        Value::Float(f) => f,
+                        Value::Bool(b) => b.to_string(),
+                        Value::Null => "null".to_string(),
+                        // Handled by the next iteration
+                        Value::Local(_) | Value::Global(_) => continue,
+                    };
+                    children.push(ProvenanceNode::leaf(
+                        format!("{} {}", type_, value),
+                        ProvenanceKind::Constant,
+                        span.start..value_span.end,
+                    ));
+                    rest = tail;
+                }
+                _ => {}
+            }
+        }
+        children
+    }
+}
+
+/// Traces back where the value `name` comes from: through `phi`s, arithmetic, address
+/// computations and calls up to function arguments, constants and memory
+pub fn provenance_tree(
+    rope: &Rope,
+    signature: &FuncSignature,
+    body: &FuncBody,
+    name: &str,
+    use_span: &Span,
+) -> ProvenanceNode {
+    let mut walker = ProvenanceWalker {
+        rope,
+        defs: value_defs(signature, body),
+        stack: vec![],
+        expanded: HashSet::new(),
+        nodes: 0,
+    };
+    // Look up the name in the definitions, such that it lives as long as the walker
+    match walker.defs.get_key_value(name) {
+        Some((&name, _)) => walker.value_node(name, use_span),
+        None => ProvenanceNode::leaf(
+            name.to_string(),
+            ProvenanceKind::Undefined,
+            use_span.clone(),
+        ),
+    }
+}
+
+#[cfg(test)]
+fn provenance_lines(src: &str, name: &str) -> Vec<String> {
+    fn describe(node: &ProvenanceNode, depth: usize, out: &mut Vec<String>) {
+        out.push(format!(
+            "{}{:?} {}",
+            "  ".repeat(depth),
+            node.kind,
+            node.label
+        ));
+        for c in &node.children {
+            describe(c, depth + 1, out);
+        }
+    }
+    let res = crate::hir_parser::parse_from_str(src);
+    let (signature, body) = crate::hir_parser::first_function(&res);
+    let rope = Rope::from_str(src);
+    let mut lines = vec![];
+    describe(
+        &provenance_tree(&rope, signature, body, name, &(0..0)),
+        0,
+        &mut lines,
+    );
+    lines
+}
+
+#[test]
+fn test_provenance_tree() {
+    let src = "
+define int64 @f(ptr %p, int64 %n) {
+body_0:
+  ptr %a = gep {int32, int64} ptr %p, int32 0, int32 1
+  int64 %base = load int64 ptr %a
+  br loop_1
+loop_1:
+  int64 %i = phi [body_0, int64 0], [loop_1, int64 %next]
+  int64 %next = add int64 %i, int64 %base
+  int1 %done = ult int64 %next, int64 %n
+  condbr int1 %done loop_1, exit_2
+exit_2:
+  ret int64 %next
+}";
+    assert_eq!(
+        provenance_lines(src, "%next"),
+        [
+            "Instruction int64 %next = add int64 %i, int64 %base",
+            "  Phi int64 %i = phi [body_0, int64 0], [loop_1, int64 %next]",
+            "    Constant int64 0",
+            "    Cycle %next",
+            "  Load int64 %base = load int64 ptr %a",
+            "    Instruction ptr %a = gep {int32, int64} ptr %p, int32 0, int32 1",
+            "      Argument ptr %p",
+            "      Constant int32 0",
+            "      Constant int32 1",
+        ]
+    );
+    let res = crate::hir_parser::parse_from_str(src);
+    let (signature, body) = crate::hir_parser::first_function(&res);
+    let rope = Rope::from_str(src);
+    let tree = provenance_tree(&rope, signature, body, "%missing", &(5..10));
+    assert_eq!((tree.kind, tree.span), (ProvenanceKind::Undefined, 5..10));
+}
+
+#[test]
+fn test_provenance_calls_and_repeated_values() {
+    let src = "
+define int64 @f(int64 %x) {
+body_0:
+  int64 %c = call @g(int64 %x, ptr @table)
+  int64 %sq = mul int64 %c, int64 %c
+  int64 %r = add int64 %sq, int64 %undefined
+  ret int64 %r
+}";
+    assert_eq!(
+        provenance_lines(src, "%r"),
+        [
+            "Instruction int64 %r = add int64 %sq, int64 %undefined",
+            "  Instruction int64 %sq = mul int64 %c, int64 %c",
+            "    Call int64 %c = call @g(int64 %x, ptr @table)",
+            "      Global @g",
+            "      Argument int64 %x",
+            "      Global @table",
+            "    Repeated %c",
+            "  Undefined %undefined",
+        ]
+    );
+    assert_eq!(provenance_lines(src, "%x"), ["Argument int64 %x"]);
+}
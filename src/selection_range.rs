@@ -0,0 +1,102 @@
+use crate::{
+    hir_cst::Cst,
+    hir_index::HIRIndex,
+    hir_operands::split_operands,
+    hir_tokenizer::{Span, Spanned, Token},
+};
+
+fn span_of_tokens(tokens: &[Spanned<Token>]) -> Option<Span> {
+    Some(tokens.first()?.1.start..tokens.last()?.1.end)
+}
+
+/// The spans for "expand selection", from the innermost to the outermost one:
+/// token, operand, instruction, basic block, function and finally the complete file.
+pub fn selection_spans(cst: &Cst, index: &HIRIndex, src_len: usize, offset: usize) -> Vec<Span> {
+    let mut candidates = vec![];
+
+    // The token. We also accept a cursor directly behind the token.
+    let token_idx = cst
+        .tokens
+        .partition_point(|t| t.token.1.end < offset)
+        .min(cst.tokens.len().saturating_sub(1));
+    if let Some(token) = cst.tokens.get(token_idx) {
+        if token.token.1.start <= offset && offset <= token.token.1.end {
+            candidates.push(token.token.1.clone());
+        }
+    }
+
+    // The function structure
+    if let Some(func) = index
+        .function_bodies
+        .iter()
+        .find(|f| f.complete_range.contains(&offset))
+    {
+        if let Some(bb) = func
+            .basic_blocks
+            .iter()
+            .find(|bb| bb.span.contains(&offset))
+        {
+            if let Some(i) = bb.instructions.iter().find(|i| i.span.contains(&offset)) {
+                let operand = split_operands(&i.operands)
+                    .into_iter()
+                    .filter_map(span_of_tokens)
+                    .find(|s| s.start <= offset && offset <= s.end);
+                candidates.extend(operand);
+                candidates.push(i.span.clone());
+            }
+            candidates.push(bb.span.clone());
+        }
+        candidates.push(func.complete_range.clone());
+    }
+
+    // Statements outside of functions
+    if let Some(node) = cst.nodes.iter().find(|n| n.span.contains(&offset)) {
+        candidates.push(node.span.clone());
+    }
+
+    candidates.push(0..src_len);
+
+    // Each range must contain the previous one
+    let mut spans: Vec<Span> = vec![];
+    for candidate in candidates {
+        match spans.last() {
+            Some(last) if candidate.start > last.start || candidate.end < last.end => {}
+            Some(last) if *last == candidate => {}
+            _ => spans.push(candidate),
+        }
+    }
+    spans
+}
+
+#[test]
+fn test_selection_spans() {
+    let src = "@g = int32 [1]
+define void @foo(int32 %a) {
+body_0:
+  int32 %v = add int32 %a, int32 42
+  ret
+}
+";
+    let res = crate::hir_parser::parse_from_str(src);
+    let cst = crate::hir_cst::build_cst(src, &res.tokens, &res.stmts);
+    let index = crate::hir_index::create_index(src, &res.tokens, &res.stmts);
+    let selections = |needle: &str| {
+        let offset = src.find(needle).unwrap();
+        selection_spans(&cst, &index, src.len(), offset)
+            .into_iter()
+            .map(|s| &src[s])
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        selections("42"),
+        [
+            "42",
+            "int32 42",
+            "int32 %v = add int32 %a, int32 42",
+            "body_0:\n  int32 %v = add int32 %a, int32 42\n  ret",
+            &src[15..src.len() - 1],
+            src,
+        ]
+    );
+    assert_eq!(selections("[1]"), ["[", "@g = int32 [1]", src]);
+}
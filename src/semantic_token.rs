@@ -1,7 +1,15 @@
 use ropey::Rope;
-use tower_lsp::lsp_types::{SemanticToken, SemanticTokenType};
+use std::collections::{HashMap, HashSet};
 
-use crate::hir_tokenizer::{Span, Token};
+use tower_lsp::lsp_types::{
+    SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokensEdit,
+};
+
+use crate::{
+    hir_index::{HIRIndex, SymbolKind, UseDefKind},
+    hir_parser::Statement,
+    hir_tokenizer::{Span, Token},
+};
 
 pub const LEGEND_TYPE: &[SemanticTokenType] = &[
     SemanticTokenType::COMMENT,
@@ -11,6 +19,17 @@ pub const LEGEND_TYPE: &[SemanticTokenType] = &[
     SemanticTokenType::NUMBER,
     SemanticTokenType::KEYWORD,
     SemanticTokenType::MODIFIER,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::PARAMETER,
+    SemanticTokenType::NAMESPACE,
+    SemanticTokenType::OPERATOR,
+    SemanticTokenType::DECORATOR,
+];
+
+pub const LEGEND_MODIFIER: &[SemanticTokenModifier] = &[
+    SemanticTokenModifier::DECLARATION,
+    SemanticTokenModifier::DEFINITION,
+    SemanticTokenModifier::READONLY,
 ];
 
 #[derive(Debug)]
@@ -18,13 +37,46 @@ pub struct HIRSemanticToken {
     pub start: usize,
     pub length: usize,
     pub token_type: usize,
+    /// Bitset over `LEGEND_MODIFIER`
+    pub token_modifiers: u32,
 }
 
 pub fn create_semantic_token(span: &Span, ttype: &SemanticTokenType) -> HIRSemanticToken {
+    create_semantic_token_with_modifiers(span, ttype, &[])
+}
+
+pub fn create_semantic_token_with_modifiers(
+    span: &Span,
+    ttype: &SemanticTokenType,
+    modifiers: &[SemanticTokenModifier],
+) -> HIRSemanticToken {
     HIRSemanticToken {
         start: span.start,
         length: span.len(),
         token_type: LEGEND_TYPE.iter().position(|item| item == ttype).unwrap(),
+        token_modifiers: modifiers
+            .iter()
+            .map(|m| 1 << LEGEND_MODIFIER.iter().position(|item| item == m).unwrap())
+            .fold(0, |a, b| a | b),
+    }
+}
+
+fn lexical_token_type(token: &Token) -> Option<SemanticTokenType> {
+    match token {
+        Token::Newline => None,
+        Token::Comment(_) => Some(SemanticTokenType::COMMENT),
+        Token::Num(_) => Some(SemanticTokenType::NUMBER),
+        Token::HexNum(_) => Some(SemanticTokenType::NUMBER),
+        Token::Str(_) => Some(SemanticTokenType::STRING),
+        Token::LocalName(_) => Some(SemanticTokenType::VARIABLE),
+        Token::GlobalName(_) => Some(SemanticTokenType::VARIABLE),
+        Token::Ident(_) => None,
+        Token::DebugRef(_) => Some(SemanticTokenType::DECORATOR),
+        Token::Type(_) => Some(SemanticTokenType::TYPE),
+        Token::Punctuation(_) => None,
+        Token::Declare => Some(SemanticTokenType::KEYWORD),
+        Token::Define => Some(SemanticTokenType::KEYWORD),
+        Token::FuncModifier(_) => Some(SemanticTokenType::MODIFIER),
     }
 }
 
@@ -32,27 +84,147 @@ pub fn create_semantic_token(span: &Span, ttype: &SemanticTokenType) -> HIRSeman
 pub fn semantic_tokens_from_tokens(tokens: &[(Token, Span)]) -> Vec<HIRSemanticToken> {
     tokens
         .iter()
-        .filter_map(|(token, span)| match token {
-            Token::Newline => None,
-            Token::Comment => Some(create_semantic_token(span, &SemanticTokenType::COMMENT)),
-            Token::Num(_) => Some(create_semantic_token(span, &SemanticTokenType::NUMBER)),
-            Token::HexNum(_) => Some(create_semantic_token(span, &SemanticTokenType::NUMBER)),
-            Token::Str(_) => Some(create_semantic_token(span, &SemanticTokenType::STRING)),
-            Token::LocalName(_) => Some(create_semantic_token(span, &SemanticTokenType::VARIABLE)),
-            Token::GlobalName(_) => Some(create_semantic_token(span, &SemanticTokenType::VARIABLE)),
-            Token::Ident(_) => None,
-            Token::DebugRef(_) => None,
-            Token::Type(_) => Some(create_semantic_token(span, &SemanticTokenType::TYPE)),
-            Token::Punctuation(_) => None,
-            Token::Declare => Some(create_semantic_token(span, &SemanticTokenType::KEYWORD)),
-            Token::Define => Some(create_semantic_token(span, &SemanticTokenType::KEYWORD)),
-            Token::FuncModifier(_) => {
-                Some(create_semantic_token(span, &SemanticTokenType::MODIFIER))
-            }
-        })
+        .filter_map(|(token, span)| Some(create_semantic_token(span, &lexical_token_type(token)?)))
         .collect::<Vec<_>>()
 }
 
+/// Creates semantic tokens from the lexer tokens, refined by the information from the index.
+/// This distinguishes functions, global variables, arguments, labels and opcodes, and marks
+/// declarations and definitions.
+pub fn semantic_tokens_from_index(
+    tokens: &[(Token, Span)],
+    stmts: &[Statement],
+    index: &HIRIndex,
+) -> Vec<HIRSemanticToken> {
+    // The classification of the tokens starting at a given offset
+    let mut classified = HashMap::<usize, HIRSemanticToken>::new();
+
+    // Function arguments, by function body
+    let mut func_body_id = 0;
+    let mut args = HashMap::<usize, HashSet<&str>>::new();
+    for s in stmts {
+        match s {
+            Statement::FuncDecl { signature, .. } => {
+                for arg in &signature.args {
+                    classified.insert(
+                        arg.name.1.start,
+                        create_semantic_token_with_modifiers(
+                            &arg.name.1,
+                            &SemanticTokenType::PARAMETER,
+                            &[SemanticTokenModifier::DECLARATION],
+                        ),
+                    );
+                }
+            }
+            Statement::FuncDef {
+                signature, body, ..
+            } => {
+                args.insert(
+                    func_body_id,
+                    signature.args.iter().map(|a| a.name.0.as_str()).collect(),
+                );
+                func_body_id += 1;
+                // Opcodes. Terminators are highlighted as keywords, since they shape the control flow
+                for i in body.basic_blocks.iter().flat_map(|bb| &bb.instructions) {
+                    let ttype = match i.opcode() {
+                        Some(spec) if spec.terminator => SemanticTokenType::KEYWORD,
+                        _ => SemanticTokenType::OPERATOR,
+                    };
+                    classified.insert(
+                        i.instruction.1.start,
+                        create_semantic_token(&i.instruction.1, &ttype),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Symbols
+    for occurrence in index.reverse_idx.values() {
+        let mut modifiers = match occurrence.use_def {
+            UseDefKind::Decl => vec![SemanticTokenModifier::DECLARATION],
+            UseDefKind::Def => vec![SemanticTokenModifier::DEFINITION],
+            UseDefKind::Use => vec![],
+        };
+        let ttype = match occurrence.symbol_kind {
+            SymbolKind::GlobalVar => {
+                modifiers.push(SemanticTokenModifier::READONLY);
+                SemanticTokenType::VARIABLE
+            }
+            SymbolKind::Function => SemanticTokenType::FUNCTION,
+            SymbolKind::DbgAnnotation => SemanticTokenType::DECORATOR,
+            SymbolKind::Label => SemanticTokenType::NAMESPACE,
+            SymbolKind::LocalVar => {
+                let is_arg = occurrence
+                    .func_body_id
+                    .and_then(|id| args.get(&id))
+                    .is_some_and(|args| args.contains(occurrence.name.as_str()));
+                if is_arg {
+                    SemanticTokenType::PARAMETER
+                } else {
+                    SemanticTokenType::VARIABLE
+                }
+            }
+        };
+        classified.insert(
+            occurrence.span.start,
+            create_semantic_token_with_modifiers(&occurrence.span, &ttype, &modifiers),
+        );
+    }
+
+    // Merge with the lexical classification. Opcodes such as `extract.d128` span multiple tokens.
+    let mut covered_until = 0;
+    let mut semantic_tokens = vec![];
+    for (token, span) in tokens {
+        if span.start < covered_until {
+            continue;
+        }
+        if let Some(semantic_token) = classified.remove(&span.start) {
+            covered_until = semantic_token.start + semantic_token.length;
+            semantic_tokens.push(semantic_token);
+        } else if let Some(ttype) = lexical_token_type(token) {
+            semantic_tokens.push(create_semantic_token(span, &ttype));
+        }
+    }
+    semantic_tokens
+}
+
+/// The semantic tokens overlapping with `span`. The tokens must be sorted.
+pub fn semantic_tokens_in_range<'a>(
+    semtoks: &'a [HIRSemanticToken],
+    span: &Span,
+) -> &'a [HIRSemanticToken] {
+    let start = semtoks.partition_point(|t| t.start + t.length <= span.start);
+    let end = semtoks.partition_point(|t| t.start < span.end);
+    &semtoks[start..end.max(start)]
+}
+
+/// Computes the edit which transforms the `old` tokens into the `new` tokens.
+/// Usually, only a small part of the document changed, so we only replace
+/// everything between the common prefix and the common suffix.
+pub fn semantic_tokens_delta(
+    old: &[SemanticToken],
+    new: &[SemanticToken],
+) -> Vec<SemanticTokensEdit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if prefix == old.len() && prefix == new.len() {
+        return vec![];
+    }
+    // Offsets are counted in integers, and each token consists of 5 integers
+    vec![SemanticTokensEdit {
+        start: (prefix * 5) as u32,
+        delete_count: ((old.len() - prefix - suffix) * 5) as u32,
+        data: Some(new[prefix..new.len() - suffix].to_vec()),
+    }]
+}
+
 // Converts our internal semantic tokens to the LSP representation of tokens
 pub fn convert_to_lsp_tokens(rope: &Rope, semtoks: &[HIRSemanticToken]) -> Vec<SemanticToken> {
     let mut pre_line = 0;
@@ -74,7 +246,7 @@ pub fn convert_to_lsp_tokens(rope: &Rope, semtoks: &[HIRSemanticToken]) -> Vec<S
                 delta_start,
                 length: token.length as u32,
                 token_type: token.token_type as u32,
-                token_modifiers_bitset: 0,
+                token_modifiers_bitset: token.token_modifiers,
             });
             pre_line = line;
             pre_start = start;
@@ -83,3 +255,127 @@ pub fn convert_to_lsp_tokens(rope: &Rope, semtoks: &[HIRSemanticToken]) -> Vec<S
         .collect::<Vec<_>>();
     lsp_tokens
 }
+
+#[test]
+fn test_semantic_tokens_from_index() {
+    let src = "declare int32 @bar(int32 %a) !0
+define int32 @foo(int32 %x) {
+body_0:
+  int32 %v = extract.d128 data128 %x, int32 0
+  int32 %r = call int32 @bar(int32 %v)
+  br body_0
+}
+@g = int32 [1]
+!0 = \"f.cpp:1\"
+";
+    let res = crate::hir_parser::parse_from_str(src);
+    let index = crate::hir_index::create_index(src, &res.tokens, &res.stmts);
+    let tokens = semantic_tokens_from_index(&res.tokens, &res.stmts, &index)
+        .into_iter()
+        .map(|t| {
+            (
+                &src[t.start..t.start + t.length],
+                LEGEND_TYPE[t.token_type].as_str(),
+                t.token_modifiers,
+            )
+        })
+        .filter(|t| !matches!(t.1, "type" | "number" | "keyword" | "string") || t.0 == "br")
+        .collect::<Vec<_>>();
+    assert_eq!(
+        tokens,
+        [
+            ("@bar", "function", 1),
+            ("%a", "parameter", 1),
+            ("!0", "decorator", 0),
+            ("@foo", "function", 2),
+            ("%x", "parameter", 2),
+            ("body_0", "namespace", 2),
+            ("%v", "variable", 2),
+            ("extract.d128", "operator", 0),
+            ("%x", "parameter", 0),
+            ("%r", "variable", 2),
+            ("call", "operator", 0),
+            ("@bar", "function", 0),
+            ("%v", "variable", 0),
+            ("br", "keyword", 0),
+            ("body_0", "namespace", 0),
+            ("@g", "variable", 6),
+            ("!0", "decorator", 2),
+        ]
+    );
+}
+
+#[cfg(test)]
+fn lsp_token(delta_line: u32, delta_start: u32, length: u32) -> SemanticToken {
+    SemanticToken {
+        delta_line,
+        delta_start,
+        length,
+        token_type: 0,
+        token_modifiers_bitset: 0,
+    }
+}
+
+#[test]
+fn test_semantic_tokens_delta() {
+    let old = [lsp_token(0, 0, 1), lsp_token(1, 0, 2), lsp_token(1, 0, 3)];
+    assert_eq!(semantic_tokens_delta(&old, &old), []);
+    // Replace the middle token
+    let new = [lsp_token(0, 0, 1), lsp_token(1, 0, 5), lsp_token(1, 0, 3)];
+    assert_eq!(
+        semantic_tokens_delta(&old, &new),
+        [SemanticTokensEdit {
+            start: 5,
+            delete_count: 5,
+            data: Some(vec![lsp_token(1, 0, 5)]),
+        }]
+    );
+    // Insert a token at the end
+    let new = [
+        lsp_token(0, 0, 1),
+        lsp_token(1, 0, 2),
+        lsp_token(1, 0, 3),
+        lsp_token(0, 4, 3),
+    ];
+    assert_eq!(
+        semantic_tokens_delta(&old, &new),
+        [SemanticTokensEdit {
+            start: 15,
+            delete_count: 0,
+            data: Some(vec![lsp_token(0, 4, 3)]),
+        }]
+    );
+    // Remove a token which is identical to its neighbor
+    let new = [lsp_token(0, 0, 1), lsp_token(1, 0, 3)];
+    assert_eq!(
+        semantic_tokens_delta(&old, &new),
+        [SemanticTokensEdit {
+            start: 5,
+            delete_count: 5,
+            data: Some(vec![]),
+        }]
+    );
+}
+
+#[test]
+fn test_semantic_tokens_in_range() {
+    let semtoks = [(0, 3), (4, 2), (10, 5)]
+        .iter()
+        .map(|(start, length)| HIRSemanticToken {
+            start: *start,
+            length: *length,
+            token_type: 0,
+            token_modifiers: 0,
+        })
+        .collect::<Vec<_>>();
+    let starts = |span: Span| {
+        semantic_tokens_in_range(&semtoks, &span)
+            .iter()
+            .map(|t| t.start)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(starts(0..20), [0, 4, 10]);
+    assert_eq!(starts(2..5), [0, 4]);
+    assert!(starts(6..10).is_empty());
+    assert_eq!(starts(12..13), [10]);
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use crate::{
+    hir_parser::{FuncArg, FuncBody, FuncSignature, Instruction},
+    hir_tokenizer::{Spanned, Token},
+};
+
+/// Where an SSA value is defined
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueDef<'a> {
+    Argument(&'a FuncArg),
+    Instruction(&'a Instruction),
+}
+
+impl<'a> ValueDef<'a> {
+    /// The type and the name of the defined value
+    pub fn declaration(&self) -> Option<(&'a Spanned<String>, &'a Spanned<String>)> {
+        match self {
+            ValueDef::Argument(arg) => Some((&arg.type_, &arg.name)),
+            ValueDef::Instruction(instr) => Some((
+                instr.assignment_type.as_ref()?,
+                instr.assignment_target.as_ref()?,
+            )),
+        }
+    }
+}
+
+/// A use of an SSA value within the operands of an instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValueUse<'a> {
+    pub instruction: &'a Instruction,
+    pub name: &'a Spanned<Token>,
+    /// The type written in front of the value, e.g. `int64` for `int64 %v15`
+    pub written_type: Option<&'a str>,
+}
+
+/// The definitions of the arguments and of all values assigned within a function
+pub fn value_defs<'a>(
+    signature: &'a FuncSignature,
+    body: &'a FuncBody,
+) -> HashMap<&'a str, ValueDef<'a>> {
+    let args = signature
+        .args
+        .iter()
+        .filter(|arg| arg.is_named())
+        .map(|arg| (arg.name.0.as_str(), ValueDef::Argument(arg)));
+    let instrs = body
+        .basic_blocks
+        .iter()
+        .flat_map(|bb| &bb.instructions)
+        .filter_map(|instr| {
+            let target = instr.assignment_target.as_ref()?;
+            Some((target.0.as_str(), ValueDef::Instruction(instr)))
+        });
+    args.chain(instrs).collect()
+}
+
+/// All uses of local values within the operands of a function body
+pub fn value_uses(body: &FuncBody) -> impl Iterator<Item = ValueUse<'_>> {
+    body.basic_blocks
+        .iter()
+        .flat_map(|bb| &bb.instructions)
+        .flat_map(|instr| {
+            instr
+                .operands
+                .iter()
+                .enumerate()
+                .filter(|(_, t)| matches!(t.0, Token::LocalName(_)))
+                .map(move |(idx, name)| ValueUse {
+                    instruction: instr,
+                    name,
+                    written_type: match idx.checked_sub(1).map(|i| &instr.operands[i].0) {
+                        Some(Token::Type(t)) => Some(t.as_str()),
+                        _ => None,
+                    },
+                })
+        })
+}
+
+impl<'a> ValueUse<'a> {
+    pub fn name(&self) -> &'a str {
+        match &self.name.0 {
+            Token::LocalName(name) => name,
+            _ => unreachable!("only local names are values"),
+        }
+    }
+}
+
+#[test]
+fn test_value_uses() {
+    let res = crate::hir_parser::parse_from_str(
+        "
+define void @f(ptr %p, int64 %n) {
+body_0:
+  int64 %v1 = load int64 ptr %p
+  int64 %v2 = phi [body_0, int64 0], [body_0, int64 %v1]
+  ret
+}",
+    );
+    let (signature, body) = crate::hir_parser::first_function(&res);
+    let defs = value_defs(signature, body);
+    assert_eq!(defs.len(), 4);
+    assert_eq!(defs["%v1"].declaration().unwrap().0 .0, "int64");
+    assert!(matches!(defs["%n"], ValueDef::Argument(_)));
+    let uses = value_uses(body)
+        .map(|u| (u.name(), u.written_type))
+        .collect::<Vec<_>>();
+    assert_eq!(uses, [("%p", Some("ptr")), ("%v1", Some("int64"))]);
+}
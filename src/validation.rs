@@ -0,0 +1,437 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    diagnostics::DiagnosticCode,
+    hir_operands::split_call,
+    hir_parser::{
+        function_signatures, BasicBlock, FuncBody, FuncSignature, Instruction, Statement,
+    },
+    hir_tokenizer::{Span, Spanned, Token},
+    hir_types::{parse_type, parse_type_from_str},
+};
+
+/// A structural problem in a function body
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationError {
+    pub code: DiagnosticCode,
+    pub span: Span,
+    pub message: String,
+    /// Other locations which help to understand the problem
+    pub related: Vec<(Span, String)>,
+}
+
+fn block_name(bb: &BasicBlock) -> String {
+    match &bb.label {
+        Some(label) => format!("Basic block `{}`", label.0),
+        None => "The entry block".to_string(),
+    }
+}
+
+fn is_terminator(i: &Instruction) -> bool {
+    i.opcode().is_some_and(|spec| spec.terminator)
+}
+
+/// Checks that each basic block ends with exactly one terminator
+pub fn validate_terminators(func_name: &str, body: &FuncBody) -> Vec<ValidationError> {
+    let mut errors = vec![];
+    for (idx, bb) in body.basic_blocks.iter().enumerate() {
+        let fallthrough = match body.basic_blocks.get(idx + 1) {
+            Some(next) => match &next.label {
+                Some(label) => format!("falls through into `{}`", label.0),
+                None => "falls through into the next block".to_string(),
+            },
+            None => format!("falls off the end of `{}`", func_name),
+        };
+
+        let Some(first_term) = bb.instructions.iter().position(is_terminator) else {
+            // Hyper puts the `alloca`s into an unlabeled block, which falls through into the
+            // first labeled block
+            if bb.label.is_none() && idx + 1 < body.basic_blocks.len() {
+                continue;
+            }
+            match bb.instructions.last() {
+                None => errors.push(ValidationError {
+                    code: DiagnosticCode::EmptyBasicBlock,
+                    span: bb.label.as_ref().map_or(bb.span.clone(), |l| l.1.clone()),
+                    message: format!("{} is empty and {}", block_name(bb), fallthrough),
+                    related: vec![],
+                }),
+                // We can't tell whether instructions we don't know are terminators
+                Some(last) if last.opcode().is_none() => {}
+                Some(last) => errors.push(ValidationError {
+                    code: DiagnosticCode::MissingTerminator,
+                    span: last.span.clone(),
+                    message: format!(
+                        "{} does not end with a terminator and {}",
+                        block_name(bb),
+                        fallthrough
+                    ),
+                    related: vec![],
+                }),
+            }
+            continue;
+        };
+
+        let first = &bb.instructions[first_term];
+        let rest = &bb.instructions[first_term + 1..];
+        if rest.iter().any(|i| !is_terminator(i)) {
+            errors.push(ValidationError {
+                code: DiagnosticCode::MisplacedTerminator,
+                span: first.instruction.1.clone(),
+                message: format!(
+                    "`{}` terminates the basic block, but further instructions follow",
+                    first.instruction.0
+                ),
+                related: vec![],
+            });
+        }
+        for duplicate in rest.iter().filter(|i| is_terminator(i)) {
+            errors.push(ValidationError {
+                code: DiagnosticCode::DuplicateTerminator,
+                span: duplicate.instruction.1.clone(),
+                message: format!(
+                    "{} already ended with `{}`",
+                    block_name(bb),
+                    first.instruction.0
+                ),
+                related: vec![(first.span.clone(), "Block terminated here".to_string())],
+            });
+        }
+    }
+    errors
+}
+
+/// Compares two types, ignoring differences in the spelling such as `int8*` vs. `ptr`
+fn same_type(a: &str, b: &str) -> bool {
+    match (parse_type_from_str(a), parse_type_from_str(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn describe_modifiers(modifiers: &[Spanned<String>]) -> String {
+    if modifiers.is_empty() {
+        return "without modifiers".to_string();
+    }
+    let modifiers = modifiers
+        .iter()
+        .map(|m| m.0.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("as `{}`", modifiers)
+}
+
+/// Checks that the definition of a function matches its declaration
+pub fn validate_declaration(decl: &FuncSignature, def: &FuncSignature) -> Vec<ValidationError> {
+    let name = &def.name.0;
+    let mut errors = vec![];
+    let mut decl_modifiers = decl.modifiers.iter().map(|m| &m.0).collect::<Vec<_>>();
+    let mut def_modifiers = def.modifiers.iter().map(|m| &m.0).collect::<Vec<_>>();
+    decl_modifiers.sort();
+    def_modifiers.sort();
+    if decl_modifiers != def_modifiers {
+        errors.push(ValidationError {
+            code: DiagnosticCode::SignatureMismatch,
+            span: def.name.1.clone(),
+            message: format!(
+                "`{}` is declared {}, but defined {}",
+                name,
+                describe_modifiers(&decl.modifiers),
+                describe_modifiers(&def.modifiers)
+            ),
+            related: vec![(decl.name.1.clone(), "Declared here".to_string())],
+        });
+    }
+    if !same_type(&decl.ret_type.0, &def.ret_type.0) {
+        errors.push(ValidationError {
+            code: DiagnosticCode::SignatureMismatch,
+            span: def.ret_type.1.clone(),
+            message: format!(
+                "`{}` is declared to return `{}`, but defined to return `{}`",
+                name, decl.ret_type.0, def.ret_type.0
+            ),
+            related: vec![(decl.ret_type.1.clone(), "Declared here".to_string())],
+        });
+    }
+    if decl.args.len() != def.args.len() {
+        errors.push(ValidationError {
+            code: DiagnosticCode::SignatureMismatch,
+            span: def.name.1.clone(),
+            message: format!(
+                "`{}` is declared with {}, but defined with {}",
+                name,
+                count_arguments(decl.args.len()),
+                def.args.len()
+            ),
+            related: vec![(decl.name.1.clone(), "Declared here".to_string())],
+        });
+    }
+    for (idx, (decl_arg, def_arg)) in decl.args.iter().zip(&def.args).enumerate() {
+        if !same_type(&decl_arg.type_.0, &def_arg.type_.0) {
+            errors.push(ValidationError {
+                code: DiagnosticCode::SignatureMismatch,
+                span: def_arg.type_.1.clone(),
+                message: format!(
+                    "Argument {} of `{}` is declared as `{}`, but defined as `{}`",
+                    idx + 1,
+                    name,
+                    decl_arg.type_.0,
+                    def_arg.type_.0
+                ),
+                related: vec![(decl_arg.type_.1.clone(), "Declared here".to_string())],
+            });
+        }
+    }
+    errors
+}
+
+fn count_arguments(count: usize) -> String {
+    match count {
+        1 => "1 argument".to_string(),
+        _ => format!("{} arguments", count),
+    }
+}
+
+fn tokens_span(tokens: &[Spanned<Token>]) -> Option<Span> {
+    Some(tokens.first()?.1.start..tokens.last()?.1.end)
+}
+
+/// Checks the arguments passed by a `call` against the callee's signature
+pub fn validate_call(
+    instr: &Instruction,
+    signatures: &HashMap<&str, &FuncSignature>,
+) -> Vec<ValidationError> {
+    let Some((callee, args)) = split_call(&instr.operands) else {
+        return vec![];
+    };
+    let [(Token::GlobalName(callee), _)] = callee else {
+        return vec![];
+    };
+    let Some(signature) = signatures.get(callee.as_str()) else {
+        return vec![];
+    };
+    let mut errors = vec![];
+    if args.len() != signature.args.len() {
+        errors.push(ValidationError {
+            code: DiagnosticCode::CallArgumentMismatch,
+            span: tokens_span(&instr.operands).unwrap_or(instr.instruction.1.clone()),
+            message: format!(
+                "`{}` expects {}, but {} passed",
+                callee,
+                count_arguments(signature.args.len()),
+                match args.len() {
+                    1 => "1 was".to_string(),
+                    n => format!("{} were", n),
+                }
+            ),
+            related: vec![(
+                signature.name.1.clone(),
+                "Signature of the callee".to_string(),
+            )],
+        });
+    }
+    for (idx, (arg, param)) in args.iter().zip(&signature.args).enumerate() {
+        let (Some((arg_type, _)), Some(param_type)) =
+            (parse_type(arg), parse_type_from_str(&param.type_.0))
+        else {
+            continue;
+        };
+        if arg_type != param_type {
+            errors.push(ValidationError {
+                code: DiagnosticCode::CallArgumentMismatch,
+                span: tokens_span(arg).unwrap_or(instr.instruction.1.clone()),
+                message: format!(
+                    "Argument {} of `{}` has type `{}`, but `{}` was passed",
+                    idx + 1,
+                    callee,
+                    param_type,
+                    arg_type
+                ),
+                related: vec![(param.type_.1.clone(), "Parameter declared here".to_string())],
+            });
+        }
+    }
+    errors
+}
+
+/// Checks that declarations match the definitions and that calls match the signatures
+pub fn validate_signatures(stmts: &[Statement]) -> Vec<ValidationError> {
+    let signatures = function_signatures(stmts);
+    let definitions = stmts
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::FuncDef { signature, .. } => Some(signature.name.0.as_str()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    let mut errors = vec![];
+    for stmt in stmts {
+        match stmt {
+            // Without a definition, the signature is just the first declaration,
+            // which is not what the messages of `validate_declaration` claim
+            Statement::FuncDecl { signature, .. }
+                if definitions.contains(signature.name.0.as_str()) =>
+            {
+                let def = signatures[signature.name.0.as_str()];
+                errors.extend(validate_declaration(signature, def));
+            }
+            Statement::FuncDef { body, .. } => {
+                for instr in body.basic_blocks.iter().flat_map(|bb| &bb.instructions) {
+                    if instr.instruction.0 == "call" {
+                        errors.extend(validate_call(instr, &signatures));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    errors
+}
+
+pub fn validate_statements(stmts: &[Statement]) -> Vec<ValidationError> {
+    stmts
+        .iter()
+        .flat_map(|s| match s {
+            Statement::FuncDef {
+                signature, body, ..
+            } => validate_terminators(&signature.name.0, body),
+            _ => vec![],
+        })
+        .chain(validate_signatures(stmts))
+        .collect()
+}
+
+#[cfg(test)]
+fn validation_messages(src: &str) -> Vec<(String, String)> {
+    let res = crate::hir_parser::parse_from_str(src);
+    assert_eq!(res.errors, []);
+    validate_statements(&res.stmts)
+        .into_iter()
+        .map(|e| (src[e.span].to_string(), e.message))
+        .collect()
+}
+
+#[test]
+fn test_validate_terminators() {
+    let src = "
+define void @foo(int1 %c) {
+  ptr %a = alloca int32 align 4
+body_0:
+  br int1 %c, then_1, else_2
+then_1:
+  int1 %v = bitor int1 %c, int1 %c
+else_2:
+  ret
+  br body_0
+empty_3:
+loop_4:
+  br loop_4
+  int1 %w = bitor int1 %c, int1 %c
+last_5:
+  int1 %x = bitor int1 %c, int1 %c
+}";
+    assert_eq!(
+        validation_messages(src),
+        [
+            (
+                "int1 %v = bitor int1 %c, int1 %c".to_string(),
+                "Basic block `then_1` does not end with a terminator and falls through into `else_2`"
+                    .to_string()
+            ),
+            (
+                "br".to_string(),
+                "Basic block `else_2` already ended with `ret`".to_string()
+            ),
+            (
+                "empty_3".to_string(),
+                "Basic block `empty_3` is empty and falls through into `loop_4`".to_string()
+            ),
+            (
+                "br".to_string(),
+                "`br` terminates the basic block, but further instructions follow".to_string()
+            ),
+            (
+                "int1 %x = bitor int1 %c, int1 %c".to_string(),
+                "Basic block `last_5` does not end with a terminator and falls off the end of `@foo`"
+                    .to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_validate_signatures() {
+    let src = "
+declare exported int32 @f(ptr %a, int64 %b)
+declare void @g(ptr %)
+define void @f(int32 %a) {
+body_0:
+  call @g(ptr %a, int32 1)
+  call @g(int32 %a)
+  call @h(int32 %a)
+  ret
+}";
+    assert_eq!(
+        validation_messages(src),
+        [
+            (
+                "@f".to_string(),
+                "`@f` is declared as `exported`, but defined without modifiers".to_string()
+            ),
+            (
+                "void".to_string(),
+                "`@f` is declared to return `int32`, but defined to return `void`".to_string()
+            ),
+            (
+                "@f".to_string(),
+                "`@f` is declared with 2 arguments, but defined with 1".to_string()
+            ),
+            (
+                "int32".to_string(),
+                "Argument 1 of `@f` is declared as `ptr`, but defined as `int32`".to_string()
+            ),
+            (
+                "@g(ptr %a, int32 1)".to_string(),
+                "`@g` expects 1 argument, but 2 were passed".to_string()
+            ),
+            (
+                "int32 %a".to_string(),
+                "Argument 1 of `@g` has type `ptr`, but `int32` was passed".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_validate_repeated_declarations() {
+    // Declarations are only checked against the definition, not against each other
+    let src = "
+declare void @f(ptr %a)
+declare int32 @f(int32 %a)
+declare void @g()
+define int32 @g() {
+body_0:
+  ret
+}";
+    assert_eq!(
+        validation_messages(src),
+        [(
+            "int32".to_string(),
+            "`@g` is declared to return `void`, but defined to return `int32`".to_string()
+        )]
+    );
+}
+
+#[test]
+fn examples_are_valid() {
+    for path in [
+        "examples/fcf.hir",
+        "examples/insert.hir",
+        "examples/query.hir",
+        "examples/relation.hir",
+    ] {
+        let src = std::fs::read_to_string(path).unwrap();
+        let res = crate::hir_parser::parse_from_str(&src);
+        assert_eq!(validate_statements(&res.stmts), [], "{}", path);
+    }
+}
@@ -37,7 +37,30 @@ export async function activate (context: ExtensionContext) {
   // Options to control the language client
   const clientOptions: LanguageClientOptions = {
     // Register the server for Hyper IR documents
-    documentSelector: [{ language: 'hir' }]
+    documentSelector: [{ language: 'hir' }],
+    initializationOptions: workspace.getConfiguration('hyperIr'),
+    synchronize: {
+      // Notify the server about configuration changes
+      configurationSection: 'hyperIr'
+    },
+    middleware: {
+      // The arguments of `editor.action.showReferences` must be VS Code objects, not JSON
+      async provideCodeLenses (document, token, next) {
+        const lenses = await next(document, token);
+        for (const lens of lenses ?? []) {
+          if (lens.command?.command !== 'editor.action.showReferences' || client === null) {
+            continue;
+          }
+          const [uri, position, locations] = lens.command.arguments ?? [];
+          lens.command.arguments = [
+            client.protocol2CodeConverter.asUri(uri),
+            client.protocol2CodeConverter.asPosition(position),
+            await client.protocol2CodeConverter.asLocations(locations)
+          ];
+        }
+        return lenses;
+      }
+    }
   };
 
   // Create the language client and start the client.